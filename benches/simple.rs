@@ -33,5 +33,52 @@ fn big_limit_ladder(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, small_limit_ladder, big_limit_ladder);
+fn quote_churn(c: &mut Criterion) {
+    c.bench_function("quote churn", |b| {
+        let mut ob = OrderBook::default();
+        // A deep queue at the best level and 100 levels behind it, on each
+        // side of a 10.0 wide spread
+        for i in 0..1_100 {
+            let offset = match i {
+                0..=999 => 0.0,
+                _ => ((i - 999) as f64) / 10.0,
+            };
+            ob.execute(OrderType::Limit {
+                id: i as u128,
+                price: 12340.0 - offset,
+                qty: 1.0,
+                side: Side::Bid,
+            });
+            ob.execute(OrderType::Limit {
+                id: 10_000 + i as u128,
+                price: 12350.0 + offset,
+                qty: 1.0,
+                side: Side::Ask,
+            });
+        }
+        b.iter(|| {
+            for i in 0..100_000 {
+                let id = 20_000 + (i % 4) as u128;
+                // Alternate between joining the best level of each side and
+                // improving it at a price moving inside the spread
+                let step = ((1 + (i / 4) % 49) as f64) / 10.0;
+                let (side, price) = match i % 4 {
+                    0 => (Side::Bid, 12340.0),
+                    1 => (Side::Ask, 12350.0),
+                    2 => (Side::Bid, 12340.0 + step),
+                    _ => (Side::Ask, 12350.0 - step),
+                };
+                ob.execute(OrderType::Limit {
+                    id,
+                    price,
+                    qty: 1.0,
+                    side,
+                });
+                ob.execute(OrderType::Cancel { id });
+            }
+        });
+    });
+}
+
+criterion_group!(benches, small_limit_ladder, big_limit_ladder, quote_churn);
 criterion_main!(benches);
@@ -1,13 +1,14 @@
 use std::collections::HashMap;
 use std::ops::{Index, IndexMut};
 
-use crate::models::LimitOrder;
+use crate::models::{LimitOrder, Side};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OrderArena {
     orders: Vec<LimitOrder>,
     free: Vec<usize>,
     order_map: HashMap<u128, usize>,
+    last_ts: u64,
 }
 
 impl OrderArena {
@@ -16,24 +17,58 @@ impl OrderArena {
             orders: Vec::with_capacity(capacity),
             free: Vec::with_capacity(capacity),
             order_map: HashMap::with_capacity(capacity),
+            last_ts: 0,
         };
 
         // Preallocate
         for i in 0..capacity {
             list.orders.push(LimitOrder {
                 id: 0,
+                side: Side::Bid,
                 price: 0.0,
                 qty: 0.0,
+                reserve_qty: 0.0,
+                peak_qty: 0.0,
+                all_or_none: false,
+                expires_after: None,
+                placed_at: 0,
+                ts: 0,
+                account: None,
             });
             list.free.push(i);
         }
         list
     }
 
+    /// Delete all the orders and reset the time priority sequence, keeping
+    /// every allocated slot for reuse.
+    pub fn clear(&mut self) {
+        for order in self.orders.iter_mut() {
+            order.qty = 0.0;
+        }
+        self.free.clear();
+        self.free.extend(0..self.orders.len());
+        self.order_map.clear();
+        self.last_ts = 0;
+    }
+
     pub fn get(&self, id: u128) -> Option<(f64, usize)> {
         self.order_map.get(&id).map(|i| (self.orders[*i].price, *i))
     }
 
+    /// Return `true` if an order with the given ID is live in the arena, i.e.
+    /// it has not been deleted and still has quantity left.
+    pub fn contains(&self, id: u128) -> bool {
+        matches!(self.get(id), Some((_, idx)) if self.orders[idx].qty > 0.0)
+    }
+
+    /// Return the order in the slot at the given index, or `None` if the index
+    /// is out of bounds.
+    #[cfg(any(test, feature = "debug"))]
+    pub fn slot(&self, idx: usize) -> Option<&LimitOrder> {
+        self.orders.get(idx)
+    }
+
     #[cfg(test)]
     pub fn get_full(&self, id: u128) -> Option<(f64, f64, usize)> {
         self.order_map
@@ -41,10 +76,46 @@ impl OrderArena {
             .map(|i| (self.orders[*i].price, self.orders[*i].qty, *i))
     }
 
-    pub fn insert(&mut self, id: u128, price: f64, qty: f64) -> usize {
+    /// Return the next time priority sequence number.
+    pub fn next_ts(&mut self) -> u64 {
+        self.last_ts += 1;
+        self.last_ts
+    }
+
+    /// Return the last time priority sequence number.
+    pub fn last_ts(&self) -> u64 {
+        self.last_ts
+    }
+
+    /// Set the last time priority sequence number, e.g. to carry it over
+    /// from another arena.
+    pub fn set_last_ts(&mut self, ts: u64) {
+        self.last_ts = ts;
+    }
+
+    pub fn insert(
+        &mut self,
+        id: u128,
+        side: Side,
+        price: f64,
+        qty: f64,
+    ) -> usize {
+        let ts = self.next_ts();
         match self.free.pop() {
             None => {
-                self.orders.push(LimitOrder { id, price, qty });
+                self.orders.push(LimitOrder {
+                    id,
+                    side,
+                    price,
+                    qty,
+                    reserve_qty: 0.0,
+                    peak_qty: 0.0,
+                    all_or_none: false,
+                    expires_after: None,
+                    placed_at: 0,
+                    ts,
+                    account: None,
+                });
                 let index = self.orders.len() - 1;
                 self.order_map.insert(id, index);
                 index
@@ -52,8 +123,16 @@ impl OrderArena {
             Some(index) => {
                 let ord = &mut self.orders[index];
                 ord.id = id;
+                ord.side = side;
                 ord.qty = qty;
                 ord.price = price;
+                ord.reserve_qty = 0.0;
+                ord.peak_qty = 0.0;
+                ord.all_or_none = false;
+                ord.expires_after = None;
+                ord.placed_at = 0;
+                ord.ts = ts;
+                ord.account = None;
                 self.order_map.insert(id, index);
                 index
             }
@@ -91,6 +170,7 @@ impl IndexMut<usize> for OrderArena {
 #[cfg(test)]
 mod test {
     use super::OrderArena;
+    use crate::models::Side;
 
     #[test]
     fn growing_arena() {
@@ -105,7 +185,12 @@ mod test {
         for capacity in 0_u64..30 {
             let mut arena = OrderArena::new(capacity as usize);
             for i in 0_u64..capacity {
-                arena.insert(i as u128, (i * 100 + i) as f64, (2 * i) as f64);
+                arena.insert(
+                    i as u128,
+                    Side::Bid,
+                    (i * 100 + i) as f64,
+                    (2 * i) as f64,
+                );
             }
             for i in 0_u64..capacity {
                 assert_eq!(
@@ -117,7 +202,12 @@ mod test {
                 assert_eq!(arena.get_full(i as u128), None);
             }
             for i in capacity..2 * capacity {
-                arena.insert(i as u128, (i * 100 + i) as f64, (2 * i) as f64);
+                arena.insert(
+                    i as u128,
+                    Side::Bid,
+                    (i * 100 + i) as f64,
+                    (2 * i) as f64,
+                );
             }
             for i in 0..capacity {
                 assert_eq!(
@@ -133,4 +223,26 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn clear_keeps_slots() {
+        let mut arena = OrderArena::new(2);
+        for i in 0..3 {
+            arena.insert(i, Side::Bid, 100.0, 1.0);
+        }
+        let slots = arena.orders.as_ptr();
+        arena.clear();
+        assert_eq!(arena.orders.as_ptr(), slots);
+        assert_eq!(arena.free.len(), 3);
+        assert_eq!(arena.last_ts(), 0);
+        for i in 0..3 {
+            assert!(!arena.contains(i));
+        }
+
+        // The slots are reused, last one first like in a new arena
+        assert_eq!(arena.insert(3, Side::Ask, 101.0, 2.0), 2);
+        assert_eq!(arena.get_full(3), Some((101.0, 2.0, 2)));
+        assert_eq!(arena.last_ts(), 1);
+        assert_eq!(arena.orders.len(), 3);
+    }
 }
@@ -0,0 +1,428 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::models::{FillMetadata, OrderEvent, RejectReason, Side};
+
+/// Version of the binary layout written by [`OrderEvent::encode`]. Bump it
+/// whenever the layout changes.
+///
+/// [`OrderEvent::encode`]: enum.OrderEvent.html#method.encode
+pub const ENCODING_VERSION: u8 = 5;
+
+const TAG_UNFILLED: u8 = 0;
+const TAG_PLACED: u8 = 1;
+const TAG_CANCELED: u8 = 2;
+const TAG_PARTIALLY_FILLED: u8 = 3;
+const TAG_FILLED: u8 = 4;
+const TAG_AMENDED: u8 = 5;
+const TAG_REJECTED: u8 = 6;
+const TAG_EXPIRED: u8 = 7;
+const TAG_REPLACED: u8 = 8;
+
+// version + tag + id
+const HEADER_SIZE: usize = 1 + 1 + 16;
+// order_1 + order_2 + qty + price + taker_side + total_fill + maker_ts +
+// price_improvement
+const FILL_SIZE: usize = 16 + 16 + 8 + 8 + 1 + 1 + 8 + 8;
+
+/// An error returned when decoding a binary encoded [`OrderEvent`] fails.
+///
+/// [`OrderEvent`]: enum.OrderEvent.html
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The buffer ended before the event was completely decoded.
+    UnexpectedEnd,
+    /// The buffer was encoded with an unsupported layout version.
+    UnsupportedVersion(u8),
+    /// The buffer contains a byte that is not valid at its position.
+    InvalidByte(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of buffer"),
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported encoding version {}", v)
+            }
+            DecodeError::InvalidByte(b) => write!(f, "invalid byte {:#04x}", b),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl OrderEvent {
+    /// Encode the event in a compact binary layout, meant for consumers that
+    /// need to marshal the event stream cheaply (e.g. across processes).
+    ///
+    /// All the integers and floats are little-endian. The layout is a version
+    /// byte ([`ENCODING_VERSION`]), a variant tag byte and the 16-byte order
+    /// ID. Events with fills are followed by the filled quantity, a `u32` fill
+    /// count and the fills, each one taking 66 bytes. Canceled events are
+    /// followed by the canceled quantity and a found byte, amended events by
+    /// the new quantity and rejected events by a reason byte.
+    ///
+    /// [`ENCODING_VERSION`]: constant.ENCODING_VERSION.html
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, id, filled) = match self {
+            OrderEvent::Unfilled { id } => (TAG_UNFILLED, *id, None),
+            OrderEvent::Placed { id } => (TAG_PLACED, *id, None),
+            OrderEvent::Expired { id } => (TAG_EXPIRED, *id, None),
+            OrderEvent::Replaced { id } => (TAG_REPLACED, *id, None),
+            OrderEvent::Canceled {
+                id,
+                canceled_qty,
+                found,
+            } => {
+                let mut buf = Self::header(TAG_CANCELED, *id, 8 + 1);
+                buf.extend_from_slice(&canceled_qty.to_le_bytes());
+                buf.push(*found as u8);
+                return buf;
+            }
+            OrderEvent::Amended { id, new_qty } => {
+                let mut buf = Self::header(TAG_AMENDED, *id, 8);
+                buf.extend_from_slice(&new_qty.to_le_bytes());
+                return buf;
+            }
+            OrderEvent::Rejected { id, reason } => {
+                let mut buf = Self::header(TAG_REJECTED, *id, 1);
+                buf.push(reason_to_byte(*reason));
+                return buf;
+            }
+            OrderEvent::PartiallyFilled {
+                id,
+                filled_qty,
+                fills,
+            } => (TAG_PARTIALLY_FILLED, *id, Some((*filled_qty, fills))),
+            OrderEvent::Filled {
+                id,
+                filled_qty,
+                fills,
+            } => (TAG_FILLED, *id, Some((*filled_qty, fills))),
+        };
+
+        let fill_bytes = filled.map_or(0, |(_, fills)| {
+            8 + 4 + fills.len() * FILL_SIZE
+        });
+        let mut buf = Self::header(tag, id, fill_bytes);
+        if let Some((filled_qty, fills)) = filled {
+            buf.extend_from_slice(&filled_qty.to_le_bytes());
+            buf.extend_from_slice(&(fills.len() as u32).to_le_bytes());
+            for fill in fills {
+                buf.extend_from_slice(&fill.order_1.to_le_bytes());
+                buf.extend_from_slice(&fill.order_2.to_le_bytes());
+                buf.extend_from_slice(&fill.qty.to_le_bytes());
+                buf.extend_from_slice(&fill.price.to_le_bytes());
+                buf.push(match fill.taker_side {
+                    Side::Bid => 0,
+                    Side::Ask => 1,
+                });
+                buf.push(fill.total_fill as u8);
+                buf.extend_from_slice(&fill.maker_ts.to_le_bytes());
+                buf.extend_from_slice(&fill.price_improvement.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    fn header(tag: u8, id: u128, payload_size: usize) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + payload_size);
+        buf.push(ENCODING_VERSION);
+        buf.push(tag);
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf
+    }
+
+    /// Decode an event previously encoded with [`encode`]. Trailing bytes
+    /// after the event are ignored.
+    ///
+    /// [`encode`]: #method.encode
+    pub fn decode(bytes: &[u8]) -> Result<OrderEvent, DecodeError> {
+        let mut reader = Reader { bytes };
+        let version = reader.u8()?;
+        if version != ENCODING_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let tag = reader.u8()?;
+        let id = reader.u128()?;
+
+        match tag {
+            TAG_UNFILLED => Ok(OrderEvent::Unfilled { id }),
+            TAG_PLACED => Ok(OrderEvent::Placed { id }),
+            TAG_CANCELED => Ok(OrderEvent::Canceled {
+                id,
+                canceled_qty: reader.f64()?,
+                found: reader.bool()?,
+            }),
+            TAG_EXPIRED => Ok(OrderEvent::Expired { id }),
+            TAG_REPLACED => Ok(OrderEvent::Replaced { id }),
+            TAG_AMENDED => Ok(OrderEvent::Amended {
+                id,
+                new_qty: reader.f64()?,
+            }),
+            TAG_REJECTED => Ok(OrderEvent::Rejected {
+                id,
+                reason: reader.reason()?,
+            }),
+            TAG_PARTIALLY_FILLED | TAG_FILLED => {
+                let filled_qty = reader.f64()?;
+                let count = reader.u32()? as usize;
+                if reader.bytes.len() < count * FILL_SIZE {
+                    return Err(DecodeError::UnexpectedEnd);
+                }
+                let mut fills = Vec::with_capacity(count);
+                for _ in 0..count {
+                    fills.push(reader.fill()?);
+                }
+                if tag == TAG_FILLED {
+                    Ok(OrderEvent::Filled {
+                        id,
+                        filled_qty,
+                        fills,
+                    })
+                } else {
+                    Ok(OrderEvent::PartiallyFilled {
+                        id,
+                        filled_qty,
+                        fills,
+                    })
+                }
+            }
+            _ => Err(DecodeError::InvalidByte(tag)),
+        }
+    }
+}
+
+fn reason_to_byte(reason: RejectReason) -> u8 {
+    match reason {
+        RejectReason::UnknownOrder => 0,
+        RejectReason::InvalidQty => 1,
+        RejectReason::InvalidPrice => 2,
+        RejectReason::NoReferencePrice => 3,
+        RejectReason::WouldCross => 4,
+        RejectReason::WouldNotImprove => 5,
+        RejectReason::Frozen => 6,
+        RejectReason::DuplicateId => 7,
+        RejectReason::MinRestingTime => 8,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn u128(&mut self) -> Result<u128, DecodeError> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(DecodeError::InvalidByte(b)),
+        }
+    }
+
+    fn reason(&mut self) -> Result<RejectReason, DecodeError> {
+        match self.u8()? {
+            0 => Ok(RejectReason::UnknownOrder),
+            1 => Ok(RejectReason::InvalidQty),
+            2 => Ok(RejectReason::InvalidPrice),
+            3 => Ok(RejectReason::NoReferencePrice),
+            4 => Ok(RejectReason::WouldCross),
+            5 => Ok(RejectReason::WouldNotImprove),
+            6 => Ok(RejectReason::Frozen),
+            7 => Ok(RejectReason::DuplicateId),
+            8 => Ok(RejectReason::MinRestingTime),
+            b => Err(DecodeError::InvalidByte(b)),
+        }
+    }
+
+    fn fill(&mut self) -> Result<FillMetadata, DecodeError> {
+        let order_1 = self.u128()?;
+        let order_2 = self.u128()?;
+        let qty = self.f64()?;
+        let price = self.f64()?;
+        let taker_side = match self.u8()? {
+            0 => Side::Bid,
+            1 => Side::Ask,
+            b => return Err(DecodeError::InvalidByte(b)),
+        };
+        let total_fill = self.bool()?;
+        let maker_ts = self.u64()?;
+        let price_improvement = self.f64()?;
+        Ok(FillMetadata {
+            order_1,
+            order_2,
+            qty,
+            price,
+            taker_side,
+            total_fill,
+            maker_ts,
+            price_improvement,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DecodeError, ENCODING_VERSION};
+    use crate::{FillMetadata, OrderEvent, RejectReason, Side};
+
+    fn fills() -> Vec<FillMetadata> {
+        vec![
+            FillMetadata {
+                order_1: 7,
+                order_2: 3,
+                qty: 2.5,
+                price: 398.421,
+                taker_side: Side::Ask,
+                total_fill: true,
+                maker_ts: 0,
+                price_improvement: 0.421,
+            },
+            FillMetadata {
+                order_1: 7,
+                order_2: u128::MAX,
+                qty: 0.125,
+                price: 395.0,
+                taker_side: Side::Ask,
+                total_fill: false,
+                maker_ts: 0,
+                price_improvement: 0.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let events = vec![
+            OrderEvent::Unfilled { id: 0 },
+            OrderEvent::Placed { id: 1 },
+            OrderEvent::Canceled {
+                id: u128::MAX,
+                canceled_qty: 2.5,
+                found: true,
+            },
+            OrderEvent::Canceled {
+                id: 9,
+                canceled_qty: 0.0,
+                found: false,
+            },
+            OrderEvent::Expired { id: 2 },
+            OrderEvent::Replaced { id: 6 },
+            OrderEvent::Amended {
+                id: 3,
+                new_qty: 1.5,
+            },
+            OrderEvent::Rejected {
+                id: 4,
+                reason: RejectReason::UnknownOrder,
+            },
+            OrderEvent::Rejected {
+                id: 5,
+                reason: RejectReason::InvalidPrice,
+            },
+            OrderEvent::PartiallyFilled {
+                id: 7,
+                filled_qty: 2.625,
+                fills: fills(),
+            },
+            OrderEvent::Filled {
+                id: 7,
+                filled_qty: 2.625,
+                fills: fills(),
+            },
+            OrderEvent::Filled {
+                id: 8,
+                filled_qty: 2.5,
+                fills: fills()[..1].to_vec(),
+            },
+        ];
+        for event in events {
+            assert_eq!(OrderEvent::decode(&event.encode()), Ok(event));
+        }
+    }
+
+    #[test]
+    fn encoded_size() {
+        assert_eq!(OrderEvent::Placed { id: 1 }.encode().len(), 18);
+        let event = OrderEvent::Amended {
+            id: 3,
+            new_qty: 1.5,
+        };
+        assert_eq!(event.encode().len(), 18 + 8);
+        let event = OrderEvent::Canceled {
+            id: 2,
+            canceled_qty: 1.5,
+            found: true,
+        };
+        assert_eq!(event.encode().len(), 18 + 8 + 1);
+        let event = OrderEvent::Rejected {
+            id: 4,
+            reason: RejectReason::UnknownOrder,
+        };
+        assert_eq!(event.encode().len(), 18 + 1);
+        let event = OrderEvent::Filled {
+            id: 7,
+            filled_qty: 2.625,
+            fills: fills(),
+        };
+        assert_eq!(event.encode().len(), 18 + 12 + 2 * 66);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut bytes = OrderEvent::Filled {
+            id: 7,
+            filled_qty: 2.625,
+            fills: fills(),
+        }
+        .encode();
+        assert_eq!(
+            OrderEvent::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(OrderEvent::decode(&[]), Err(DecodeError::UnexpectedEnd));
+
+        bytes[0] = ENCODING_VERSION + 1;
+        assert_eq!(
+            OrderEvent::decode(&bytes),
+            Err(DecodeError::UnsupportedVersion(ENCODING_VERSION + 1))
+        );
+
+        bytes[0] = ENCODING_VERSION;
+        bytes[1] = 42;
+        assert_eq!(
+            OrderEvent::decode(&bytes),
+            Err(DecodeError::InvalidByte(42))
+        );
+    }
+}
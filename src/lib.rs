@@ -25,6 +25,8 @@
 //!                 price: 120.0,
 //!                 taker_side: Side::Bid,
 //!                 total_fill: true,
+//!                 maker_ts: 1,
+//!                 price_improvement: 0.0,
 //!             }
 //!         ],
 //!     },
@@ -44,14 +46,21 @@
 use pyo3::prelude::*;
 
 mod arena;
+mod codec;
 mod models;
 mod orderbook;
 mod python;
 
+pub use codec::{DecodeError, ENCODING_VERSION};
 pub use models::{
-    BookDepth, BookLevel, FillMetadata, OrderEvent, OrderType, Side, Trade,
+    AuctionResult, Bbo, BookDepth, BookLevel, BookSnapshot, CancelReport,
+    ExecutionReport, FillMetadata, LimitOrder, MatchingMode, OrderEvent,
+    OrderStatus, OrderType, RejectReason, RestingOrder, SessionSummary, Side,
+    Signals, StpMode, Trade, SYNTHETIC_COUNTERPARTY,
 };
-pub use orderbook::OrderBook;
+#[cfg(any(test, feature = "debug"))]
+pub use models::TraceStep;
+pub use orderbook::{CumulativeDepth, LevelCallback, OrderBook};
 
 #[pymodule]
 fn lobster(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
@@ -60,9 +69,12 @@ fn lobster(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<python::Order>()?;
     m.add_class::<python::OrderEventType>()?;
     m.add_class::<python::OrderEvent>()?;
+    m.add_class::<python::CancelReport>()?;
+    m.add_class::<models::Bbo>()?;
     m.add_class::<models::BookDepth>()?;
     m.add_class::<models::BookLevel>()?;
     m.add_class::<models::FillMetadata>()?;
+    m.add_class::<models::RejectReason>()?;
     m.add_class::<models::Side>()?;
     m.add_class::<models::Trade>()?;
 
@@ -1,7 +1,10 @@
 use pyo3::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// An order book side.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[pyclass]
 pub enum Side {
     /// The bid (or buy) side.
@@ -21,13 +24,80 @@ impl std::ops::Not for Side {
     }
 }
 
+/// The maker ID reported in the fills of [`OrderType::Internalized`] orders
+/// against the synthetic counterparty. This ID is reserved and should not be
+/// used for orders.
+///
+/// [`OrderType::Internalized`]: enum.OrderType.html#variant.Internalized
+pub const SYNTHETIC_COUNTERPARTY: u128 = u128::MAX;
+
 /// An order to be executed by the order book.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
 pub enum OrderType {
     /// A market order, which is either filled immediately (even partially), or
     /// canceled.
     Market {
         /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+    },
+    /// A market-if-touched order, which waits off the order book until a
+    /// trade touches its trigger price and then is executed as a market order.
+    /// A buy order is triggered by trades at or below the trigger price, and a
+    /// sell order by trades at or above it, so that positions are entered at
+    /// favorable prices. The events of the triggered market order are queued,
+    /// and can be collected with [`OrderBook::drain_events`].
+    ///
+    /// [`OrderBook::drain_events`]: struct.OrderBook.html#method.drain_events
+    MarketIfTouched {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book once triggered.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The price that triggers the order when touched by a trade.
+        trigger_price: f64,
+    },
+    /// A stop-market order, which waits off the order book until a trade
+    /// touches its stop price and then is executed as a market order. A buy
+    /// order is triggered by trades at or above the stop price, and a sell
+    /// order by trades at or below it, so that losses are limited. The events
+    /// of the triggered market order are queued, and can be collected with
+    /// [`OrderBook::drain_events`].
+    ///
+    /// [`OrderBook::drain_events`]: struct.OrderBook.html#method.drain_events
+    StopMarket {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book once triggered.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The price that triggers the order when touched by a trade.
+        stop_price: f64,
+    },
+    /// An internalized order, modeling broker internalization: it is first
+    /// matched against the order book like a market order, then any remaining
+    /// quantity is filled at the mid price of the order book at submission
+    /// time, against a synthetic counterparty. Synthetic fills have
+    /// [`SYNTHETIC_COUNTERPARTY`] as maker ID.
+    ///
+    /// [`SYNTHETIC_COUNTERPARTY`]: constant.SYNTHETIC_COUNTERPARTY.html
+    Internalized {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
         /// The order side. It will be matched against the resting orders on the
         /// other side of the order book.
@@ -39,6 +109,7 @@ pub enum OrderType {
     /// book.
     Limit {
         /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
         /// The order side. It will be matched against the resting orders on the
         /// other side of the order book.
@@ -49,39 +120,249 @@ pub enum OrderType {
         /// other orders at this price or better.
         price: f64,
     },
+    /// An immediate-or-cancel order, which is matched like a limit order, but
+    /// whose unfilled quantity is canceled instead of being added to the order
+    /// book.
+    ImmediateOrCancel {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The limit price. The order book will only match this order with
+        /// other orders at this price or better.
+        price: f64,
+    },
+    /// A fill-or-kill order, which is only matched if its whole quantity can
+    /// be filled at its limit price or better. Otherwise, it is canceled
+    /// without touching the order book.
+    FillOrKill {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The limit price. The order book will only match this order with
+        /// other orders at this price or better.
+        price: f64,
+    },
+    /// An iceberg order, which is matched like a limit order, but only shows
+    /// up to `peak_qty` of its unfilled quantity on the order book at a time.
+    /// When the visible quantity is filled, the next slice of up to `peak_qty`
+    /// is released from the hidden reserve, and queued at the back of the
+    /// price level.
+    Iceberg {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The total order quantity, visible and hidden.
+        qty: f64,
+        /// The limit price. The order book will only match this order with
+        /// other orders at this price or better.
+        price: f64,
+        /// The maximum quantity visible on the order book at a time.
+        peak_qty: f64,
+    },
+    /// An all-or-none order, which is matched like a limit order when placed,
+    /// but whose resting quantity is only matched by an incoming order that
+    /// can take all of it at once. Smaller incoming orders skip it and match
+    /// the orders queued behind it.
+    AllOrNone {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The limit price. The order book will only match this order with
+        /// other orders at this price or better.
+        price: f64,
+    },
+    /// A limit order priced relative to the mid price of the order book. It
+    /// is resolved to an absolute limit price at submission time, and then
+    /// handled like a regular [`Limit`] order.
+    ///
+    /// [`Limit`]: #variant.Limit
+    RelativeLimit {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The distance from the mid price in ticks, away from the other side
+        /// of the order book: bids are priced below the mid and asks above
+        /// it. Negative values move the price towards the other side.
+        ticks: i64,
+    },
+    /// A limit order that is only added to the order book if it sets a new
+    /// best price on its side, strictly improving the highest bid or lowest
+    /// ask. It is rejected if it would match or would not improve the best
+    /// price, and it is never filled on submission.
+    ImproveOrReject {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The limit price.
+        price: f64,
+    },
+    /// A limit order that is only added to the order book if it is at the
+    /// front of the best price level of its side, i.e. if it sets a new best
+    /// price. Otherwise, it would be queued behind other orders and is
+    /// canceled instead. It can be filled on submission like a regular
+    /// [`Limit`] order, with any remaining quantity resting at the touch.
+    ///
+    /// [`Limit`]: #variant.Limit
+    TouchOrCancel {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The limit price. The order book will only match this order with
+        /// other orders at this price or better.
+        price: f64,
+    },
+    /// A limit order that is canceled if it is still resting on the order
+    /// book after the specified number of trades occurred, for simulations
+    /// that do not model time. Each fill counts as a trade.
+    GoodForTrades {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the resting orders on the
+        /// other side of the order book.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+        /// The limit price. The order book will only match this order with
+        /// other orders at this price or better.
+        price: f64,
+        /// The number of trades after which the order expires.
+        trades: u64,
+    },
+    /// A hidden order pegged to the mid price, as in a dark midpoint pool. It
+    /// never shows up in the depth of the order book, and it is matched with
+    /// incoming orders at the mid price they find, if they would trade at it.
+    /// The mid price is rounded to the nearest tick of the order side, in
+    /// favour of the order when it falls exactly between two ticks, so its
+    /// fills are on the tick grid. Whether incoming orders priced exactly at
+    /// that price trade with it is set with
+    /// [`OrderBook::allow_peg_fills_at_mid`].
+    ///
+    /// The displayed orders take precedence at the same price: the order only
+    /// trades while the rounded mid price is strictly inside the spread, ahead
+    /// of the displayed orders, which it improves on. Orders of the other side
+    /// pegged to the mid match it when they are placed. The self-trade
+    /// prevention applies to it like to the displayed orders.
+    ///
+    /// [`OrderBook::allow_peg_fills_at_mid`]: struct.OrderBook.html#method.allow_peg_fills_at_mid
+    MidpointPeg {
+        /// The unique ID of this order.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The order side. It will be matched against the incoming orders of
+        /// the other side.
+        side: Side,
+        /// The order quantity.
+        qty: f64,
+    },
     /// A cancel order, which removes the order with the specified ID from the
     /// order book.
     Cancel {
         /// The unique ID of the order to be canceled.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
     },
 }
 
+impl OrderType {
+    /// Create a [`Market`] order.
+    ///
+    /// [`Market`]: #variant.Market
+    pub fn market(id: u128, side: Side, qty: f64) -> Self {
+        OrderType::Market { id, side, qty }
+    }
+
+    /// Create a [`Limit`] order.
+    ///
+    /// [`Limit`]: #variant.Limit
+    pub fn limit(id: u128, side: Side, qty: f64, price: f64) -> Self {
+        OrderType::Limit {
+            id,
+            side,
+            qty,
+            price,
+        }
+    }
+
+    /// Create a [`Cancel`] order.
+    ///
+    /// [`Cancel`]: #variant.Cancel
+    pub fn cancel(id: u128) -> Self {
+        OrderType::Cancel { id }
+    }
+}
+
 /// An event resulting from the execution of an order.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
 pub enum OrderEvent {
     /// Indicating that the corresponding order was not filled. It is only sent
     /// in response to market orders.
     Unfilled {
         /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
     },
     /// Indicating that the corresponding order was placed on the order book. It
     /// is only send in response to limit orders.
     Placed {
         /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
     },
     /// Indicating that the corresponding order was removed from the order book.
-    /// It is only sent in response to cancel orders.
+    /// It is sent in response to cancel orders, and for orders that are
+    /// canceled by the order book itself (e.g. touch-or-cancel orders).
     Canceled {
         /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
+        /// The quantity that was still resting when the order was removed, or
+        /// `0.0` if the order was not on the order book.
+        canceled_qty: f64,
+        /// Whether the order was found, i.e. `false` if a cancel order was
+        /// given the ID of an order that is not resting or dormant on the
+        /// order book, in which case nothing was removed.
+        found: bool,
     },
     /// Indicating that the corresponding order was only partially filled. It is
     /// sent in response to market or limit orders.
     PartiallyFilled {
         /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
         /// The filled quantity.
         filled_qty: f64,
@@ -92,24 +373,291 @@ pub enum OrderEvent {
     /// sent in response to market or limit orders.
     Filled {
         /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
         id: u128,
         /// The filled quantity.
         filled_qty: f64,
         /// A vector with information on the order fills.
         fills: Vec<FillMetadata>,
     },
+    /// Indicating that the corresponding resting order was modified in place.
+    Amended {
+        /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// The quantity resting on the order book after the amendment.
+        new_qty: f64,
+    },
+    /// Indicating that the corresponding resting order was removed from the
+    /// order book because it expired.
+    Expired {
+        /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+    },
+    /// Indicating that the corresponding resting order was replaced by a new
+    /// order resting on the order book.
+    Replaced {
+        /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+    },
+    /// Indicating that the request was refused and the order book was left
+    /// untouched.
+    Rejected {
+        /// The ID of the order this event is referring to.
+        #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
+        id: u128,
+        /// Why the request was refused.
+        reason: RejectReason,
+    },
+}
+
+impl OrderEvent {
+    /// Return the number of distinct price levels consumed by the order, a
+    /// cheap indicator of its market impact, or `0` if it was not filled.
+    /// Fills are reported in matching order, so each price change in the
+    /// fills marks a new level.
+    pub fn levels_swept(&self) -> usize {
+        match self {
+            OrderEvent::PartiallyFilled { fills, .. }
+            | OrderEvent::Filled { fills, .. } => {
+                let mut levels = 0;
+                let mut last_price = None;
+                for fill in fills {
+                    if last_price != Some(fill.price) {
+                        levels += 1;
+                        last_price = Some(fill.price);
+                    }
+                }
+                levels
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// The reason attached to an [`OrderEvent::Rejected`] event.
+///
+/// [`OrderEvent::Rejected`]: enum.OrderEvent.html#variant.Rejected
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[pyclass]
+pub enum RejectReason {
+    /// The referenced order is not resting on the order book.
+    UnknownOrder,
+    /// The order quantity is not a finite, positive number.
+    InvalidQty,
+    /// The order price is not a finite, positive number.
+    InvalidPrice,
+    /// The order is priced relative to the mid price, but the order book has
+    /// no mid price.
+    NoReferencePrice,
+    /// The order would have matched resting orders on the other side.
+    WouldCross,
+    /// The order would not have improved the best price of its side.
+    WouldNotImprove,
+    /// The order book is frozen and does not accept changes.
+    Frozen,
+    /// An order with the same ID is already on the order book.
+    DuplicateId,
+    /// The order to cancel has not rested on the order book for the minimum
+    /// resting time yet.
+    MinRestingTime,
+}
+
+/// The status of an order, as returned by [`OrderBook::order_status`].
+///
+/// [`OrderBook::order_status`]: struct.OrderBook.html#method.order_status
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OrderStatus {
+    /// The order rests on the order book.
+    Resting {
+        /// The remaining quantity of the order. For iceberg orders, this is
+        /// the visible quantity.
+        qty: f64,
+    },
+    /// The order was fully filled as a maker order, with no quantity left.
+    Filled,
+}
+
+/// The self-trade prevention policy applied when an order would be matched
+/// with a resting order of the same account (see
+/// [`OrderBook::set_stp_mode`]).
+///
+/// [`OrderBook::set_stp_mode`]: struct.OrderBook.html#method.set_stp_mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StpMode {
+    /// Cancel the unfilled quantity of the incoming order, leaving the resting
+    /// order in place.
+    CancelNewest,
+    /// Cancel the resting order, and keep matching the incoming order.
+    CancelOldest,
+    /// Cancel both the resting order and the unfilled quantity of the
+    /// incoming order.
+    CancelBoth,
+}
+
+/// How the quantity matched at a price level is allocated among its resting
+/// orders (see [`OrderBook::set_matching_mode`]).
+///
+/// [`OrderBook::set_matching_mode`]: struct.OrderBook.html#method.set_matching_mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MatchingMode {
+    /// Price-time priority at every level: the orders of a level are filled
+    /// in the order they were queued.
+    Fifo,
+    /// Time priority for the top order, pro-rata for the rest, at the best
+    /// level: the first order queued at the best level is filled first, and
+    /// the quantity left for that level is allocated among its other orders
+    /// in proportion to their visible quantity. Deeper levels are matched in
+    /// price-time priority.
+    TopOrderProRata,
+}
+
+/// The result of [`OrderBook::execute_with_levels`]: the order event,
+/// together with the price levels whose aggregate quantity was changed by the
+/// order.
+///
+/// [`OrderBook::execute_with_levels`]: struct.OrderBook.html#method.execute_with_levels
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExecutionReport {
+    /// The event resulting from the execution of the order.
+    pub event: OrderEvent,
+    /// The changed ask levels with their new quantity, which is `0.0` for
+    /// levels that were emptied, sorted from the lowest price.
+    pub asks: Vec<BookLevel>,
+    /// The changed bid levels with their new quantity, which is `0.0` for
+    /// levels that were emptied, sorted from the highest price.
+    pub bids: Vec<BookLevel>,
+}
+
+/// The result of [`OrderBook::cancel_report`]: the cancel event, together with
+/// the state of the canceled order right before it was removed.
+///
+/// [`OrderBook::cancel_report`]: struct.OrderBook.html#method.cancel_report
+#[derive(Debug, PartialEq, Clone)]
+pub struct CancelReport {
+    /// The event resulting from the cancel.
+    pub event: OrderEvent,
+    /// Whether a resting or dormant order was canceled.
+    pub found: bool,
+    /// The side of the canceled order, or `None` if nothing was canceled.
+    pub side: Option<Side>,
+    /// The price of the canceled resting order, or `0.0` if no resting order
+    /// was canceled.
+    pub price: f64,
+    /// The quantity of the order left when it was canceled, or `0.0` if
+    /// nothing was canceled.
+    pub qty: f64,
+    /// The position of the canceled resting order in the queue at its price
+    /// level (`0` being the front), or `0` if no resting order was canceled.
+    pub queue_position: usize,
+}
+
+/// The indicative result of uncrossing a crossed order book in an auction, as
+/// returned by [`OrderBook::auction_info`].
+///
+/// [`OrderBook::auction_info`]: struct.OrderBook.html#method.auction_info
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuctionResult {
+    /// The price maximizing the matched quantity.
+    pub price: f64,
+    /// The quantity that would be matched at the clearing price.
+    pub matched_qty: f64,
+    /// The quantity left unmatched at the clearing price on the side with
+    /// more quantity.
+    pub imbalance_qty: f64,
+    /// The side with more quantity at the clearing price, or `None` if both
+    /// sides are balanced.
+    pub imbalance_side: Option<Side>,
+}
+
+/// A snapshot of the common order book signals, as returned by
+/// [`OrderBook::signals`].
+///
+/// [`OrderBook::signals`]: struct.OrderBook.html#method.signals
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signals {
+    /// The difference of the lowest ask and highest bid.
+    pub spread: Option<f64>,
+    /// The spread in basis points of the mid price.
+    pub spread_bps: Option<f64>,
+    /// The average of the lowest ask and highest bid.
+    pub mid: Option<f64>,
+    /// The mid price weighted by the quantity of the best level of each side.
+    pub micro_price: Option<f64>,
+    /// The difference of the bid and ask volumes divided by their sum, from
+    /// `-1.0` (only asks) to `1.0` (only bids).
+    pub imbalance: Option<f64>,
+    /// The resting quantity of the top bid levels.
+    pub bid_volume: f64,
+    /// The resting quantity of the top ask levels.
+    pub ask_volume: f64,
+}
+
+/// The statistics of the trades recorded while the stats tracking was active,
+/// as returned by [`OrderBook::session_summary`].
+///
+/// [`OrderBook::session_summary`]: struct.OrderBook.html#method.session_summary
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    /// The total traded quantity.
+    pub traded_volume: f64,
+    /// The number of executed orders that traded.
+    pub order_count: u64,
+    /// The number of fills, an order trading with several resting orders
+    /// generating several fills.
+    pub fill_count: u64,
+    /// The highest fill price.
+    pub high: Option<f64>,
+    /// The lowest fill price.
+    pub low: Option<f64>,
+    /// The volume-weighted average fill price.
+    pub vwap: Option<f64>,
+    /// The quantity traded by buying takers.
+    pub buy_volume: f64,
+    /// The quantity traded by selling takers.
+    pub sell_volume: f64,
 }
 
 /// Information on a single order fill. When an order is matched with multiple
 /// resting orders, it generates multiple `FillMetadata` values.
+///
+/// The order book only matches continuously. The order being executed is
+/// always the taker (order 1) and trades at the price of the resting maker
+/// order (order 2), also when both orders have the same price. The makers are
+/// picked according to the matching mode (see
+/// [`OrderBook::set_matching_mode`]):
+///
+/// - [`MatchingMode::Fifo`]: in price-time priority at every level.
+/// - [`MatchingMode::TopOrderProRata`]: at the best level, the first order
+///   queued is filled first and the rest of the quantity matched there is
+///   allocated among the other orders in proportion to their visible
+///   quantity, while deeper levels are matched in price-time priority.
+///
+/// In both modes, the midpoint pegged orders of the other side are matched
+/// first, in the order they were placed, at the mid price rounded to the
+/// tick grid in their favour (see [`OrderType::MidpointPeg`]). The fill of an
+/// internalized order against the synthetic counterparty is priced at the
+/// mid price, and a triggered order is the taker of the fills of the market
+/// order it is executed as.
+///
+/// [`OrderBook::set_matching_mode`]: struct.OrderBook.html#method.set_matching_mode
+/// [`MatchingMode::Fifo`]: enum.MatchingMode.html#variant.Fifo
+/// [`MatchingMode::TopOrderProRata`]: enum.MatchingMode.html#variant.TopOrderProRata
+/// [`OrderType::MidpointPeg`]: enum.OrderType.html#variant.MidpointPeg
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[pyclass]
 pub struct FillMetadata {
     /// The ID of the order that triggered the fill (taker).
     #[pyo3(get, set)]
+    #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
     pub order_1: u128,
     /// The ID of the matching order.
     #[pyo3(get, set)]
+    #[cfg_attr(feature = "serde", serde(with = "serde_u128"))]
     pub order_2: u128,
     /// The quantity that was traded.
     #[pyo3(get, set)]
@@ -117,27 +665,42 @@ pub struct FillMetadata {
     /// The price at which the trade happened.
     #[pyo3(get, set)]
     pub price: f64,
-    /// The side of the taker order (order 1)
+    /// The side of the taker order (order 1).
     #[pyo3(get, set)]
     pub taker_side: Side,
     /// Whether this order was a total (true) or partial (false) fill of the
     /// maker order.
     #[pyo3(get, set)]
     pub total_fill: bool,
+    /// The sequence number of the maker order at its price level (see
+    /// [`OrderBook::order_ts`]), or 0 for synthetic fills.
+    ///
+    /// [`OrderBook::order_ts`]: struct.OrderBook.html#method.order_ts
+    #[pyo3(get, set)]
+    pub maker_ts: u64,
+    /// How much better than the limit price of the taker order the trade
+    /// happened, or `0.0` if the taker had no limit price.
+    #[pyo3(get, set)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub price_improvement: f64,
 }
 
 #[pymethods]
 impl FillMetadata {
     #[new]
+    #[args(maker_ts = "0", price_improvement = "0.0")]
+    #[allow(clippy::too_many_arguments)]
     fn py_new(
         order_1: u128,
         order_2: u128,
         qty: f64,
         price: f64,
         taker_side: Side,
-        total_fill: bool
+        total_fill: bool,
+        maker_ts: u64,
+        price_improvement: f64
         ) -> PyResult<Self> {
-            Ok(FillMetadata { order_1, order_2, qty, price, taker_side, total_fill })
+            Ok(FillMetadata { order_1, order_2, qty, price, taker_side, total_fill, maker_ts, price_improvement })
     }
 }
 
@@ -146,6 +709,7 @@ impl FillMetadata {
 ///
 /// [`BookLevel`]: /struct.BookLevel.html
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[pyclass]
 pub struct BookDepth {
     /// The requested level. This field will always contain the level that was
@@ -174,7 +738,8 @@ impl BookDepth {
 
 /// A single level in the order book. This struct is used both for the bid and
 /// ask side.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[pyclass]
 pub struct BookLevel {
     /// The price point this level represents.
@@ -196,8 +761,42 @@ impl BookLevel {
     }
 }
 
+/// The best bid and offer of the order book, with the total quantity resting
+/// at each price.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[pyclass]
+pub struct Bbo {
+    /// The highest bid price.
+    #[pyo3(get, set)]
+    pub bid_px: f64,
+    /// The total quantity of all the bids resting at the highest price.
+    #[pyo3(get, set)]
+    pub bid_qty: f64,
+    /// The lowest ask price.
+    #[pyo3(get, set)]
+    pub ask_px: f64,
+    /// The total quantity of all the asks resting at the lowest price.
+    #[pyo3(get, set)]
+    pub ask_qty: f64,
+}
+
+#[pymethods]
+impl Bbo {
+    #[new]
+    fn py_new(
+        bid_px: f64,
+        bid_qty: f64,
+        ask_px: f64,
+        ask_qty: f64
+        ) -> PyResult<Self> {
+            Ok(Bbo { bid_px, bid_qty, ask_px, ask_qty })
+    }
+}
+
 /// A trade that happened as part of the matching process.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[pyclass]
 pub struct Trade {
     /// The total quantity transacted as part of this trade.
@@ -228,20 +827,250 @@ impl Trade {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// A public view of an order resting on the order book, as yielded by
+/// [`OrderBook::iter_orders`].
+///
+/// [`OrderBook::iter_orders`]: struct.OrderBook.html#method.iter_orders
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RestingOrder {
+    /// The ID of the order.
+    pub id: u128,
+    /// The side of the order.
+    pub side: Side,
+    /// The limit price of the order.
+    pub price: f64,
+    /// The remaining quantity of the order. For iceberg orders, this is the
+    /// visible quantity.
+    pub qty: f64,
+}
+
+/// A limit order resting on the order book.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LimitOrder {
+    /// The ID of the order.
     pub id: u128,
+    /// The side of the order.
+    pub side: Side,
+    /// The remaining quantity of the order. For iceberg orders, this is the
+    /// visible quantity.
     pub qty: f64,
+    /// The limit price of the order.
     pub price: f64,
+    /// The hidden quantity of an iceberg order, not yet released to the order
+    /// book, or 0 for other orders.
+    pub reserve_qty: f64,
+    /// The quantity released at a time by an iceberg order, or 0 for other
+    /// orders.
+    pub peak_qty: f64,
+    /// Whether the order can only be matched in full, for all-or-none orders.
+    pub all_or_none: bool,
+    /// The trade count after which the order expires, for good-for-trades
+    /// orders.
+    pub expires_after: Option<u64>,
+    /// The order book revision (see [`OrderBook::version`]) created by placing
+    /// the order.
+    ///
+    /// [`OrderBook::version`]: struct.OrderBook.html#method.version
+    pub placed_at: u64,
+    /// The sequence number of the order at its price level, taken when the
+    /// order was queued at the back of the level. Orders at the same price
+    /// are matched in increasing sequence number order.
+    pub ts: u64,
+    /// The account the order was placed for (see
+    /// [`OrderBook::execute_for_account`]), if any.
+    ///
+    /// [`OrderBook::execute_for_account`]: struct.OrderBook.html#method.execute_for_account
+    pub account: Option<u64>,
+}
+
+/// The state of an order book, as returned by [`OrderBook::snapshot`], from
+/// which it can be rebuilt with [`OrderBook::restore`].
+///
+/// [`OrderBook::snapshot`]: struct.OrderBook.html#method.snapshot
+/// [`OrderBook::restore`]: struct.OrderBook.html#method.restore
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BookSnapshot {
+    /// The resting orders, asks first and bids second, each side in priority
+    /// order (best price first, then time priority).
+    pub orders: Vec<LimitOrder>,
+    /// The number of decimal digits kept for bid prices.
+    pub bid_precision: u128,
+    /// The number of decimal digits kept for ask prices.
+    pub ask_precision: u128,
+    /// Whether volume and trades tracking is enabled.
+    pub track_stats: bool,
+    /// The last trade, if stats tracking is enabled, unrounded.
+    pub last_trade: Option<Trade>,
+    /// The side of the order that made the last trade, if any.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub last_taker_side: Option<Side>,
+    /// The total traded volume, if stats tracking is enabled.
+    pub traded_volume: f64,
+    /// The number of trades that occurred.
+    pub trade_count: u64,
+    /// The revision number of the order book.
+    pub version: u64,
+    /// The last time priority sequence number assigned to an order (see
+    /// [`FillMetadata::maker_ts`]).
+    ///
+    /// [`FillMetadata::maker_ts`]: struct.FillMetadata.html#structfield.maker_ts
+    pub last_ts: u64,
+}
+
+/// A maker order matched by the order being executed, as recorded in the
+/// matching trace (see [`OrderBook::last_trace`]).
+///
+/// [`OrderBook::last_trace`]: struct.OrderBook.html#method.last_trace
+#[cfg(any(test, feature = "debug"))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    /// The ID of the maker order.
+    pub maker_id: u128,
+    /// The internal key of the price level of the maker order.
+    pub level: u64,
+    /// The quantity matched with the maker order.
+    pub qty: f64,
+}
+
+/// A dormant order, executed as a market order once a trade touches its
+/// trigger price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerOrder {
+    pub id: u128,
+    pub side: Side,
+    pub qty: f64,
+    pub trigger_price: f64,
+    /// Whether the order is triggered by trades at or below the trigger
+    /// price, rather than at or above it.
+    pub on_fall: bool,
+}
+
+/// A hidden order pegged to the mid price, waiting for incoming orders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeggedOrder {
+    pub id: u128,
+    pub side: Side,
+    pub qty: f64,
+    /// The time priority sequence number of the order.
+    pub ts: u64,
+    /// The account the order was placed on behalf of, if any.
+    pub account: Option<u64>,
+}
+
+/// (De)serialization of the `u128` IDs, which the internally tagged enums
+/// cannot buffer: IDs that fit in a `u64` are written as numbers, and larger
+/// ones (e.g. [`SYNTHETIC_COUNTERPARTY`]) as strings.
+///
+/// [`SYNTHETIC_COUNTERPARTY`]: constant.SYNTHETIC_COUNTERPARTY.html
+#[cfg(feature = "serde")]
+mod serde_u128 {
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    pub fn serialize<S>(id: &u128, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match u64::try_from(*id) {
+            Ok(id) => s.serialize_u64(id),
+            Err(_) => s.serialize_str(&id.to_string()),
+        }
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<u128, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct IdVisitor;
+
+        impl<'de> Visitor<'de> for IdVisitor {
+            type Value = u128;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an unsigned integer or a string holding one")
+            }
+
+            fn visit_u64<E: de::Error>(self, id: u64) -> Result<u128, E> {
+                Ok(id.into())
+            }
+
+            fn visit_u128<E: de::Error>(self, id: u128) -> Result<u128, E> {
+                Ok(id)
+            }
+
+            fn visit_str<E: de::Error>(self, id: &str) -> Result<u128, E> {
+                id.parse().map_err(E::custom)
+            }
+        }
+
+        d.deserialize_any(IdVisitor)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Side;
+    use super::{OrderType, Side};
+    #[cfg(feature = "serde")]
+    use super::{FillMetadata, OrderEvent, SYNTHETIC_COUNTERPARTY};
 
     #[test]
     fn side_negation() {
         assert_eq!(!Side::Ask, Side::Bid);
         assert_eq!(!Side::Bid, Side::Ask);
     }
+
+    #[test]
+    fn order_type_constructors() {
+        assert!(matches!(
+            OrderType::market(1, Side::Bid, 2.5),
+            OrderType::Market {
+                id: 1,
+                side: Side::Bid,
+                qty,
+            } if qty == 2.5
+        ));
+        assert!(matches!(
+            OrderType::limit(2, Side::Ask, 1.5, 395.0),
+            OrderType::Limit {
+                id: 2,
+                side: Side::Ask,
+                qty,
+                price,
+            } if qty == 1.5 && price == 395.0
+        ));
+        assert!(matches!(OrderType::cancel(3), OrderType::Cancel { id: 3 }));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let fill = |order_2, qty, price, maker_ts| FillMetadata {
+            order_1: 3,
+            order_2,
+            qty,
+            price,
+            taker_side: Side::Bid,
+            total_fill: true,
+            maker_ts,
+            price_improvement: 0.0,
+        };
+        let event = OrderEvent::PartiallyFilled {
+            id: 3,
+            filled_qty: 2.75,
+            fills: vec![
+                fill(0, 1.5, 395.0, 1),
+                fill(SYNTHETIC_COUNTERPARTY, 1.25, 395.5, 0),
+            ],
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.starts_with(r#"{"type":"PartiallyFilled","id":3,"#));
+        assert!(json.contains(r#""order_2":0,"qty":1.5,"price":395.0,"#));
+        assert!(json.contains(&format!(r#""{}""#, SYNTHETIC_COUNTERPARTY)));
+        assert_eq!(serde_json::from_str::<OrderEvent>(&json).unwrap(), event);
+    }
 }
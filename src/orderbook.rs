@@ -1,28 +1,175 @@
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Write};
 
 use crate::arena::OrderArena;
 use crate::models::{
-    BookDepth, BookLevel, FillMetadata, OrderEvent, OrderType, Side, Trade,
+    AuctionResult, Bbo, BookDepth, BookLevel, BookSnapshot, CancelReport,
+    ExecutionReport, FillMetadata, LimitOrder, MatchingMode, OrderEvent,
+    OrderStatus, OrderType, PeggedOrder, RejectReason, RestingOrder,
+    SessionSummary, Side, Signals, StpMode, Trade, TriggerOrder,
+    SYNTHETIC_COUNTERPARTY,
 };
+#[cfg(any(test, feature = "debug"))]
+use crate::models::TraceStep;
 
 const DEFAULT_ARENA_CAPACITY: usize = 10_000;
 const DEFAULT_QUEUE_CAPACITY: usize = 10;
 const DEFAULT_PRECISION: u128 = 8;
+const TRADE_HISTORY_CAPACITY: usize = 1_000;
+const DEFAULT_MAX_CASCADE_DEPTH: usize = 64;
+
+/// Keys of the ask and bid price levels changed by the order being executed.
+type TouchedLevels = (BTreeSet<u64>, BTreeSet<u64>);
+
+/// Ask and bid side `(price, cumulative value)` pairs, best price first.
+pub type CumulativeDepth = (Vec<(f64, f64)>, Vec<(f64, f64)>);
+
+/// A callback invoked with the side and the price of a price level (see
+/// [`OrderBook::on_level_added`]).
+///
+/// [`OrderBook::on_level_added`]: struct.OrderBook.html#method.on_level_added
+pub type LevelCallback = Box<dyn FnMut(Side, f64) + Send>;
+
+/// The price level callbacks of an order book, with the keys of the ask and
+/// bid levels that were non-empty when they were last notified.
+///
+/// The callbacks are not cloned, so that the copies of the order book used to
+/// evaluate an order without executing it do not notify anything.
+#[derive(Default)]
+struct LevelHooks {
+    added: Option<LevelCallback>,
+    removed: Option<LevelCallback>,
+    live: TouchedLevels,
+}
+
+impl LevelHooks {
+    fn is_set(&self) -> bool {
+        self.added.is_some() || self.removed.is_some()
+    }
+}
+
+impl Clone for LevelHooks {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for LevelHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LevelHooks")
+            .field("added", &self.added.is_some())
+            .field("removed", &self.removed.is_some())
+            .field("live", &self.live)
+            .finish()
+    }
+}
+
+/// The self-trade prevention state of the order being matched.
+#[derive(Debug, Clone)]
+struct SelfTradeGuard {
+    id: u128,
+    account: u64,
+    mode: StpMode,
+    /// The number of resting orders of the account met while matching.
+    matches: u64,
+    /// IDs and quantities of the resting orders canceled while matching.
+    canceled: Vec<(u128, f64)>,
+    /// Whether the unfilled quantity of the incoming order was canceled.
+    taker_canceled: bool,
+}
+
+/// The state of an order book captured before an execution, to roll it back
+/// afterwards.
+#[derive(Debug)]
+struct Savepoint {
+    /// The order book without its orders and its tape.
+    state: Box<OrderBook>,
+    /// The resting orders, in priority order.
+    snapshot: BookSnapshot,
+    /// The length of the tape, which only grows.
+    tape_len: usize,
+}
+
+/// The counters behind the session summary, updated with the other stats.
+#[derive(Debug, Clone, Default)]
+struct SessionStats {
+    orders: u64,
+    fills: u64,
+    high: Option<f64>,
+    low: Option<f64>,
+    notional: f64,
+    buy_volume: f64,
+    sell_volume: f64,
+}
 
 /// An order book that executes orders serially through the [`execute`] method.
 ///
+/// Orders are identified by a `u128` ID, and every value is valid, including
+/// `0`, except [`SYNTHETIC_COUNTERPARTY`], which is reserved. An ID can be
+/// reused once the order it identified left the order book: until then, the
+/// orders that could rest or lie dormant with it are rejected with
+/// [`RejectReason::DuplicateId`].
+///
+/// Cloning an order book makes a deep copy, fully independent of the original
+/// (e.g. to explore a branch of a simulation and discard it). It copies the
+/// resting and dormant orders, the stats and the recorded history, but not the
+/// price level callbacks. Its cost is linear in the arena capacity, as every
+/// preallocated slot is copied, plus the size of the recorded history.
+///
 /// [`execute`]: #method.execute
-#[derive(Debug)]
+/// [`SYNTHETIC_COUNTERPARTY`]: constant.SYNTHETIC_COUNTERPARTY.html
+/// [`RejectReason::DuplicateId`]: enum.RejectReason.html#variant.DuplicateId
+#[derive(Debug, Clone)]
 pub struct OrderBook {
     last_trade: Option<Trade>,
+    last_taker_side: Side,
     traded_volume: f64,
+    session: SessionStats,
     min_ask: Option<f64>,
     max_bid: Option<f64>,
     asks: BTreeMap<u64, Vec<usize>>,
     bids: BTreeMap<u64, Vec<usize>>,
     arena: OrderArena,
     default_queue_capacity: usize,
-    precision: f64,
+    bid_precision: f64,
+    ask_precision: f64,
+    display_precision: Option<f64>,
+    tick_size: Option<f64>,
+    ask_depletion: BTreeMap<u64, f64>,
+    bid_depletion: BTreeMap<u64, f64>,
+    frozen: bool,
+    cancel_while_frozen: bool,
+    peg_fills_at_mid: bool,
+    max_levels: Option<usize>,
+    round_trade_prices: bool,
+    pending_events: Vec<OrderEvent>,
+    recent_events: VecDeque<OrderEvent>,
+    recent_events_capacity: usize,
+    filled_orders: VecDeque<u128>,
+    filled_orders_capacity: usize,
+    trade_prices: VecDeque<f64>,
+    touched_levels: Option<TouchedLevels>,
+    trade_count: u64,
+    expiries: BTreeMap<u64, Vec<u128>>,
+    tape: Vec<FillMetadata>,
+    record_tape: bool,
+    aggregate_fills: bool,
+    triggers: Vec<TriggerOrder>,
+    max_cascade_depth: usize,
+    pegs: Vec<PeggedOrder>,
+    version: u64,
+    min_resting_time: u64,
+    stp_mode: Option<StpMode>,
+    matching_mode: MatchingMode,
+    taker_account: Option<u64>,
+    self_trade: Option<SelfTradeGuard>,
+    self_match_count: u64,
+    stp_canceled: Vec<u128>,
+    cancel_on_disconnect: BTreeSet<u64>,
+    level_hooks: LevelHooks,
+    #[cfg(any(test, feature = "debug"))]
+    trace: Option<Vec<TraceStep>>,
     track_stats: bool,
 }
 
@@ -54,31 +201,245 @@ impl OrderBook {
         queue_capacity: usize,
         precision: u128,
         track_stats: bool,
+    ) -> Self {
+        Self::with_side_precision(
+            arena_capacity,
+            queue_capacity,
+            precision,
+            precision,
+            track_stats,
+        )
+    }
+
+    /// Create an instance representing a single order book, where bids and
+    /// asks are quoted on different tick grids.
+    ///
+    /// The `bid_precision` and `ask_precision` parameters are the number of
+    /// decimal digits kept when converting the prices of the respective side
+    /// to price levels. All the other parameters are the same as in [`new`].
+    ///
+    /// [`new`]: #method.new
+    pub fn with_side_precision(
+        arena_capacity: usize,
+        queue_capacity: usize,
+        bid_precision: u128,
+        ask_precision: u128,
+        track_stats: bool,
     ) -> Self {
         Self {
             last_trade: None,
+            last_taker_side: Side::Bid,
             traded_volume: 0.0,
+            session: SessionStats::default(),
             min_ask: None,
             max_bid: None,
             asks: BTreeMap::new(),
             bids: BTreeMap::new(),
             arena: OrderArena::new(arena_capacity),
             default_queue_capacity: queue_capacity,
-            precision: (10.0 as f64).powf(precision as f64),
+            bid_precision: 10_f64.powf(bid_precision as f64),
+            ask_precision: 10_f64.powf(ask_precision as f64),
+            display_precision: None,
+            tick_size: None,
+            ask_depletion: BTreeMap::new(),
+            bid_depletion: BTreeMap::new(),
+            frozen: false,
+            cancel_while_frozen: false,
+            peg_fills_at_mid: true,
+            max_levels: None,
+            round_trade_prices: false,
+            pending_events: Vec::new(),
+            recent_events: VecDeque::new(),
+            recent_events_capacity: 0,
+            filled_orders: VecDeque::new(),
+            filled_orders_capacity: 0,
+            trade_prices: VecDeque::new(),
+            touched_levels: None,
+            trade_count: 0,
+            expiries: BTreeMap::new(),
+            tape: Vec::new(),
+            record_tape: false,
+            aggregate_fills: false,
+            triggers: Vec::new(),
+            max_cascade_depth: DEFAULT_MAX_CASCADE_DEPTH,
+            pegs: Vec::new(),
+            version: 0,
+            min_resting_time: 0,
+            stp_mode: None,
+            matching_mode: MatchingMode::Fifo,
+            taker_account: None,
+            self_trade: None,
+            self_match_count: 0,
+            stp_canceled: Vec::new(),
+            cancel_on_disconnect: BTreeSet::new(),
+            level_hooks: LevelHooks::default(),
+            #[cfg(any(test, feature = "debug"))]
+            trace: None,
             track_stats,
         }
     }
 
+    /// Remove every order and reset the stats and the recorded history, to
+    /// reuse the order book as if it was new, e.g. between simulation runs.
+    /// The settings (precision, capacities, stats tracking, matching rules and
+    /// callbacks) are kept, as well as the memory allocated for the orders.
+    ///
+    /// The price level callbacks are invoked for the removed levels, and the
+    /// queued events are discarded. The version is bumped once, so that
+    /// observers of the revisions see the book change.
+    pub fn clear(&mut self) {
+        self.last_trade = None;
+        self.traded_volume = 0.0;
+        self.session = SessionStats::default();
+        self.min_ask = None;
+        self.max_bid = None;
+        self.asks.clear();
+        self.bids.clear();
+        self.arena.clear();
+        self.ask_depletion.clear();
+        self.bid_depletion.clear();
+        self.frozen = false;
+        self.pending_events.clear();
+        self.recent_events.clear();
+        self.filled_orders.clear();
+        self.trade_prices.clear();
+        self.trade_count = 0;
+        self.expiries.clear();
+        self.tape.clear();
+        self.triggers.clear();
+        self.pegs.clear();
+        self.self_match_count = 0;
+        self.stp_canceled.clear();
+        self.version += 1;
+        #[cfg(any(test, feature = "debug"))]
+        if let Some(trace) = self.trace.as_mut() {
+            trace.clear();
+        }
+        if self.level_hooks.is_set() {
+            self.notify_levels(self.level_hooks.live.clone());
+        }
+    }
+
     #[cfg(test)]
     #[doc(hidden)]
-    pub fn _asks(&self) -> BTreeMap<u64, Vec<usize>> {
-        self.asks.clone()
+    pub fn _asks(&self) -> BTreeMap<u64, Vec<u128>> {
+        self.level_ids(&self.asks)
+    }
+
+    #[cfg(test)]
+    #[doc(hidden)]
+    pub fn _bids(&self) -> BTreeMap<u64, Vec<u128>> {
+        self.level_ids(&self.bids)
+    }
+
+    /// Map the arena indices queued at each level to the IDs of the orders,
+    /// so that tests do not depend on the arena capacity.
+    #[cfg(test)]
+    fn level_ids(
+        &self,
+        book: &BTreeMap<u64, Vec<usize>>,
+    ) -> BTreeMap<u64, Vec<u128>> {
+        book.iter()
+            .map(|(key, queue)| {
+                (*key, queue.iter().map(|idx| self.arena[*idx].id).collect())
+            })
+            .collect()
+    }
+
+    /// Check that every index stored in the price level queues points to a
+    /// live arena slot within bounds, whose ID maps back to the same index,
+    /// whose side matches the queue side and whose price maps to the level
+    /// key.
+    ///
+    /// This is a diagnostic for developers, only available in tests and with
+    /// the `debug` feature. Return a description of the first inconsistency
+    /// found, if any.
+    #[cfg(any(test, feature = "debug"))]
+    pub fn audit_arena(&self) -> Result<(), String> {
+        for (side, book) in [(Side::Ask, &self.asks), (Side::Bid, &self.bids)] {
+            for (key, queue) in book {
+                for idx in queue {
+                    let order = match self.arena.slot(*idx) {
+                        Some(order) => order,
+                        None => {
+                            return Err(format!(
+                                "{:?} level {} holds index {}, which is out \
+                                 of the arena bounds",
+                                side, key, idx
+                            ))
+                        }
+                    };
+                    if self.arena.get(order.id).map(|(_, i)| i) != Some(*idx) {
+                        return Err(format!(
+                            "{:?} level {} holds index {} of order {}, which \
+                             is not live at that index",
+                            side, key, idx, order.id
+                        ));
+                    }
+                    if order.side != side {
+                        return Err(format!(
+                            "{:?} level {} holds {:?} order {}",
+                            side, key, order.side, order.id
+                        ));
+                    }
+                    if self.price_key(side, order.price) != *key {
+                        return Err(format!(
+                            "{:?} level {} holds order {} priced at {}",
+                            side, key, order.id, order.price
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Toggle the recording of the matching trace on or off, depending on the
+    /// `record` parameter. Disabled by default.
+    ///
+    /// This is a diagnostic for developers, only available in tests and with
+    /// the `debug` feature.
+    #[cfg(any(test, feature = "debug"))]
+    pub fn record_trace(&mut self, record: bool) {
+        self.trace = if record { Some(Vec::new()) } else { None };
+    }
+
+    /// Return the maker orders matched by the last executed order, in match
+    /// order, if the trace is recorded (see [`record_trace`]). When executing
+    /// an order triggers other orders, the trace is the one of the last
+    /// triggered order.
+    ///
+    /// This is a diagnostic for developers, only available in tests and with
+    /// the `debug` feature.
+    ///
+    /// [`record_trace`]: #method.record_trace
+    #[cfg(any(test, feature = "debug"))]
+    pub fn last_trace(&self) -> &[TraceStep] {
+        self.trace.as_deref().unwrap_or(&[])
     }
 
+    /// Place a resting order without running it through matching. Only used
+    /// by tests that need a book in a state matching can't produce (e.g.
+    /// crossed).
     #[cfg(test)]
     #[doc(hidden)]
-    pub fn _bids(&self) -> BTreeMap<u64, Vec<usize>> {
-        self.bids.clone()
+    pub fn _rest(&mut self, id: u128, side: Side, qty: f64, price: f64) {
+        let index = self.arena.insert(id, side, price, qty);
+        let queue_capacity = self.default_queue_capacity;
+        let vect_price = self.price_key(side, price);
+        let book = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        book.entry(vect_price)
+            .or_insert_with(|| Vec::with_capacity(queue_capacity))
+            .push(index);
+        // The order can be anywhere, so rescan both sides from scratch
+        self.min_ask = None;
+        self.max_bid = None;
+        self.update_min_ask();
+        self.update_max_bid();
+        self.version += 1;
     }
 
     /// Return the lowest ask price, if present.
@@ -93,6 +454,216 @@ impl OrderBook {
         self.max_bid
     }
 
+    /// Return the highest bid and the lowest ask prices in a single call, as a
+    /// `(max_bid, min_ask)` tuple.
+    #[inline(always)]
+    pub fn quotes(&self) -> (Option<f64>, Option<f64>) {
+        (self.max_bid, self.min_ask)
+    }
+
+    /// Return the best bid and offer with the total quantity resting at each
+    /// price as a [`Bbo`] struct, or `None` if either side is empty.
+    ///
+    /// [`Bbo`]: struct.Bbo.html
+    pub fn bbo(&self) -> Option<Bbo> {
+        let (bid_px, ask_px) = (self.max_bid?, self.min_ask?);
+        Some(Bbo {
+            bid_px,
+            bid_qty: self.display_qty(self.qty_at_price(Side::Bid, bid_px)),
+            ask_px,
+            ask_qty: self.display_qty(self.qty_at_price(Side::Ask, ask_px)),
+        })
+    }
+
+    /// Return the average of the lowest ask and highest bid, if both are
+    /// present.
+    #[inline(always)]
+    pub fn mid_price(&self) -> Option<f64> {
+        match (self.max_bid, self.min_ask) {
+            (Some(b), Some(a)) => Some((a + b) / 2.0),
+            _ => None,
+        }
+    }
+
+    /// Return the mid price weighted by the resting quantity of the top
+    /// `levels` levels of each side, or `None` if either side is empty.
+    ///
+    /// Each side is summarized by the volume-weighted average price and the
+    /// total quantity of its top levels, and each side price is weighted by
+    /// the quantity of the other side, so the result leans towards the side
+    /// with less liquidity. With a single level, this is the micro-price.
+    pub fn weighted_mid(&self, levels: usize) -> Option<f64> {
+        let aggregate = |side| {
+            let (notional, qty) = self
+                .levels(side)
+                .take(levels)
+                .fold((0.0, 0.0), |(n, q), (price, qty)| {
+                    (n + price * qty, q + qty)
+                });
+            if qty > 0.0 {
+                Some((notional / qty, qty))
+            } else {
+                None
+            }
+        };
+        let (bid, bid_qty) = aggregate(Side::Bid)?;
+        let (ask, ask_qty) = aggregate(Side::Ask)?;
+        Some((bid * ask_qty + ask * bid_qty) / (bid_qty + ask_qty))
+    }
+
+    /// Return the micro-price, i.e. the mid price weighted by the quantity at
+    /// the best bid and the best ask, or `None` if either side is empty. This
+    /// is a shorthand for [`weighted_mid`] over a single level.
+    ///
+    /// [`weighted_mid`]: #method.weighted_mid
+    pub fn micro_price(&self) -> Option<f64> {
+        self.weighted_mid(1)
+    }
+
+    /// Return the spread, mid price, micro-price, volume imbalance and side
+    /// volumes as a [`Signals`] struct, walking the top `levels` levels of
+    /// each side once. The volumes and the imbalance cover those levels, and
+    /// the imbalance is `None` if they are empty.
+    ///
+    /// [`Signals`]: struct.Signals.html
+    pub fn signals(&self, levels: usize) -> Signals {
+        let aggregate = |side| {
+            let mut top = None;
+            let mut volume = 0.0;
+            for (price, qty) in self.levels(side).take(levels) {
+                top.get_or_insert((price, qty));
+                volume += qty;
+            }
+            (top, volume)
+        };
+        let (top_bid, bid_volume) = aggregate(Side::Bid);
+        let (top_ask, ask_volume) = aggregate(Side::Ask);
+        let spread = self.spread();
+        let mid = self.mid_price();
+        let spread_bps = match (spread, mid) {
+            (Some(spread), Some(mid)) => Some(spread / mid * 10_000.0),
+            _ => None,
+        };
+        let micro_price = match (top_bid, top_ask) {
+            (Some((bid, bid_qty)), Some((ask, ask_qty))) => {
+                Some((bid * ask_qty + ask * bid_qty) / (bid_qty + ask_qty))
+            }
+            _ => None,
+        };
+        let total_volume = bid_volume + ask_volume;
+        let imbalance = if total_volume > 0.0 {
+            Some((bid_volume - ask_volume) / total_volume)
+        } else {
+            None
+        };
+        Signals {
+            spread,
+            spread_bps,
+            mid,
+            micro_price,
+            imbalance,
+            bid_volume,
+            ask_volume,
+        }
+    }
+
+    /// Return the proceeds and the average price of a market order of the given
+    /// side and quantity, if it was executed right now, without executing it.
+    /// Selling `qty` is estimated with [`Side::Ask`], and buying it back with
+    /// [`Side::Bid`].
+    ///
+    /// If the opposite side cannot absorb the full quantity, return the result
+    /// for the part that would be filled. If nothing would be filled, return
+    /// `(0.0, 0.0)`.
+    ///
+    /// [`Side::Ask`]: enum.Side.html#variant.Ask
+    /// [`Side::Bid`]: enum.Side.html#variant.Bid
+    pub fn liquidation_value(&self, side: Side, qty: f64) -> (f64, f64) {
+        let mut remaining_qty = qty;
+        let mut proceeds = 0.0;
+        for (price, level_qty) in self.levels(!side) {
+            if remaining_qty <= 0.0 {
+                break;
+            }
+            let filled_qty = level_qty.min(remaining_qty);
+            proceeds += price * filled_qty;
+            remaining_qty -= filled_qty;
+        }
+        let filled_qty = qty - remaining_qty;
+        if filled_qty > 0.0 {
+            (proceeds, proceeds / filled_qty)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    /// Return the volume-weighted average fill price and the filled quantity
+    /// of a market order of the given side and quantity, if it was executed
+    /// right now, without executing it.
+    ///
+    /// If the opposite side cannot absorb the full quantity, the filled
+    /// quantity is less than `qty`. Return `None` if nothing would be filled.
+    pub fn vwap_for_qty(&self, side: Side, qty: f64) -> Option<(f64, f64)> {
+        let (notional, avg) = self.liquidation_value(side, qty);
+        (notional > 0.0).then(|| (avg, notional / avg))
+    }
+
+    /// Return the worst price a market order of the given side would reach if
+    /// it swept the opposite side until the traded notional (price times
+    /// quantity) reaches `notional`, without executing it. Spending cash is
+    /// estimated with [`Side::Bid`], and raising it with [`Side::Ask`].
+    ///
+    /// Return `None` if the opposite side cannot absorb the full notional.
+    ///
+    /// [`Side::Ask`]: enum.Side.html#variant.Ask
+    /// [`Side::Bid`]: enum.Side.html#variant.Bid
+    pub fn price_for_notional(&self, side: Side, notional: f64) -> Option<f64> {
+        let mut remaining = notional;
+        for (price, qty) in self.levels(!side) {
+            remaining -= price * qty;
+            if remaining <= 0.0 {
+                return Some(price);
+            }
+        }
+        None
+    }
+
+    /// Return the dormant [`StopMarket`] and [`MarketIfTouched`] orders, in
+    /// the order they were placed, as `(id, side, trigger_price, qty)` tuples.
+    /// They are not resting liquidity, so they do not show up in the depth of
+    /// the order book.
+    ///
+    /// [`StopMarket`]: enum.OrderType.html#variant.StopMarket
+    /// [`MarketIfTouched`]: enum.OrderType.html#variant.MarketIfTouched
+    pub fn resting_stops(&self) -> Vec<(u128, Side, f64, f64)> {
+        self.triggers
+            .iter()
+            .map(|order| (order.id, order.side, order.trigger_price, order.qty))
+            .collect()
+    }
+
+    /// Return the IDs of the dormant orders (e.g. [`StopMarket`]) that
+    /// executing the given order right now would trigger, including the ones
+    /// triggered in cascade by the fills of other triggered orders, without
+    /// executing it. Return an empty vector if nothing would be triggered.
+    ///
+    /// The order is executed on a copy of the order book, so the cost of the
+    /// check grows with the size of the book.
+    ///
+    /// [`StopMarket`]: enum.OrderType.html#variant.StopMarket
+    pub fn stops_would_trigger(&self, order: OrderType) -> Vec<u128> {
+        if self.triggers.is_empty() {
+            return Vec::new();
+        }
+        let mut ob = self.clone();
+        ob.execute(order);
+        self.triggers
+            .iter()
+            .map(|trigger| trigger.id)
+            .filter(|id| ob.triggers.iter().all(|dormant| dormant.id != *id))
+            .collect()
+    }
+
     /// Return the difference of the lowest ask and highest bid, if both are
     /// present.
     #[inline(always)]
@@ -103,25 +674,216 @@ impl OrderBook {
         }
     }
 
+    /// Return what the [`spread`] would be after executing the given order,
+    /// leaving the order book unchanged, or `None` if the resulting order book
+    /// would not have both a bid and an ask.
+    ///
+    /// The order is executed on a copy of the order book, like in
+    /// [`stops_would_trigger`], so the price level callbacks are not invoked.
+    ///
+    /// [`spread`]: #method.spread
+    /// [`stops_would_trigger`]: #method.stops_would_trigger
+    pub fn spread_after(&self, order: OrderType) -> Option<f64> {
+        let mut ob = self.clone();
+        ob.execute(order);
+        ob.spread()
+    }
+
+    /// Return `true` if the highest bid is greater than or equal to the lowest
+    /// ask, i.e. the book is locked or crossed.
+    ///
+    /// Continuous matching never leaves the book in this state, but a book
+    /// can be crossed if resting orders are loaded without going through
+    /// matching. Market orders sent to a crossed book are still matched
+    /// deterministically: they sweep the opposite side starting from its best
+    /// price, and resting orders on their own side are left untouched.
+    #[inline(always)]
+    pub fn is_crossed(&self) -> bool {
+        match (self.max_bid, self.min_ask) {
+            (Some(b), Some(a)) => b >= a,
+            _ => false,
+        }
+    }
+
+    /// Return the indicative result of uncrossing the order book in an auction,
+    /// or `None` if the order book is not crossed. Nothing is executed.
+    ///
+    /// The clearing price is the resting price maximizing the quantity that
+    /// would be matched, where bids priced at or above it match asks priced at
+    /// or below it. Ties are broken by the smallest imbalance, then by the
+    /// highest price if bids are in excess and the lowest price otherwise.
+    pub fn auction_info(&self) -> Option<AuctionResult> {
+        if !self.is_crossed() {
+            return None;
+        }
+        let bids: Vec<_> = self.levels(Side::Bid).collect();
+        let asks: Vec<_> = self.levels(Side::Ask).collect();
+        let mut best: Option<AuctionResult> = None;
+        for (price, _) in bids.iter().chain(&asks) {
+            let bid_qty: f64 = bids
+                .iter()
+                .filter(|(p, _)| p >= price)
+                .map(|(_, qty)| qty)
+                .sum();
+            let ask_qty: f64 = asks
+                .iter()
+                .filter(|(p, _)| p <= price)
+                .map(|(_, qty)| qty)
+                .sum();
+            let imbalance_side = if bid_qty > ask_qty {
+                Some(Side::Bid)
+            } else if ask_qty > bid_qty {
+                Some(Side::Ask)
+            } else {
+                None
+            };
+            let candidate = AuctionResult {
+                price: *price,
+                matched_qty: bid_qty.min(ask_qty),
+                imbalance_qty: (bid_qty - ask_qty).abs(),
+                imbalance_side,
+            };
+            let rank = |r: &AuctionResult| (r.matched_qty, -r.imbalance_qty);
+            let better = match &best {
+                None => true,
+                Some(best) => match rank(&candidate).partial_cmp(&rank(best)) {
+                    Some(Ordering::Greater) => true,
+                    Some(Ordering::Equal) => match imbalance_side {
+                        Some(Side::Bid) => candidate.price > best.price,
+                        _ => candidate.price < best.price,
+                    },
+                    _ => false,
+                },
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+        best
+    }
+
     /// Return the last trade recorded while stats tracking was active as a
-    /// [`Trade`] object, if present.
+    /// [`Trade`] object, if present, with its quantities rounded to the
+    /// display precision and its prices rounded if enabled (see
+    /// [`set_display_precision`] and [`round_trade_prices`]).
     ///
     /// [`Trade`]: struct.Trade.html
+    /// [`set_display_precision`]: #method.set_display_precision
+    /// [`round_trade_prices`]: #method.round_trade_prices
     #[inline(always)]
     pub fn last_trade(&self) -> Option<Trade> {
-        self.last_trade
+        let side = self.last_taker_side;
+        self.last_trade.map(|trade| Trade {
+            total_qty: self.display_qty(trade.total_qty),
+            avg_price: self.trade_price(side, trade.avg_price),
+            last_price: self.trade_price(side, trade.last_price),
+            last_qty: self.display_qty(trade.last_qty),
+        })
     }
 
     /// Return the total traded volume for all the trades that occurred while
-    /// the stats tracking was active.
+    /// the stats tracking was active, rounded to the display precision (see
+    /// [`set_display_precision`]).
+    ///
+    /// [`set_display_precision`]: #method.set_display_precision
     #[inline(always)]
     pub fn traded_volume(&self) -> f64 {
-        self.traded_volume
+        self.display_qty(self.traded_volume)
+    }
+
+    /// Return the statistics of the trades recorded while the stats tracking
+    /// was active as a [`SessionSummary`] struct, from the maintained counters
+    /// and without walking the order book. They are reset by
+    /// [`reset_stats`].
+    ///
+    /// [`SessionSummary`]: struct.SessionSummary.html
+    /// [`reset_stats`]: #method.reset_stats
+    pub fn session_summary(&self) -> SessionSummary {
+        let session = &self.session;
+        let volume = session.buy_volume + session.sell_volume;
+        SessionSummary {
+            traded_volume: self.traded_volume,
+            order_count: session.orders,
+            fill_count: session.fills,
+            high: session.high,
+            low: session.low,
+            vwap: match volume > 0.0 {
+                true => Some(session.notional / volume),
+                false => None,
+            },
+            buy_volume: session.buy_volume,
+            sell_volume: session.sell_volume,
+        }
+    }
+
+    /// Return the realized volatility of the last `n` trades recorded while
+    /// the stats tracking was active, computed as the sample standard
+    /// deviation of the log returns between their prices. Each fill counts as
+    /// a trade, and up to the last 1,000 trades are kept.
+    ///
+    /// Return `None` if `n` is lower than 3 or fewer than `n` trades were
+    /// recorded.
+    pub fn realized_vol(&self, n: usize) -> Option<f64> {
+        if n < 3 || n > self.trade_prices.len() {
+            return None;
+        }
+        let prices = self.trade_prices.range(self.trade_prices.len() - n..);
+        let returns: Vec<f64> = prices
+            .clone()
+            .zip(prices.skip(1))
+            .map(|(prev, next)| (next / prev).ln())
+            .collect();
+        let count = returns.len() as f64;
+        let mean = returns.iter().sum::<f64>() / count;
+        let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+            / (count - 1.0);
+        Some(var.sqrt())
+    }
+
+    /// Return the total quantity consumed from the front of the price level
+    /// of the given side by incoming orders while the stats tracking was
+    /// active, or `0.0` if the level was never hit. Sampling this value over
+    /// time gives the depletion rate of the level queue.
+    pub fn depletion(&self, side: Side, price: f64) -> f64 {
+        let depletion = match side {
+            Side::Bid => &self.bid_depletion,
+            Side::Ask => &self.ask_depletion,
+        };
+        depletion
+            .get(&self.price_key(side, price))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Return a rough estimate of the probability that a resting order is
+    /// filled entirely, from its position in the queue and the [`depletion`]
+    /// of its price level.
+    ///
+    /// The quantity consumed from the level before the order leaves is modelled
+    /// as exponentially distributed, with a mean equal to the depletion seen so
+    /// far, so the estimate is `exp(-(qty_ahead + qty) / depletion)`. It
+    /// ignores cancels ahead of the order and assumes the level keeps being
+    /// depleted as it was: it is a research convenience, not a forecast.
+    ///
+    /// Return `None` if the order is not resting on the order book, or if its
+    /// level has no depletion data, e.g. when the stats tracking is disabled.
+    ///
+    /// [`depletion`]: #method.depletion
+    pub fn fill_probability(&self, id: u128) -> Option<f64> {
+        let (_, qty_ahead) = self.queue_position(id)?;
+        let (price, idx) = self.arena.get(id)?;
+        let order = &self.arena[idx];
+        let depletion = self.depletion(order.side, price);
+        if order.qty <= 0.0 || depletion <= 0.0 {
+            return None;
+        }
+        Some((-(qty_ahead + order.qty) / depletion).exp())
     }
 
     /// Return the order book depth as a [`BookDepth`] struct, up to the
     /// specified level. Bids and offers at the same price level are merged in a
-    /// single [`BookLevel`] struct.
+    /// single [`BookLevel`] struct. At most `levels` non-empty price levels are
+    /// returned per side, best price first.
     ///
     /// [`BookDepth`]: struct.BookDepth.html
     /// [`BookLevel`]: struct.BookLevel.html
@@ -130,8 +892,11 @@ impl OrderBook {
         let mut bids: Vec<BookLevel> = Vec::with_capacity(levels);
 
         for (vect_ask_price, queue) in self.asks.iter() {
+            if asks.len() == levels {
+                break;
+            }
             let mut qty = 0.0;
-            let ask_price = (*vect_ask_price as f64) / self.precision;
+            let ask_price = (*vect_ask_price as f64) / self.ask_precision;
 
             for idx in queue {
                 qty += self.arena[*idx].qty;
@@ -139,14 +904,17 @@ impl OrderBook {
             if qty > 0.0 {
                 asks.push(BookLevel {
                     price: ask_price,
-                    qty,
+                    qty: self.display_qty(qty),
                 });
             }
         }
 
-        for (vect_bid_price, queue) in self.bids.iter() {
+        for (vect_bid_price, queue) in self.bids.iter().rev() {
+            if bids.len() == levels {
+                break;
+            }
             let mut qty = 0.0;
-            let bid_price = (*vect_bid_price as f64) / self.precision;
+            let bid_price = (*vect_bid_price as f64) / self.bid_precision;
 
             for idx in queue {
                 qty += self.arena[*idx].qty;
@@ -154,7 +922,7 @@ impl OrderBook {
             if qty > 0.0 {
                 bids.push(BookLevel {
                     price: bid_price,
-                    qty,
+                    qty: self.display_qty(qty),
                 });
             }
         }
@@ -162,618 +930,6966 @@ impl OrderBook {
         BookDepth { levels, asks, bids }
     }
 
-    /// Toggle the stats tracking on or off, depending on the `track` parameter.
-    pub fn track_stats(&mut self, track: bool) {
-        self.track_stats = track;
+    /// Return the non-empty price levels of the given side, best price first,
+    /// up to the first one at which the cumulative resting quantity reaches
+    /// `target_qty`. The last level is reported with its full resting quantity,
+    /// even if only part of it is needed. If the side holds less than
+    /// `target_qty`, all its levels are returned.
+    ///
+    /// Unlike [`depth`], the number of levels depends on their volume.
+    ///
+    /// [`depth`]: #method.depth
+    pub fn depth_until_qty(
+        &self,
+        side: Side,
+        target_qty: f64,
+    ) -> Vec<BookLevel> {
+        let mut cumulative = 0.0;
+        let mut levels = Vec::new();
+        for (price, qty) in self.levels(side) {
+            if cumulative >= target_qty {
+                break;
+            }
+            cumulative += qty;
+            levels.push(BookLevel {
+                price,
+                qty: self.display_qty(qty),
+            });
+        }
+        levels
     }
 
-    /// Execute an order, returning immediately an event indicating the result.
-    pub fn execute(&mut self, event: OrderType) -> OrderEvent {
-        let event = self._execute(event);
-        if !self.track_stats {
-            return event;
-        }
+    /// Return the top `N` non-empty price levels of the ask and the bid side,
+    /// in this order, best price first, without allocating. The entries past
+    /// the last level of a side are `None`.
+    pub fn depth_n<const N: usize>(
+        &self,
+    ) -> ([Option<BookLevel>; N], [Option<BookLevel>; N]) {
+        (
+            self.top_levels(self.asks.iter(), self.ask_precision),
+            self.top_levels(self.bids.iter().rev(), self.bid_precision),
+        )
+    }
 
-        match event.clone() {
-            OrderEvent::Filled {
-                id: _,
-                filled_qty,
-                fills,
-            } => {
-                self.traded_volume += filled_qty;
-                // If we are here, fills is not empty, so it's safe to unwrap it
-                let last_fill = fills.last().unwrap();
-                self.last_trade = Some(Trade {
-                    total_qty: filled_qty,
-                    avg_price: fills
-                        .iter()
-                        .map(|fm| fm.price * fm.qty)
-                        .sum::<f64>() / filled_qty,
-                    last_qty: last_fill.qty,
-                    last_price: last_fill.price,
-                });
-            }
-            OrderEvent::PartiallyFilled {
-                id: _,
-                filled_qty,
-                fills,
-            } => {
-                self.traded_volume += filled_qty;
-                // If we are here, fills is not empty, so it's safe to unwrap it
-                let last_fill = fills.last().unwrap();
-                self.last_trade = Some(Trade {
-                    total_qty: filled_qty,
-                    avg_price: fills
-                        .iter()
-                        .map(|fm| fm.price * fm.qty)
-                        .sum::<f64>() / filled_qty,
-                    last_qty: last_fill.qty,
-                    last_price: last_fill.price,
-                });
+    /// Collect the first `N` non-empty price levels yielded by `queues`.
+    fn top_levels<'a, const N: usize>(
+        &self,
+        queues: impl Iterator<Item = (&'a u64, &'a Vec<usize>)>,
+        precision: f64,
+    ) -> [Option<BookLevel>; N] {
+        let mut levels = [None; N];
+        let non_empty = queues.filter_map(|(key, queue)| {
+            let qty: f64 = queue.iter().map(|idx| self.arena[*idx].qty).sum();
+            match qty > 0.0 {
+                true => Some(BookLevel {
+                    price: (*key as f64) / precision,
+                    qty: self.display_qty(qty),
+                }),
+                false => None,
             }
-            _ => {}
+        });
+        for (slot, level) in levels.iter_mut().zip(non_empty) {
+            *slot = Some(level);
         }
-        event
+        levels
     }
 
-    fn _execute(&mut self, event: OrderType) -> OrderEvent {
-        match event {
-            OrderType::Market { id, side, qty } => {
-                let (fills, partial, filled_qty) = self.market(id, side, qty);
-                if fills.is_empty() {
-                    OrderEvent::Unfilled { id }
-                } else {
-                    match partial {
-                        false => OrderEvent::Filled {
-                            id,
-                            filled_qty,
-                            fills,
-                        },
-                        true => OrderEvent::PartiallyFilled {
-                            id,
-                            filled_qty,
-                            fills,
-                        },
-                    }
+    /// Return the cumulative depth of the order book up to the specified level,
+    /// as `(price, cumulative_qty)` pairs for the ask and the bid side, in this
+    /// order. Asks accumulate from the lowest price upwards and bids from the
+    /// highest price downwards, which is what a market depth chart plots.
+    pub fn cumulative_depth(&self, levels: usize) -> CumulativeDepth {
+        let cumulate = |side| {
+            let mut total = 0.0;
+            self.levels(side)
+                .take(levels)
+                .map(|(price, qty)| {
+                    total += qty;
+                    (price, self.display_qty(total))
+                })
+                .collect()
+        };
+        (cumulate(Side::Ask), cumulate(Side::Bid))
+    }
+
+    /// Return the cumulative notional value of the order book up to the
+    /// specified level, as `(price, cumulative_notional)` pairs for the ask and
+    /// the bid side, in this order. The notional value of a level is its price
+    /// times its aggregate quantity, accumulated from the best price like in
+    /// [`cumulative_depth`].
+    ///
+    /// [`cumulative_depth`]: #method.cumulative_depth
+    pub fn notional_depth(&self, levels: usize) -> CumulativeDepth {
+        let cumulate = |side| {
+            let mut total = 0.0;
+            self.levels(side)
+                .take(levels)
+                .map(|(price, qty)| {
+                    total += price * qty;
+                    (price, total)
+                })
+                .collect()
+        };
+        (cumulate(Side::Ask), cumulate(Side::Bid))
+    }
+
+    /// Return up to `levels` non-empty price levels of the given side, best
+    /// price first, as `(price, total_qty, order_count)` tuples.
+    pub fn level_stats(
+        &self,
+        side: Side,
+        levels: usize,
+    ) -> Vec<(f64, f64, usize)> {
+        let precision = self.precision(side);
+        self.queues(side)
+            .filter_map(|(key, queue)| {
+                let (qty, count) = queue
+                    .iter()
+                    .map(|idx| self.arena[*idx].qty)
+                    .filter(|qty| *qty > 0.0)
+                    .fold((0.0, 0), |(total, count), qty| {
+                        (total + qty, count + 1)
+                    });
+                match count {
+                    0 => None,
+                    _ => Some((
+                        (*key as f64) / precision,
+                        self.display_qty(qty),
+                        count,
+                    )),
                 }
-            }
-            OrderType::Limit {
-                id,
-                side,
-                qty,
-                price,
-            } => {
-                let (fills, partial, filled_qty) =
-                    self.limit(id, side, qty, price);
-                if fills.is_empty() {
-                    OrderEvent::Placed { id }
-                } else {
-                    match partial {
-                        false => OrderEvent::Filled {
-                            id,
-                            filled_qty,
-                            fills,
-                        },
-                        true => OrderEvent::PartiallyFilled {
-                            id,
-                            filled_qty,
-                            fills,
-                        },
-                    }
-                }
-            }
-            OrderType::Cancel { id } => {
-                self.cancel(id);
-                OrderEvent::Canceled { id }
-            }
-        }
+            })
+            .take(levels)
+            .collect()
     }
 
-    fn cancel(&mut self, id: u128) -> bool {
-        if let Some((price, idx)) = self.arena.get(id) {
-            let vect_price = (self.precision * price) as u64;
-            if let Some(ref mut queue) = self.asks.get_mut(&vect_price) {
-                if let Some(i) = queue.iter().position(|i| *i == idx) {
-                    queue.remove(i);
-                }
-                self.update_min_ask();
-            }
-            if let Some(ref mut queue) = self.bids.get_mut(&vect_price) {
-                if let Some(i) = queue.iter().position(|i| *i == idx) {
-                    queue.remove(i);
+    /// Return a deterministic dump of all the resting orders, one per line as
+    /// `<side> <price> <id> <qty>`. Asks come first and bids second, each side
+    /// in priority order (best price first, then time priority).
+    ///
+    /// The dump does not depend on internal details like the arena indices or
+    /// capacity, so it is suitable for snapshot (golden) tests.
+    pub fn canonical_string(&self) -> String {
+        let mut dump = String::new();
+        for side in &[Side::Ask, Side::Bid] {
+            let precision = self.precision(*side);
+            for (key, queue) in self.queues(*side) {
+                let price = (*key as f64) / precision;
+                for idx in queue {
+                    let order = &self.arena[*idx];
+                    let (id, qty) = (order.id, order.qty);
+                    writeln!(dump, "{:?} {} {} {}", side, price, id, qty)
+                        .unwrap();
                 }
-                self.update_max_bid();
             }
         }
-        self.arena.delete(&id)
+        dump
     }
 
-    fn market(
-        &mut self,
-        id: u128,
-        side: Side,
-        qty: f64,
-    ) -> (Vec<FillMetadata>, bool, f64) {
-        let mut partial = false;
-        let remaining_qty: f64;
-        let mut fills = Vec::new();
+    /// Return the total resting quantity of the price level of the given side
+    /// that `price` maps to, or `0.0` if there is none.
+    pub fn qty_at_price(&self, side: Side, price: f64) -> f64 {
+        self.level_qty(side, self.price_key(side, price))
+    }
 
-        match side {
-            Side::Bid => {
-                remaining_qty = self.match_with_asks(id, qty, &mut fills, None);
-                if remaining_qty > 0.0 {
-                    partial = true;
-                }
-            }
-            Side::Ask => {
-                remaining_qty = self.match_with_bids(id, qty, &mut fills, None);
-                if remaining_qty > 0.0 {
-                    partial = true;
-                }
-            }
-        }
+    /// Return the number of orders resting on the given side, not counting
+    /// the filled ones still lingering in their queue.
+    pub fn order_count(&self, side: Side) -> usize {
+        self.queues(side)
+            .flat_map(|(_, queue)| queue.iter())
+            .filter(|idx| self.arena[**idx].qty > 0.0)
+            .count()
+    }
 
-        (fills, partial, (((qty - remaining_qty) * self.precision) as u64) as f64 / self.precision)
+    /// Return the total quantity resting on the given side, i.e. the open
+    /// interest of its orders, or `0.0` if there is none. Unlike
+    /// [`traded_volume`], it does not count the executed quantity.
+    ///
+    /// [`traded_volume`]: #method.traded_volume
+    pub fn resting_volume(&self, side: Side) -> f64 {
+        self.queues(side)
+            .flat_map(|(_, queue)| queue.iter())
+            .map(|idx| self.arena[*idx].qty)
+            .sum()
     }
 
-    fn limit(
-        &mut self,
-        id: u128,
-        side: Side,
-        qty: f64,
-        price: f64,
-    ) -> (Vec<FillMetadata>, bool, f64) {
-        let mut partial = false;
-        let remaining_qty: f64;
-        let mut fills: Vec<FillMetadata> = Vec::new();
+    /// Return the number of non-empty price levels on the given side.
+    pub fn price_level_count(&self, side: Side) -> usize {
+        self.queues(side)
+            .filter(|(_, queue)| {
+                queue.iter().any(|idx| self.arena[*idx].qty > 0.0)
+            })
+            .count()
+    }
 
-        match side {
-            Side::Bid => {
-                remaining_qty =
-                    self.match_with_asks(id, qty, &mut fills, Some(price));
-                if remaining_qty > 0.0 {
-                    partial = true;
-                    let index = self.arena.insert(id, price, remaining_qty);
-                    let queue_capacity = self.default_queue_capacity;
-                    let vect_price = (self.precision * price) as u64;
-                    self.bids
-                        .entry(vect_price)
-                        .or_insert_with(|| Vec::with_capacity(queue_capacity))
-                        .push(index);
-                    match self.max_bid {
-                        None => {
-                            self.max_bid = Some(price);
-                        }
-                        Some(b) if price > b => {
-                            self.max_bid = Some(price);
-                        }
-                        _ => {}
-                    };
-                }
-            }
-            Side::Ask => {
-                remaining_qty =
-                    self.match_with_bids(id, qty, &mut fills, Some(price));
-                if remaining_qty > 0.0 {
-                    partial = true;
-                    let index = self.arena.insert(id, price, remaining_qty);
-                    if let Some(a) = self.min_ask {
-                        if price < a {
-                            self.min_ask = Some(price);
-                        }
-                    }
-                    let queue_capacity = self.default_queue_capacity;
-                    let vect_price = (self.precision * price) as u64;
-                    self.asks
-                        .entry(vect_price)
-                        .or_insert_with(|| Vec::with_capacity(queue_capacity))
-                        .push(index);
-                    match self.min_ask {
-                        None => {
-                            self.min_ask = Some(price);
-                        }
-                        Some(a) if price < a => {
-                            self.min_ask = Some(price);
-                        }
-                        _ => {}
-                    };
-                }
-            }
+    /// Return the total resting quantity of all the levels on the given side
+    /// whose price falls within `[low, high]`, or `0.0` if there are none.
+    /// Bounds off the price grid only include the levels within them.
+    pub fn volume_in_range(&self, side: Side, low: f64, high: f64) -> f64 {
+        let low = self.price_key_ceil(side, low);
+        let high = self.price_key(side, high);
+        if low > high {
+            return 0.0;
         }
+        let book = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        book.range(low..=high)
+            .flat_map(|(_, queue)| queue.iter())
+            .map(|idx| self.arena[*idx].qty)
+            .sum()
+    }
 
-        (fills, partial, (((qty - remaining_qty) * self.precision) as u64) as f64 / self.precision)
+    /// Return the total resting quantity of the asks and the bids whose price
+    /// is within `bps` basis points of the mid price, or `(0.0, 0.0)` if either
+    /// side is empty. Unlike [`volume_in_range`], the band is relative to the
+    /// mid price, so the result is comparable across instruments.
+    ///
+    /// [`volume_in_range`]: #method.volume_in_range
+    pub fn liquidity_within_bps(&self, bps: f64) -> (f64, f64) {
+        let mid = match self.mid_price() {
+            Some(mid) => mid,
+            None => return (0.0, 0.0),
+        };
+        let band = mid * bps / 10_000.0;
+        (
+            self.volume_in_range(Side::Ask, mid, mid + band),
+            self.volume_in_range(Side::Bid, mid - band, mid),
+        )
     }
 
-    fn match_with_asks(
-        &mut self,
-        id: u128,
-        qty: f64,
-        fills: &mut Vec<FillMetadata>,
-        limit_price: Option<f64>,
-    ) -> f64 {
-        let mut remaining_qty = qty;
-        let mut update_bid_ask = false;
-        for (vect_ask_price, queue) in self.asks.iter_mut() {
-            let ask_price = (*vect_ask_price as f64) / self.precision;
-            if queue.is_empty() {
-                continue;
-            }
-            if (update_bid_ask || self.min_ask.is_none()) && !queue.is_empty() {
-                self.min_ask = Some(ask_price);
-                update_bid_ask = false;
-            }
-            if let Some(lp) = limit_price {
-                if lp < ask_price {
-                    break;
-                }
-            }
-            if remaining_qty == 0.0 {
-                break;
-            }
-            let filled_qty = Self::process_queue(
-                &mut self.arena,
-                queue,
-                remaining_qty,
-                id,
-                Side::Bid,
-                fills,
-            );
-            if queue.is_empty() {
-                update_bid_ask = true;
-            }
-            remaining_qty -= filled_qty;
+    /// Return the individual resting orders of the given side whose price
+    /// falls within `[low, high]`, in priority order (best price first, then
+    /// time priority), or an empty vector if there are none. The filled orders
+    /// still lingering in their queue are skipped.
+    pub fn l3_in_range(
+        &self,
+        side: Side,
+        low: f64,
+        high: f64,
+    ) -> Vec<LimitOrder> {
+        let low = self.price_key_ceil(side, low);
+        let high = self.price_key(side, high);
+        if low > high {
+            return Vec::new();
         }
+        let levels: Box<dyn Iterator<Item = (&u64, &Vec<usize>)>> =
+            match side {
+                Side::Bid => Box::new(self.bids.range(low..=high).rev()),
+                Side::Ask => Box::new(self.asks.range(low..=high)),
+            };
+        levels
+            .flat_map(|(_, queue)| queue.iter())
+            .map(|idx| &self.arena[*idx])
+            .filter(|order| order.qty > 0.0)
+            .cloned()
+            .collect()
+    }
 
-        self.update_min_ask();
-        remaining_qty
+    /// Iterate over the orders resting on the given side in priority order
+    /// (best price first, then time priority), i.e. the order in which they
+    /// would be matched, skipping the filled ones still lingering in their
+    /// queue.
+    pub fn iter_orders(
+        &self,
+        side: Side,
+    ) -> impl Iterator<Item = RestingOrder> + '_ {
+        self.queues(side)
+            .flat_map(|(_, queue)| queue.iter())
+            .map(move |idx| &self.arena[*idx])
+            .filter(|order| order.qty > 0.0)
+            .map(|order| RestingOrder {
+                id: order.id,
+                side: order.side,
+                price: order.price,
+                qty: order.qty,
+            })
     }
 
-    fn match_with_bids(
-        &mut self,
-        id: u128,
-        qty: f64,
-        fills: &mut Vec<FillMetadata>,
-        limit_price: Option<f64>,
-    ) -> f64 {
-        let mut remaining_qty = qty;
-        let mut update_bid_ask = false;
-        for (vect_bid_price, queue) in self.bids.iter_mut().rev() {
-            let bid_price = (*vect_bid_price as f64) / self.precision;
-            if queue.is_empty() {
-                continue;
-            }
-            if (update_bid_ask || self.max_bid.is_none()) && !queue.is_empty() {
-                self.max_bid = Some(bid_price);
-                update_bid_ask = false;
-            }
-            if let Some(lp) = limit_price {
-                if lp > bid_price {
-                    break;
-                }
-            }
-            if remaining_qty == 0.0 {
-                break;
-            }
-            let filled_qty = Self::process_queue(
-                &mut self.arena,
-                queue,
-                remaining_qty,
-                id,
-                Side::Ask,
-                fills,
-            );
-            if queue.is_empty() {
-                update_bid_ask = true;
-            }
-            remaining_qty -= filled_qty;
-        }
+    /// Toggle the stats tracking on or off, depending on the `track` parameter.
+    pub fn track_stats(&mut self, track: bool) {
+        self.track_stats = track;
+    }
 
-        self.update_max_bid();
-        remaining_qty
+    /// Reset the traded volume to `0.0` and forget the last trade, e.g. to
+    /// measure the volume of each interval of a rolling window. The session
+    /// summary and the self-trade prevention counters are reset too, while the
+    /// resting orders are left untouched.
+    pub fn reset_stats(&mut self) {
+        self.traded_volume = 0.0;
+        self.last_trade = None;
+        self.session = SessionStats::default();
+        self.self_match_count = 0;
+        self.stp_canceled.clear();
     }
 
-    fn update_min_ask(&mut self) {
-        let mut cur_asks = self.asks.iter().filter(|(_, q)| !q.is_empty());
-        self.min_ask = match cur_asks.next() {
-            None => None,
-            Some((p, _)) => Some((*p as f64) / self.precision),
-        };
+    /// Toggle the recording of all the fills on a tape on or off, depending
+    /// on the `record` parameter (see [`tape`] and [`fills_for`]). Disabled
+    /// by default, as the tape grows with every fill.
+    ///
+    /// [`tape`]: #method.tape
+    /// [`fills_for`]: #method.fills_for
+    pub fn record_tape(&mut self, record: bool) {
+        self.record_tape = record;
     }
 
-    fn update_max_bid(&mut self) {
-        let mut cur_bids =
-            self.bids.iter().rev().filter(|(_, q)| !q.is_empty());
-        self.max_bid = match cur_bids.next() {
-            None => None,
-            Some((p, _)) => Some((*p as f64) / self.precision),
-        };
+    /// Toggle the aggregation of the fills reported for an order, depending on
+    /// the `aggregate` parameter. Disabled by default, so that every matched
+    /// maker order gets its own fill.
+    ///
+    /// When enabled, the consecutive fills at the same price are merged into a
+    /// single fill with their summed quantity. Its `order_2` is the first maker
+    /// order matched at that price, and `total_fill` refers to the last one,
+    /// as all the others were fully filled. The trade count still counts every
+    /// matched maker order.
+    pub fn aggregate_fills(&mut self, aggregate: bool) {
+        self.aggregate_fills = aggregate;
     }
 
-    fn process_queue(
-        arena: &mut OrderArena,
-        opposite_orders: &mut Vec<usize>,
-        remaining_qty: f64,
-        id: u128,
-        side: Side,
-        fills: &mut Vec<FillMetadata>,
-    ) -> f64 {
-        let mut qty_to_fill = remaining_qty;
-        let mut filled_qty: f64 = 0.0;
-        let mut filled_index = None;
+    /// Keep the last `capacity` events produced by the order book (see
+    /// [`recent_events`]), or disable the buffer if `capacity` is 0, which is
+    /// the default. The oldest events are dropped when the capacity is
+    /// lowered.
+    ///
+    /// [`recent_events`]: #method.recent_events
+    pub fn record_recent_events(&mut self, capacity: usize) {
+        self.recent_events_capacity = capacity;
+        self.truncate_recent_events();
+    }
 
-        for (index, head_order_idx) in opposite_orders.iter_mut().enumerate() {
-            if qty_to_fill == 0.0 {
-                break;
-            }
-            let head_order = &mut arena[*head_order_idx];
-            let traded_price = head_order.price;
-            let available_qty = head_order.qty;
-            if available_qty == 0.0 {
-                filled_index = Some(index);
-                continue;
-            }
-            let traded_quantity: f64;
-            let filled;
+    /// Return the last events produced by the order book, from the oldest to
+    /// the most recent, if the buffer is enabled (see
+    /// [`record_recent_events`]).
+    ///
+    /// Unlike the tape, the buffer holds every event: the ones returned by
+    /// [`execute`], including rejections, and the ones queued for other
+    /// orders (see [`drain_events`]). The events queued while an order is
+    /// executed come before the order's own event, except for the events of
+    /// the orders it triggers, which come after it.
+    ///
+    /// [`record_recent_events`]: #method.record_recent_events
+    /// [`execute`]: #method.execute
+    /// [`drain_events`]: #method.drain_events
+    pub fn recent_events(&self) -> impl Iterator<Item = &OrderEvent> {
+        self.recent_events.iter()
+    }
 
-            if qty_to_fill >= available_qty {
-                traded_quantity = available_qty;
-                qty_to_fill -= available_qty;
-                filled_index = Some(index);
-                filled = true;
-            } else {
-                traded_quantity = qty_to_fill;
-                qty_to_fill = 0.0;
-                filled = false;
+    /// Remember the IDs of the last `capacity` resting orders fully filled as
+    /// makers, so that [`order_status`] reports them as filled rather than
+    /// unknown for a while, or disable it if `capacity` is 0, which is the
+    /// default. The oldest IDs are forgotten when the capacity is lowered.
+    ///
+    /// [`order_status`]: #method.order_status
+    pub fn record_filled_orders(&mut self, capacity: usize) {
+        self.filled_orders_capacity = capacity;
+        self.truncate_filled_orders();
+    }
+
+    /// Return the status of an order: [`OrderStatus::Resting`] if it rests on
+    /// the order book, [`OrderStatus::Filled`] if it was fully filled as a
+    /// maker order and is still among the last filled orders remembered (see
+    /// [`record_filled_orders`]), or `None` if the order is unknown, e.g.
+    /// canceled, filled too long ago, or never placed.
+    ///
+    /// [`OrderStatus::Resting`]: enum.OrderStatus.html#variant.Resting
+    /// [`OrderStatus::Filled`]: enum.OrderStatus.html#variant.Filled
+    /// [`record_filled_orders`]: #method.record_filled_orders
+    pub fn order_status(&self, id: u128) -> Option<OrderStatus> {
+        let (_, idx) = self.arena.get(id)?;
+        match self.arena[idx].qty {
+            qty if qty > 0.0 => Some(OrderStatus::Resting { qty }),
+            _ if self.filled_orders.contains(&id) => Some(OrderStatus::Filled),
+            _ => None,
+        }
+    }
+
+    /// Remember the makers fully filled by the given fills, which are left in
+    /// the arena with no quantity.
+    fn record_filled(&mut self, fills: &[FillMetadata]) {
+        if self.filled_orders_capacity == 0 {
+            return;
+        }
+        for fill in fills.iter().filter(|fill| fill.total_fill) {
+            if matches!(
+                self.arena.get(fill.order_2),
+                Some((_, idx)) if self.arena[idx].qty == 0.0
+            ) {
+                self.filled_orders.push_back(fill.order_2);
             }
-            head_order.qty -= traded_quantity;
-            let fill: FillMetadata;
-            fill = FillMetadata {
-                order_1: id,
-                order_2: head_order.id,
-                qty: traded_quantity,
-                price: traded_price,
-                taker_side: side,
-                total_fill: filled,
-            };
-            fills.push(fill);
-            filled_qty += traded_quantity;
         }
-        if let Some(index) = filled_index {
-            opposite_orders.drain(0..index + 1);
+        self.truncate_filled_orders();
+    }
+
+    fn truncate_filled_orders(&mut self) {
+        while self.filled_orders.len() > self.filled_orders_capacity {
+            self.filled_orders.pop_front();
         }
+    }
 
-        filled_qty
+    /// Return all the fills recorded while the tape was enabled, in the order
+    /// they occurred.
+    pub fn tape(&self) -> &[FillMetadata] {
+        &self.tape
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::{
-        BookDepth, BookLevel, FillMetadata, OrderBook, OrderEvent, OrderType,
-        Side, Trade,
-    };
-    use std::collections::BTreeMap;
+    /// Return the fills recorded on the tape where the order with the given
+    /// ID was either the taker or the maker, in the order they occurred, or an
+    /// empty vector if it never traded while the tape was enabled.
+    pub fn fills_for(&self, id: u128) -> Vec<FillMetadata> {
+        self.tape
+            .iter()
+            .filter(|fill| fill.order_1 == id || fill.order_2 == id)
+            .copied()
+            .collect()
+    }
 
-    const DEFAULT_QUEUE_SIZE: usize = 10;
-    const BID_ASK_COMBINATIONS: [(Side, Side); 2] =
-        [(Side::Bid, Side::Ask), (Side::Ask, Side::Bid)];
+    /// Set the number of decimal digits the quantities reported by [`depth`],
+    /// [`cumulative_depth`], [`last_trade`] and [`traded_volume`] are rounded
+    /// to, or `None` (the default) to report them unrounded. This only affects
+    /// reporting: matching and the stats always use the full-precision
+    /// quantities, so changing the precision applies to past trades as well.
+    ///
+    /// [`depth`]: #method.depth
+    /// [`cumulative_depth`]: #method.cumulative_depth
+    /// [`last_trade`]: #method.last_trade
+    /// [`traded_volume`]: #method.traded_volume
+    pub fn set_display_precision(&mut self, precision: Option<u128>) {
+        self.display_precision = precision.map(|p| 10_f64.powf(p as f64));
+    }
 
-    // In general, floating point values cannot be compared for equality. That's
-    // why we don't derive PartialEq in lobster::models, but we do it here for
-    // our tests in some very specific cases.
-    impl PartialEq for Trade {
-        fn eq(&self, other: &Self) -> bool {
-            self.total_qty == other.total_qty
-                && (self.avg_price - other.avg_price).abs() < 1.0e-6
-                && self.last_qty == other.last_qty
-                && self.last_price == other.last_price
+    /// Toggle the rounding of the prices reported by [`last_trade`] to the
+    /// price precision of the side the maker orders rested on, depending on
+    /// the `round` parameter. Disabled by default, so that the average price
+    /// keeps the float error of its computation.
+    ///
+    /// [`last_trade`]: #method.last_trade
+    pub fn round_trade_prices(&mut self, round: bool) {
+        self.round_trade_prices = round;
+    }
+
+    /// Set the tick size used to resolve the price of
+    /// [`OrderType::RelativeLimit`] orders, or `None` (the default) to use the
+    /// smallest price increment allowed by the precision of the order side.
+    ///
+    /// [`OrderType::RelativeLimit`]: enum.OrderType.html#variant.RelativeLimit
+    pub fn set_tick_size(&mut self, tick_size: Option<f64>) {
+        self.tick_size = tick_size;
+    }
+
+    /// Freeze the order book, modeling a trading halt: until [`unfreeze`] is
+    /// called, every order sent to [`execute`] is rejected with
+    /// [`RejectReason::Frozen`], while the read methods keep working. Cancels
+    /// are rejected too, unless allowed with [`allow_cancel_while_frozen`].
+    ///
+    /// [`unfreeze`]: #method.unfreeze
+    /// [`execute`]: #method.execute
+    /// [`RejectReason::Frozen`]: enum.RejectReason.html#variant.Frozen
+    /// [`allow_cancel_while_frozen`]: #method.allow_cancel_while_frozen
+    pub fn freeze(&mut self) {
+        if !self.frozen {
+            self.frozen = true;
+            self.version += 1;
         }
     }
 
-    fn init_ob(events: Vec<OrderType>) -> (OrderBook, Vec<OrderEvent>) {
-        let mut ob = OrderBook::default();
-        ob.track_stats(true);
-        let mut results = Vec::new();
-        for e in events {
-            results.push(ob.execute(e));
+    /// Resume accepting orders after a [`freeze`].
+    ///
+    /// [`freeze`]: #method.freeze
+    pub fn unfreeze(&mut self) {
+        if self.frozen {
+            self.frozen = false;
+            self.version += 1;
         }
-        (ob, results)
     }
 
-    fn init_book(orders: Vec<(u64, usize)>) -> BTreeMap<u64, Vec<usize>> {
-        let mut bk = BTreeMap::new();
-        for (p, i) in orders {
-            bk.entry(p)
-                .or_insert_with(|| Vec::with_capacity(DEFAULT_QUEUE_SIZE))
-                .push(i);
+    /// Return `true` if the order book is frozen.
+    #[inline(always)]
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Toggle whether cancel orders are accepted while the order book is
+    /// frozen, depending on the `allow` parameter. Disabled by default.
+    pub fn allow_cancel_while_frozen(&mut self, allow: bool) {
+        self.cancel_while_frozen = allow;
+    }
+
+    /// Toggle whether an incoming order priced exactly at the price of the
+    /// orders pegged to the mid (see [`OrderType::MidpointPeg`]) is matched
+    /// with them, depending on the `allow` parameter. Enabled by default.
+    /// When disabled, the price of the incoming order must improve on the mid
+    /// price for it to trade with them.
+    ///
+    /// [`OrderType::MidpointPeg`]: enum.OrderType.html#variant.MidpointPeg
+    pub fn allow_peg_fills_at_mid(&mut self, allow: bool) {
+        self.peg_fills_at_mid = allow;
+    }
+
+    /// Return the number of trades that occurred on the order book, counting
+    /// each fill as a trade.
+    #[inline(always)]
+    pub fn trade_count(&self) -> u64 {
+        self.trade_count
+    }
+
+    /// Return the revision number of the order book, starting at 0 and
+    /// incremented by every operation that changes its state: executing an
+    /// order that changes the order book (unlike a rejected order, a cancel
+    /// of an unknown order or an unfilled order with no side effects),
+    /// amending a resting order, and freezing or unfreezing it. Comparing two
+    /// revision numbers is a cheap way to find out whether a snapshot or a
+    /// value derived from the order book is stale.
+    #[inline(always)]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Rebuild the price levels of both sides under a new price precision,
+    /// given as a number of decimal digits like in [`new`]. The resting orders
+    /// are re-keyed by their limit price, keeping their time priority.
+    ///
+    /// If the new precision is too coarse to tell apart the prices of two
+    /// levels of the same side, the order book is left unchanged and the price
+    /// of the first level found that would be merged into a previous one is
+    /// returned.
+    ///
+    /// The depletion statistics are keyed by price level, so they are reset.
+    /// Both sides get the same precision: see [`reprecision_sides`] to keep
+    /// a different precision on each side.
+    ///
+    /// [`new`]: #method.new
+    /// [`reprecision_sides`]: #method.reprecision_sides
+    pub fn reprecision(&mut self, new_precision: u128) -> Result<(), f64> {
+        self.reprecision_sides(new_precision, new_precision)
+    }
+
+    /// Rebuild the price levels like [`reprecision`], with a different
+    /// precision for each side, like in [`with_side_precision`].
+    ///
+    /// [`reprecision`]: #method.reprecision
+    /// [`with_side_precision`]: #method.with_side_precision
+    pub fn reprecision_sides(
+        &mut self,
+        bid_precision: u128,
+        ask_precision: u128,
+    ) -> Result<(), f64> {
+        let ask_precision = 10_f64.powf(ask_precision as f64);
+        let bid_precision = 10_f64.powf(bid_precision as f64);
+        let asks = self.rekey(Side::Ask, ask_precision)?;
+        let bids = self.rekey(Side::Bid, bid_precision)?;
+        self.asks = asks;
+        self.bids = bids;
+        self.ask_precision = ask_precision;
+        self.bid_precision = bid_precision;
+        self.ask_depletion.clear();
+        self.bid_depletion.clear();
+        self.min_ask = None;
+        self.max_bid = None;
+        self.update_min_ask();
+        self.update_max_bid();
+        if self.level_hooks.is_set() {
+            self.level_hooks.live = self.live_levels();
         }
-        bk
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Set the minimum resting time of the orders, as the number of order book
+    /// revisions (see [`version`]) that must follow the placement of an order
+    /// before it can be canceled by a [`Cancel`] order. Earlier cancels are
+    /// rejected with [`RejectReason::MinRestingTime`]. Defaults to `0`, that
+    /// is no restriction.
+    ///
+    /// [`version`]: #method.version
+    /// [`Cancel`]: enum.OrderType.html#variant.Cancel
+    /// [`RejectReason::MinRestingTime`]: enum.RejectReason.html#variant.MinRestingTime
+    pub fn set_min_resting_time(&mut self, revisions: u64) {
+        self.min_resting_time = revisions;
+    }
+
+    /// Set the maximum number of price levels kept on each side of the order
+    /// book, or `None` (the default) for no limit. When a limit order opens a
+    /// level beyond the cap, the worst-priced level of its side is evicted
+    /// (even if it is the new one), and all its orders are canceled.
+    ///
+    /// The [`OrderEvent::Canceled`] events of the evicted orders are queued,
+    /// and can be collected with [`drain_events`].
+    ///
+    /// [`OrderEvent::Canceled`]: enum.OrderEvent.html#variant.Canceled
+    /// [`drain_events`]: #method.drain_events
+    pub fn set_max_levels(&mut self, max_levels: Option<usize>) {
+        self.max_levels = max_levels;
+    }
+
+    /// Set the maximum depth of a cascade of triggered orders within a single
+    /// execution. The orders triggered by the fills of the executed order are
+    /// at depth `1`, the ones triggered by their fills at depth `2`, and so
+    /// on. The orders that would be triggered beyond the cap are left dormant,
+    /// and can be triggered by the next trades. Defaults to `64`.
+    pub fn set_max_cascade_depth(&mut self, depth: usize) {
+        self.max_cascade_depth = depth;
+    }
+
+    /// Set the self-trade prevention policy applied when an order executed
+    /// with [`execute_for_account`] would be matched with a resting order of
+    /// the same account, or `None` (the default) to let them trade.
+    ///
+    /// The [`OrderEvent::Canceled`] events of the resting orders canceled by
+    /// the policy are queued, and can be collected with [`drain_events`]. An
+    /// incoming limit order whose unfilled quantity is canceled does not rest,
+    /// and gets an [`OrderEvent::Canceled`] event if it was not filled at all.
+    ///
+    /// [`execute_for_account`]: #method.execute_for_account
+    /// [`OrderEvent::Canceled`]: enum.OrderEvent.html#variant.Canceled
+    /// [`drain_events`]: #method.drain_events
+    pub fn set_stp_mode(&mut self, mode: Option<StpMode>) {
+        self.stp_mode = mode;
+    }
+
+    /// Return the number of times an order was about to be matched with a
+    /// resting order of its own account, and the IDs of the orders canceled
+    /// by the self-trade prevention policy as a result, both resting and
+    /// incoming, in the order they were canceled (see [`set_stp_mode`]).
+    /// They are kept until [`reset_stats`] is called.
+    ///
+    /// [`set_stp_mode`]: #method.set_stp_mode
+    /// [`reset_stats`]: #method.reset_stats
+    pub fn self_match_stats(&self) -> (u64, &[u128]) {
+        (self.self_match_count, &self.stp_canceled)
+    }
+
+    /// Set how the quantity matched at a price level is allocated among its
+    /// resting orders. Defaults to [`MatchingMode::Fifo`].
+    ///
+    /// With [`MatchingMode::TopOrderProRata`], the best level falls back to
+    /// time priority when the incoming order takes all of it, when it holds
+    /// all-or-none orders, which cannot be partially filled, and when the
+    /// self-trade prevention applies to the incoming order.
+    ///
+    /// [`MatchingMode::Fifo`]: enum.MatchingMode.html#variant.Fifo
+    /// [`MatchingMode::TopOrderProRata`]: enum.MatchingMode.html#variant.TopOrderProRata
+    pub fn set_matching_mode(&mut self, mode: MatchingMode) {
+        self.matching_mode = mode;
+    }
+
+    /// Set a callback invoked with the side and the price of every price level
+    /// that becomes non-empty, e.g. to publish an L2 add message. Levels that
+    /// are already non-empty when the first callback is set are not reported.
+    ///
+    /// The callbacks are invoked once the order book reflects the executed
+    /// order, before the event of the order is returned: asks first, then
+    /// bids, in ascending price order. The levels changed by the dormant
+    /// orders it triggers are reported after it, with their own execution.
+    /// A level emptied and refilled within a single execution is not
+    /// reported, and neither are the levels changed by an [`execute_atomic`]
+    /// call that is rolled back.
+    ///
+    /// The callbacks are not kept by the copies of the order book.
+    ///
+    /// [`execute_atomic`]: #method.execute_atomic
+    pub fn on_level_added<F>(&mut self, callback: F)
+    where
+        F: FnMut(Side, f64) + Send + 'static,
+    {
+        self.track_levels();
+        self.level_hooks.added = Some(Box::new(callback));
+    }
+
+    /// Set a callback invoked with the side and the price of every price level
+    /// that becomes empty, e.g. to publish an L2 delete message. The callbacks
+    /// are invoked like the one set with [`on_level_added`].
+    ///
+    /// [`on_level_added`]: #method.on_level_added
+    pub fn on_level_removed<F>(&mut self, callback: F)
+    where
+        F: FnMut(Side, f64) + Send + 'static,
+    {
+        self.track_levels();
+        self.level_hooks.removed = Some(Box::new(callback));
     }
 
-    fn init_book_holes(
-        orders: Vec<(u64, usize)>,
-        holes: Vec<u64>,
-    ) -> BTreeMap<u64, Vec<usize>> {
-        let mut bk = init_book(orders);
-        for h in holes {
-            bk.insert(h, Vec::new());
+    /// Remove the callbacks set with [`on_level_added`] and
+    /// [`on_level_removed`].
+    ///
+    /// [`on_level_added`]: #method.on_level_added
+    /// [`on_level_removed`]: #method.on_level_removed
+    pub fn clear_level_callbacks(&mut self) {
+        self.level_hooks = LevelHooks::default();
+    }
+
+    /// Start tracking the non-empty price levels if no callback was set yet.
+    fn track_levels(&mut self) {
+        if !self.level_hooks.is_set() {
+            self.level_hooks.live = self.live_levels();
         }
-        bk
     }
 
-    #[test]
-    fn empty_book() {
-        let (ob, results) = init_ob(Vec::new());
-        assert_eq!(results, Vec::new());
-        assert_eq!(ob.min_ask(), None);
-        assert_eq!(ob.max_bid(), None);
-        assert_eq!(ob._asks(), BTreeMap::new());
-        assert_eq!(ob._bids(), BTreeMap::new());
-        assert_eq!(ob.spread(), None);
-        assert_eq!(ob.traded_volume(), 0.0);
-        assert_eq!(
-            ob.depth(2),
-            BookDepth {
-                levels: 2,
-                asks: Vec::new(),
-                bids: Vec::new()
+    /// Return the keys of the non-empty ask and bid price levels.
+    fn live_levels(&self) -> TouchedLevels {
+        let live = |side| {
+            self.queues(side)
+                .filter(|(key, _)| self.level_qty(side, **key) > 0.0)
+                .map(|(key, _)| *key)
+                .collect()
+        };
+        (live(Side::Ask), live(Side::Bid))
+    }
+
+    /// Return the aggregate quantity resting at a price level.
+    fn level_qty(&self, side: Side, key: u64) -> f64 {
+        let book = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        book.get(&key).map_or(0.0, |queue| {
+            queue.iter().map(|idx| self.arena[*idx].qty).sum()
+        })
+    }
+
+    /// Invoke the price level callbacks for the given levels whose state
+    /// differs from the one last notified.
+    fn notify_levels(&mut self, (asks, bids): TouchedLevels) {
+        for (side, keys) in [(Side::Ask, asks), (Side::Bid, bids)] {
+            let precision = self.precision(side);
+            for key in keys {
+                let live = self.level_qty(side, key) > 0.0;
+                let hooks = &mut self.level_hooks;
+                let tracked = match side {
+                    Side::Ask => &mut hooks.live.0,
+                    Side::Bid => &mut hooks.live.1,
+                };
+                let callback = match live {
+                    true if tracked.insert(key) => &mut hooks.added,
+                    false if tracked.remove(&key) => &mut hooks.removed,
+                    _ => continue,
+                };
+                if let Some(callback) = callback {
+                    callback(side, (key as f64) / precision);
+                }
+            }
+        }
+    }
+
+    /// Return the events generated for orders other than the ones being
+    /// executed (e.g. resting orders evicted by the level cap or expired), in
+    /// the order they occurred, and clear the queue.
+    pub fn drain_events(&mut self) -> Vec<OrderEvent> {
+        std::mem::take(&mut self.pending_events)
+    }
+
+    /// Execute an order, returning immediately an event indicating the result.
+    ///
+    /// If the fills of the order trigger dormant orders (e.g. stop orders),
+    /// they are executed right after it as market orders, and their events are
+    /// queued (see [`drain_events`]). The orders triggered by the same fills
+    /// are executed from the trigger price closest to the price of the first
+    /// fill to the farthest, then in placement order. The orders triggered by
+    /// their own fills follow, in the same way. Every dormant order is
+    /// triggered at most once, and the cascade stops at the depth set with
+    /// [`set_max_cascade_depth`].
+    ///
+    /// [`drain_events`]: #method.drain_events
+    /// [`set_max_cascade_depth`]: #method.set_max_cascade_depth
+    pub fn execute(&mut self, event: OrderType) -> OrderEvent {
+        let event = self.execute_untriggered(event);
+        self.fire_triggers(&event);
+        event
+    }
+
+    /// Execute an order like [`execute`], on behalf of an account. Limit
+    /// orders resting on the order book keep the account, which is checked
+    /// by the self-trade prevention policy (see [`set_stp_mode`]). The
+    /// dormant orders triggered by the execution are not attributed to the
+    /// account.
+    ///
+    /// [`execute`]: #method.execute
+    /// [`set_stp_mode`]: #method.set_stp_mode
+    pub fn execute_for_account(
+        &mut self,
+        account: u64,
+        event: OrderType,
+    ) -> OrderEvent {
+        self.taker_account = Some(account);
+        let event = self.execute_untriggered(event);
+        self.taker_account = None;
+        self.fire_triggers(&event);
+        event
+    }
+
+    /// Enable the cancel-on-disconnect protection for an account: its resting
+    /// orders are all canceled when [`trigger_disconnect`] reports that its
+    /// session dropped.
+    ///
+    /// [`trigger_disconnect`]: #method.trigger_disconnect
+    pub fn register_cancel_on_disconnect(&mut self, account: u64) {
+        self.cancel_on_disconnect.insert(account);
+    }
+
+    /// Report that the session of an account dropped, canceling the orders it
+    /// placed with [`execute_for_account`] that still rest on the order book
+    /// if the account registered for it with
+    /// [`register_cancel_on_disconnect`], and return their events in priority
+    /// order, asks first, followed by the events of its midpoint pegged
+    /// orders in the order they were placed. Nothing is canceled for other
+    /// accounts.
+    ///
+    /// Each order is canceled in its own execution. The cancels are issued by
+    /// the order book itself, so they are neither held back by the minimum
+    /// resting time nor by a freeze.
+    ///
+    /// [`execute_for_account`]: #method.execute_for_account
+    /// [`register_cancel_on_disconnect`]: #method.register_cancel_on_disconnect
+    pub fn trigger_disconnect(&mut self, account: u64) -> Vec<OrderEvent> {
+        if !self.cancel_on_disconnect.contains(&account) {
+            return Vec::new();
+        }
+        let ids: Vec<u128> = [Side::Ask, Side::Bid]
+            .iter()
+            .flat_map(|side| self.queues(*side))
+            .flat_map(|(_, queue)| queue.iter().map(|idx| &self.arena[*idx]))
+            .filter(|order| order.account == Some(account) && order.qty > 0.0)
+            .map(|order| order.id)
+            .chain(
+                self.pegs
+                    .iter()
+                    .filter(|peg| peg.account == Some(account))
+                    .map(|peg| peg.id),
+            )
+            .collect();
+        ids.into_iter()
+            .map(|id| {
+                self.execute_with(|ob| OrderEvent::Canceled {
+                    id,
+                    canceled_qty: ob.cancel(id),
+                    found: true,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace the quotes of an account on one side of the order book with a
+    /// new ladder of `(id, price, qty)` levels as a single transaction, e.g.
+    /// to refresh the quotes of a market maker, and return the events of the
+    /// cancels of its resting orders in priority order, followed by the
+    /// events of the new orders in the order of the ladder.
+    ///
+    /// The cancels and the new limit orders are executed in turn on behalf of
+    /// the account (see [`execute_for_account`]). If any of them is rejected,
+    /// e.g. while the order book is frozen or because an order has not rested
+    /// for the minimum resting time, or if a new order has a quantity or price
+    /// that is not a finite, positive number, the order book is rolled back
+    /// to its state before the call and the rejection is returned, like with
+    /// [`execute_atomic`].
+    ///
+    /// [`execute_for_account`]: #method.execute_for_account
+    /// [`execute_atomic`]: #method.execute_atomic
+    pub fn replace_side(
+        &mut self,
+        side: Side,
+        account: u64,
+        levels: &[(u128, f64, f64)],
+    ) -> Result<Vec<OrderEvent>, OrderEvent> {
+        let ids: Vec<u128> = self
+            .queues(side)
+            .flat_map(|(_, queue)| queue.iter().map(|idx| &self.arena[*idx]))
+            .filter(|order| order.account == Some(account) && order.qty > 0.0)
+            .map(|order| order.id)
+            .collect();
+        let cancels = ids.len();
+        let ladder = levels
+            .iter()
+            .map(|&(id, price, qty)| OrderType::limit(id, side, qty, price));
+        let orders = ids.into_iter().map(OrderType::cancel).chain(ladder);
+        let savepoint = self.savepoint();
+        let hooks = std::mem::take(&mut self.level_hooks);
+        let mut events = Vec::with_capacity(cancels + levels.len());
+        let mut rejected = None;
+        for order in orders {
+            let event = match Self::validate(&order) {
+                Some(event) => event,
+                None => self.execute_for_account(account, order),
+            };
+            if let OrderEvent::Rejected { .. } = event {
+                rejected = Some(event);
+                break;
             }
+            events.push(event);
+        }
+        self.end_transaction(savepoint, hooks, rejected.is_none());
+        match rejected {
+            Some(event) => Err(event),
+            None => Ok(events),
+        }
+    }
+
+    /// Execute an order like [`execute`], without executing the dormant orders
+    /// it triggers.
+    ///
+    /// [`execute`]: #method.execute
+    fn execute_untriggered(&mut self, event: OrderType) -> OrderEvent {
+        self.execute_with(|ob| ob._execute(event))
+    }
+
+    /// Run an operation producing an event as an execution, i.e. with the
+    /// bookkeeping done around every executed order (revision, expiries,
+    /// recent events, tape and stats), without firing triggers.
+    fn execute_with<F>(&mut self, operation: F) -> OrderEvent
+    where
+        F: FnOnce(&mut Self) -> OrderEvent,
+    {
+        #[cfg(any(test, feature = "debug"))]
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+        let queued = self.pending_events.len();
+        let hooked = self.level_hooks.is_set();
+        let outer = match hooked {
+            true => self.touched_levels.replace(Default::default()),
+            false => None,
+        };
+        let event = operation(self);
+        let unchanged = matches!(
+            event,
+            OrderEvent::Rejected { .. }
+                | OrderEvent::Canceled { found: false, .. }
+                | OrderEvent::Unfilled { .. }
         );
-        assert_eq!(ob.last_trade(), None);
+        if !unchanged || self.pending_events.len() > queued {
+            self.version += 1;
+        }
+        self.expire_orders();
+        if hooked {
+            let (asks, bids) = self.touched_levels.take().unwrap_or_default();
+            if let Some((mut outer_asks, mut outer_bids)) = outer {
+                outer_asks.extend(&asks);
+                outer_bids.extend(&bids);
+                self.touched_levels = Some((outer_asks, outer_bids));
+            }
+            self.notify_levels((asks, bids));
+        }
+        if self.recent_events_capacity > 0 {
+            self.recent_events
+                .extend(self.pending_events[queued..].iter().cloned());
+            self.recent_events.push_back(event.clone());
+            self.truncate_recent_events();
+        }
+        if self.record_tape {
+            if let OrderEvent::Filled { fills, .. }
+            | OrderEvent::PartiallyFilled { fills, .. } = &event
+            {
+                self.tape.extend_from_slice(fills);
+            }
+        }
+        if self.track_stats {
+            self.record_stats(&event);
+        }
+        event
     }
 
-    #[test]
-    fn one_resting_order() {
-        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
-            let (ob, results) = init_ob(vec![OrderType::Limit {
-                id: 0,
-                side: *bid_ask,
-                qty: 12.0,
-                price: 395.0,
-            }]);
-            assert_eq!(results, vec![OrderEvent::Placed { id: 0 }]);
-            if *bid_ask == Side::Bid {
-                assert_eq!(ob.min_ask(), None);
-                assert_eq!(ob.max_bid(), Some(395.0));
-                assert_eq!(ob._asks(), BTreeMap::new());
-                assert_eq!(ob._bids(), init_book(vec![(39500000000, 9999)]));
-                assert_eq!(ob.spread(), None);
-                assert_eq!(ob.traded_volume(), 0.0);
-                assert_eq!(
-                    ob.depth(3),
-                    BookDepth {
-                        levels: 3,
-                        asks: Vec::new(),
-                        bids: vec![BookLevel {
-                            price: 395.0,
-                            qty: 12.0
-                        }],
-                    }
+    fn truncate_recent_events(&mut self) {
+        while self.recent_events.len() > self.recent_events_capacity {
+            self.recent_events.pop_front();
+        }
+    }
+
+    fn record_stats(&mut self, event: &OrderEvent) {
+        if let OrderEvent::Filled { fills, .. }
+        | OrderEvent::PartiallyFilled { fills, .. } = event
+        {
+            let session = &mut self.session;
+            session.orders += 1;
+            for fill in fills {
+                if self.trade_prices.len() == TRADE_HISTORY_CAPACITY {
+                    self.trade_prices.pop_front();
+                }
+                self.trade_prices.push_back(fill.price);
+                session.fills += 1;
+                session.high = Some(session.high.map_or(fill.price, |high| {
+                    high.max(fill.price)
+                }));
+                session.low = Some(
+                    session.low.map_or(fill.price, |low| low.min(fill.price)),
                 );
-                assert_eq!(ob.last_trade(), None);
-            } else {
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(ob._asks(), init_book(vec![(39500000000, 9999)]));
-                assert_eq!(ob._bids(), BTreeMap::new());
-                assert_eq!(ob.spread(), None);
-                assert_eq!(ob.traded_volume(), 0.0);
-                assert_eq!(
-                    ob.depth(4),
-                    BookDepth {
-                        levels: 4,
-                        asks: vec![BookLevel {
-                            price: 395.0,
-                            qty: 12.0
-                        }],
-                        bids: Vec::new()
-                    }
+                session.notional += fill.price * fill.qty;
+                match fill.taker_side {
+                    Side::Bid => session.buy_volume += fill.qty,
+                    Side::Ask => session.sell_volume += fill.qty,
+                }
+            }
+        }
+
+        match event.clone() {
+            OrderEvent::Filled {
+                id: _,
+                filled_qty,
+                fills,
+            } => {
+                self.traded_volume += filled_qty;
+                // If we are here, fills is not empty, so it's safe to unwrap it
+                let last_fill = fills.last().unwrap();
+                let side = last_fill.taker_side;
+                self.last_taker_side = side;
+                self.last_trade = Some(Trade {
+                    total_qty: filled_qty,
+                    avg_price: fills
+                        .iter()
+                        .map(|fm| fm.price * fm.qty)
+                        .sum::<f64>()
+                        / filled_qty,
+                    last_qty: last_fill.qty,
+                    last_price: last_fill.price,
+                });
+            }
+            OrderEvent::PartiallyFilled {
+                id: _,
+                filled_qty,
+                fills,
+            } => {
+                self.traded_volume += filled_qty;
+                // If we are here, fills is not empty, so it's safe to unwrap it
+                let last_fill = fills.last().unwrap();
+                let side = last_fill.taker_side;
+                self.last_taker_side = side;
+                self.last_trade = Some(Trade {
+                    total_qty: filled_qty,
+                    avg_price: fills
+                        .iter()
+                        .map(|fm| fm.price * fm.qty)
+                        .sum::<f64>()
+                        / filled_qty,
+                    last_qty: last_fill.qty,
+                    last_price: last_fill.price,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Execute the dormant orders triggered by the fills of the given event,
+    /// queueing their events, then the ones triggered by their own fills,
+    /// breadth first, up to the maximum cascade depth.
+    fn fire_triggers(&mut self, event: &OrderEvent) {
+        if self.max_cascade_depth == 0 {
+            return;
+        }
+        let mut fired: Vec<_> = self
+            .take_triggered(event)
+            .into_iter()
+            .map(|order| (order, 1))
+            .collect();
+        let mut next = 0;
+        while let Some((order, depth)) = fired.get(next).cloned() {
+            next += 1;
+            let event = self.execute_untriggered(OrderType::Market {
+                id: order.id,
+                side: order.side,
+                qty: order.qty,
+            });
+            if depth < self.max_cascade_depth {
+                let triggered = self.take_triggered(&event);
+                fired.extend(
+                    triggered.into_iter().map(|order| (order, depth + 1)),
                 );
-                assert_eq!(ob.last_trade(), None);
             }
+            self.pending_events.push(event);
         }
     }
 
-    #[test]
-    fn two_resting_orders() {
-        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (ob, results) = init_ob(vec![
-                OrderType::Limit {
-                    id: 0,
+    /// Remove the dormant orders whose trigger price was touched by the fills
+    /// of the given event, and return them from the trigger price closest to
+    /// the price of the first fill to the farthest, then in placement order.
+    fn take_triggered(&mut self, event: &OrderEvent) -> Vec<TriggerOrder> {
+        let fills = match event {
+            OrderEvent::Filled { fills, .. }
+            | OrderEvent::PartiallyFilled { fills, .. } => fills,
+            _ => return Vec::new(),
+        };
+        if self.triggers.is_empty() {
+            return Vec::new();
+        }
+        let prices = fills.iter().map(|fill| fill.price);
+        let low = prices.clone().fold(f64::INFINITY, f64::min);
+        let high = prices.fold(f64::NEG_INFINITY, f64::max);
+        let (mut fired, dormant): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.triggers)
+                .into_iter()
+                .partition(|order| match order.on_fall {
+                    true => low <= order.trigger_price,
+                    false => high >= order.trigger_price,
+                });
+        self.triggers = dormant;
+        // The sort is stable, so placement order breaks the ties
+        let start = fills[0].price;
+        let distance =
+            |order: &TriggerOrder| (order.trigger_price - start).abs();
+        fired.sort_by(|a, b| {
+            distance(a).partial_cmp(&distance(b)).unwrap_or(Ordering::Equal)
+        });
+        fired
+    }
+
+    /// Execute an order like [`execute`], also returning the price levels whose
+    /// aggregate quantity was changed by the order, with their new quantity.
+    /// The changed levels are collected while the order is matched, so a
+    /// single call yields everything needed to publish an L2 update.
+    ///
+    /// [`execute`]: #method.execute
+    pub fn execute_with_levels(&mut self, event: OrderType) -> ExecutionReport {
+        self.touched_levels = Some(Default::default());
+        let event = self.execute(event);
+        let (asks, bids) = self.touched_levels.take().unwrap_or_default();
+        let level = |side, key: u64| BookLevel {
+            price: (key as f64) / self.precision(side),
+            qty: self.display_qty(self.level_qty(side, key)),
+        };
+        ExecutionReport {
+            event,
+            asks: asks.into_iter().map(|key| level(Side::Ask, key)).collect(),
+            bids: bids
+                .into_iter()
+                .rev()
+                .map(|key| level(Side::Bid, key))
+                .collect(),
+        }
+    }
+
+    /// Execute a sequence of orders as a single transaction: if any of them
+    /// is rejected, or has a quantity or price that is not a finite, positive
+    /// number, the order book is rolled back to its state before the call and
+    /// the index of the order is returned. Otherwise, return the events of
+    /// all the orders, in submission order.
+    ///
+    /// The rollback state is captured up front, copying the resting orders
+    /// but not the whole order book.
+    pub fn execute_atomic(
+        &mut self,
+        orders: &[OrderType],
+    ) -> Result<Vec<OrderEvent>, usize> {
+        let savepoint = self.savepoint();
+        let hooks = std::mem::take(&mut self.level_hooks);
+        let mut events = Vec::with_capacity(orders.len());
+        let mut rejected = None;
+        for (i, order) in orders.iter().enumerate() {
+            if Self::validate(order).is_some() {
+                rejected = Some(i);
+                break;
+            }
+            let event = self.execute(*order);
+            if let OrderEvent::Rejected { .. } = event {
+                rejected = Some(i);
+                break;
+            }
+            events.push(event);
+        }
+        self.end_transaction(savepoint, hooks, rejected.is_none());
+        match rejected {
+            Some(i) => Err(i),
+            None => Ok(events),
+        }
+    }
+
+    /// End a transaction started by taking a savepoint and the price level
+    /// callbacks, rolling the order book back unless `commit` is set. The
+    /// callbacks are put back and notified of the net changes, once the
+    /// transaction is over.
+    fn end_transaction(
+        &mut self,
+        savepoint: Savepoint,
+        hooks: LevelHooks,
+        commit: bool,
+    ) {
+        if !commit {
+            self.roll_back(savepoint);
+        }
+        self.level_hooks = hooks;
+        if self.level_hooks.is_set() {
+            let (mut asks, mut bids) = self.live_levels();
+            asks.extend(&self.level_hooks.live.0);
+            bids.extend(&self.level_hooks.live.1);
+            self.notify_levels((asks, bids));
+        }
+    }
+
+    /// Resolve the limit price of an order placed `ticks` away from the mid
+    /// price, rounded to the tick grid away from the other side of the order
+    /// book.
+    fn relative_price(&self, side: Side, ticks: i64) -> Option<f64> {
+        let mid = self.mid_price()?;
+        let tick = self.tick_size.unwrap_or(1.0 / self.precision(side));
+        let steps = match side {
+            Side::Bid => mid / tick - ticks as f64,
+            Side::Ask => mid / tick + ticks as f64,
+        };
+        // Ignore the floating point noise of the division when snapping to the
+        // grid, otherwise prices already on the grid can move by one tick.
+        let steps = match side {
+            _ if (steps - steps.round()).abs() < 1e-9 => steps.round(),
+            Side::Bid => steps.floor(),
+            Side::Ask => steps.ceil(),
+        };
+        Some(steps * tick)
+    }
+
+    /// Reject an order that could rest or lie dormant on the order book if its
+    /// ID is already used by a resting or dormant order, which it would
+    /// otherwise shadow.
+    fn reject_duplicate(&self, event: &OrderType) -> Option<OrderEvent> {
+        let id = match *event {
+            OrderType::Limit { id, .. }
+            | OrderType::Iceberg { id, .. }
+            | OrderType::AllOrNone { id, .. }
+            | OrderType::MarketIfTouched { id, .. }
+            | OrderType::StopMarket { id, .. }
+            | OrderType::MidpointPeg { id, .. } => id,
+            _ => return None,
+        };
+        match self.has_order(id) {
+            true => Some(OrderEvent::Rejected {
+                id,
+                reason: RejectReason::DuplicateId,
+            }),
+            false => None,
+        }
+    }
+
+    /// Return `true` if an order with the given ID is resting or dormant on
+    /// the order book.
+    fn has_order(&self, id: u128) -> bool {
+        self.arena.contains(id)
+            || self.triggers.iter().any(|t| t.id == id)
+            || self.pegs.iter().any(|p| p.id == id)
+    }
+
+    fn reject_frozen(event: OrderType) -> OrderEvent {
+        let id = match event {
+            OrderType::Market { id, .. }
+            | OrderType::MarketIfTouched { id, .. }
+            | OrderType::StopMarket { id, .. }
+            | OrderType::Internalized { id, .. }
+            | OrderType::Limit { id, .. }
+            | OrderType::ImmediateOrCancel { id, .. }
+            | OrderType::FillOrKill { id, .. }
+            | OrderType::Iceberg { id, .. }
+            | OrderType::AllOrNone { id, .. }
+            | OrderType::RelativeLimit { id, .. }
+            | OrderType::ImproveOrReject { id, .. }
+            | OrderType::TouchOrCancel { id, .. }
+            | OrderType::GoodForTrades { id, .. }
+            | OrderType::MidpointPeg { id, .. }
+            | OrderType::Cancel { id } => id,
+        };
+        OrderEvent::Rejected {
+            id,
+            reason: RejectReason::Frozen,
+        }
+    }
+
+    pub(crate) fn validate(event: &OrderType) -> Option<OrderEvent> {
+        let (id, qty, price) = match *event {
+            OrderType::Market { id, qty, .. }
+            | OrderType::Internalized { id, qty, .. }
+            | OrderType::MidpointPeg { id, qty, .. } => (id, qty, None),
+            OrderType::MarketIfTouched {
+                id,
+                qty,
+                trigger_price,
+                ..
+            } => (id, qty, Some(trigger_price)),
+            OrderType::StopMarket {
+                id,
+                qty,
+                stop_price,
+                ..
+            } => (id, qty, Some(stop_price)),
+            OrderType::Limit { id, qty, price, .. }
+            | OrderType::ImmediateOrCancel { id, qty, price, .. }
+            | OrderType::FillOrKill { id, qty, price, .. }
+            | OrderType::AllOrNone { id, qty, price, .. }
+            | OrderType::ImproveOrReject { id, qty, price, .. }
+            | OrderType::TouchOrCancel { id, qty, price, .. }
+            | OrderType::GoodForTrades { id, qty, price, .. } => {
+                (id, qty, Some(price))
+            }
+            OrderType::Iceberg {
+                id,
+                qty,
+                price,
+                peak_qty,
+                ..
+            } => {
+                if !peak_qty.is_finite() || peak_qty <= 0.0 {
+                    return Some(OrderEvent::Rejected {
+                        id,
+                        reason: RejectReason::InvalidQty,
+                    });
+                }
+                (id, qty, Some(price))
+            }
+            OrderType::RelativeLimit { .. } | OrderType::Cancel { .. } => {
+                return None
+            }
+        };
+        let reason = if !qty.is_finite() || qty <= 0.0 {
+            RejectReason::InvalidQty
+        } else if matches!(price, Some(p) if !p.is_finite() || p <= 0.0) {
+            RejectReason::InvalidPrice
+        } else {
+            return None;
+        };
+        Some(OrderEvent::Rejected { id, reason })
+    }
+
+    fn _execute(&mut self, event: OrderType) -> OrderEvent {
+        if self.frozen {
+            match event {
+                OrderType::Cancel { .. } if self.cancel_while_frozen => {}
+                _ => return Self::reject_frozen(event),
+            }
+        }
+        if let Some(rejected) = self.reject_duplicate(&event) {
+            return rejected;
+        }
+        match event {
+            OrderType::Market { id, side, qty } => {
+                let (fills, partial, filled_qty) =
+                    self.market(id, side, qty, None);
+                if fills.is_empty() {
+                    OrderEvent::Unfilled { id }
+                } else {
+                    match partial {
+                        false => OrderEvent::Filled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                        true => OrderEvent::PartiallyFilled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                    }
+                }
+            }
+            OrderType::MarketIfTouched {
+                id,
+                side,
+                qty,
+                trigger_price,
+            } => {
+                self.triggers.push(TriggerOrder {
+                    id,
+                    side,
+                    qty,
+                    trigger_price,
+                    // Buy when the price falls to the trigger, sell when it
+                    // rises to it
+                    on_fall: side == Side::Bid,
+                });
+                OrderEvent::Placed { id }
+            }
+            OrderType::StopMarket {
+                id,
+                side,
+                qty,
+                stop_price,
+            } => {
+                self.triggers.push(TriggerOrder {
+                    id,
+                    side,
+                    qty,
+                    trigger_price: stop_price,
+                    // Buy when the price rises to the stop, sell when it
+                    // falls to it
+                    on_fall: side == Side::Ask,
+                });
+                OrderEvent::Placed { id }
+            }
+            OrderType::Internalized { id, side, qty } => {
+                let mid = match self.mid_price() {
+                    Some(mid) => mid,
+                    None => {
+                        return OrderEvent::Rejected {
+                            id,
+                            reason: RejectReason::NoReferencePrice,
+                        }
+                    }
+                };
+                let (mut fills, partial, _) = self.market(id, side, qty, None);
+                if partial {
+                    let book_qty: f64 = fills.iter().map(|f| f.qty).sum();
+                    fills.push(FillMetadata {
+                        order_1: id,
+                        order_2: SYNTHETIC_COUNTERPARTY,
+                        qty: qty - book_qty,
+                        price: mid,
+                        taker_side: side,
+                        total_fill: true,
+                        maker_ts: 0,
+                        price_improvement: 0.0,
+                    });
+                }
+                OrderEvent::Filled {
+                    id,
+                    filled_qty: qty,
+                    fills,
+                }
+            }
+            OrderType::Limit {
+                id,
+                side,
+                qty,
+                price,
+            } => {
+                let (fills, partial, filled_qty, canceled) =
+                    self.limit(id, side, qty, price);
+                if fills.is_empty() && canceled {
+                    OrderEvent::Canceled {
+                        id,
+                        canceled_qty: 0.0,
+                        found: true,
+                    }
+                } else if fills.is_empty() {
+                    OrderEvent::Placed { id }
+                } else {
+                    match partial {
+                        false => OrderEvent::Filled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                        true => OrderEvent::PartiallyFilled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                    }
+                }
+            }
+            OrderType::ImmediateOrCancel {
+                id,
+                side,
+                qty,
+                price,
+            } => {
+                let (fills, partial, filled_qty) =
+                    self.market(id, side, qty, Some(price));
+                if fills.is_empty() {
+                    OrderEvent::Unfilled { id }
+                } else {
+                    match partial {
+                        false => OrderEvent::Filled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                        true => OrderEvent::PartiallyFilled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                    }
+                }
+            }
+            OrderType::FillOrKill {
+                id,
+                side,
+                qty,
+                price,
+            } => {
+                // Matching mutates the resting orders, so check beforehand
+                if self.unfilled_qty(side, qty, price) > 0.0 {
+                    return OrderEvent::Unfilled { id };
+                }
+                let (fills, partial, filled_qty) =
+                    self.market(id, side, qty, Some(price));
+                // Only the self-trade prevention can stop the order short
+                if fills.is_empty() {
+                    OrderEvent::Unfilled { id }
+                } else {
+                    match partial {
+                        false => OrderEvent::Filled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                        true => OrderEvent::PartiallyFilled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                    }
+                }
+            }
+            OrderType::Iceberg {
+                id,
+                side,
+                qty,
+                price,
+                peak_qty,
+            } => {
+                let (fills, partial, filled_qty, canceled) =
+                    self.limit(id, side, qty, price);
+                if partial {
+                    // Hide all but the first slice of the resting quantity
+                    if let Some((_, idx)) = self.arena.get(id) {
+                        let order = &mut self.arena[idx];
+                        order.reserve_qty = (order.qty - peak_qty).max(0.0);
+                        order.qty = order.qty.min(peak_qty);
+                        order.peak_qty = peak_qty;
+                    }
+                }
+                if fills.is_empty() && canceled {
+                    OrderEvent::Canceled {
+                        id,
+                        canceled_qty: 0.0,
+                        found: true,
+                    }
+                } else if fills.is_empty() {
+                    OrderEvent::Placed { id }
+                } else {
+                    match partial {
+                        false => OrderEvent::Filled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                        true => OrderEvent::PartiallyFilled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                    }
+                }
+            }
+            OrderType::AllOrNone {
+                id,
+                side,
+                qty,
+                price,
+            } => {
+                let (fills, partial, filled_qty, canceled) =
+                    self.limit(id, side, qty, price);
+                if partial {
+                    if let Some((_, idx)) = self.arena.get(id) {
+                        self.arena[idx].all_or_none = true;
+                    }
+                }
+                if fills.is_empty() && canceled {
+                    OrderEvent::Canceled {
+                        id,
+                        canceled_qty: 0.0,
+                        found: true,
+                    }
+                } else if fills.is_empty() {
+                    OrderEvent::Placed { id }
+                } else {
+                    match partial {
+                        false => OrderEvent::Filled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                        true => OrderEvent::PartiallyFilled {
+                            id,
+                            filled_qty,
+                            fills,
+                        },
+                    }
+                }
+            }
+            OrderType::RelativeLimit {
+                id,
+                side,
+                qty,
+                ticks,
+            } => match self.relative_price(side, ticks) {
+                Some(price) => self._execute(OrderType::Limit {
+                    id,
+                    side,
+                    qty,
+                    price,
+                }),
+                None => OrderEvent::Rejected {
+                    id,
+                    reason: RejectReason::NoReferencePrice,
+                },
+            },
+            OrderType::ImproveOrReject {
+                id,
+                side,
+                qty,
+                price,
+            } => {
+                let (crosses, improves) = match side {
+                    Side::Bid => (
+                        matches!(self.min_ask, Some(a) if price >= a),
+                        !matches!(self.max_bid, Some(b) if price <= b),
+                    ),
+                    Side::Ask => (
+                        matches!(self.max_bid, Some(b) if price <= b),
+                        !matches!(self.min_ask, Some(a) if price >= a),
+                    ),
+                };
+                let reason = match (crosses, improves) {
+                    (true, _) => RejectReason::WouldCross,
+                    (false, false) => RejectReason::WouldNotImprove,
+                    (false, true) => {
+                        return self._execute(OrderType::Limit {
+                            id,
+                            side,
+                            qty,
+                            price,
+                        })
+                    }
+                };
+                OrderEvent::Rejected { id, reason }
+            }
+            OrderType::TouchOrCancel {
+                id,
+                side,
+                qty,
+                price,
+            } => {
+                let event = self._execute(OrderType::Limit {
+                    id,
+                    side,
+                    qty,
+                    price,
+                });
+                if let OrderEvent::Placed { .. } = event {
+                    if !self.at_touch(side, id) {
+                        // An order evicted by the level cap is already
+                        // canceled, and its queued event is returned instead
+                        let evicted = |event: &OrderEvent| match event {
+                            OrderEvent::Canceled { id: evicted, .. } => {
+                                *evicted == id
+                            }
+                            _ => false,
+                        };
+                        let mut queued = self.pending_events.iter();
+                        if let Some(pos) = queued.rposition(evicted) {
+                            return self.pending_events.remove(pos);
+                        }
+                        let canceled_qty = self.cancel(id);
+                        return OrderEvent::Canceled {
+                            id,
+                            canceled_qty,
+                            found: true,
+                        };
+                    }
+                }
+                event
+            }
+            OrderType::GoodForTrades {
+                id,
+                side,
+                qty,
+                price,
+                trades,
+            } => {
+                let event = self._execute(OrderType::Limit {
+                    id,
+                    side,
+                    qty,
+                    price,
+                });
+                if let OrderEvent::Placed { .. }
+                | OrderEvent::PartiallyFilled { .. } = event
+                {
+                    if let Some((_, idx)) = self.arena.get(id) {
+                        let expiry = self.trade_count + trades;
+                        self.arena[idx].expires_after = Some(expiry);
+                        self.expiries.entry(expiry).or_default().push(id);
+                    }
+                }
+                event
+            }
+            OrderType::MidpointPeg { id, side, qty } => {
+                let mut fills = Vec::new();
+                self.start_self_trade(id);
+                let remaining_qty =
+                    self.match_pegs(id, side, qty, None, &mut fills);
+                let canceled = self.finish_self_trade();
+                if remaining_qty > 0.0 && !canceled {
+                    let ts = self.arena.next_ts();
+                    self.pegs.push(PeggedOrder {
+                        id,
+                        side,
+                        qty: remaining_qty,
+                        ts,
+                        account: self.taker_account,
+                    });
+                }
+                self.trade_count += fills.len() as u64;
+                if self.aggregate_fills {
+                    fills = Self::aggregate(fills);
+                }
+                let filled_qty = qty - remaining_qty;
+                match (fills.is_empty(), remaining_qty > 0.0) {
+                    (true, _) if canceled => OrderEvent::Canceled {
+                        id,
+                        canceled_qty: 0.0,
+                        found: true,
+                    },
+                    (true, _) => OrderEvent::Placed { id },
+                    (false, true) => OrderEvent::PartiallyFilled {
+                        id,
+                        filled_qty,
+                        fills,
+                    },
+                    (false, false) => OrderEvent::Filled {
+                        id,
+                        filled_qty,
+                        fills,
+                    },
+                }
+            }
+            OrderType::Cancel { id } => {
+                if let Some((_, idx)) = self.arena.get(id) {
+                    let order = &self.arena[idx];
+                    let rested = self.version - order.placed_at;
+                    if order.qty > 0.0 && rested < self.min_resting_time {
+                        return OrderEvent::Rejected {
+                            id,
+                            reason: RejectReason::MinRestingTime,
+                        };
+                    }
+                }
+                let found = self.has_order(id);
+                let canceled_qty = self.cancel(id);
+                OrderEvent::Canceled {
+                    id,
+                    canceled_qty,
+                    found,
+                }
+            }
+        }
+    }
+
+    /// Return `true` if the order with the given ID rests on the order book
+    /// at the front of the best price level of its side.
+    fn at_touch(&self, side: Side, id: u128) -> bool {
+        let idx = match self.arena.get(id) {
+            Some((_, idx)) => idx,
+            None => return false,
+        };
+        let mut levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+        .iter()
+        .filter(|(_, q)| !q.is_empty());
+        let best = match side {
+            Side::Bid => levels.next_back(),
+            Side::Ask => levels.next(),
+        };
+        match best {
+            Some((_, queue)) => queue[0] == idx,
+            None => true,
+        }
+    }
+
+    /// Cancel the resting orders whose trade count expiry was reached,
+    /// queueing an expiry event for each of them.
+    fn expire_orders(&mut self) {
+        while let Some(entry) = self.expiries.first_entry() {
+            if *entry.key() > self.trade_count {
+                break;
+            }
+            let (expiry, ids) = entry.remove_entry();
+            for id in ids {
+                // Skip orders that were filled, canceled or replaced by a new
+                // order with the same ID in the meantime
+                let resting = match self.arena.get(id) {
+                    Some((_, idx)) => {
+                        let order = &self.arena[idx];
+                        order.expires_after == Some(expiry) && order.qty > 0.0
+                    }
+                    None => false,
+                };
+                if resting {
+                    self.cancel(id);
+                    self.pending_events.push(OrderEvent::Expired { id });
+                }
+            }
+        }
+    }
+
+    /// Import all the resting orders of another order book, executing them as
+    /// limit orders in priority order, asks first. Iceberg and all-or-none
+    /// orders keep their type, with their hidden quantity for iceberg orders,
+    /// and orders keep their account. Imported orders that cross
+    /// the order book are matched, and the ones left rest behind the orders
+    /// already queued at the same price.
+    ///
+    /// Return the events of the imported orders. An order whose ID is already
+    /// used by an order on this order book is not imported, and gets an
+    /// [`OrderEvent::Rejected`] event with [`RejectReason::DuplicateId`].
+    /// Dormant and pegged orders and trade-count expiries of `other` are not
+    /// imported.
+    ///
+    /// [`OrderEvent::Rejected`]: enum.OrderEvent.html#variant.Rejected
+    /// [`RejectReason::DuplicateId`]: enum.RejectReason.html#variant.DuplicateId
+    pub fn merge(&mut self, other: OrderBook) -> Vec<OrderEvent> {
+        [Side::Ask, Side::Bid]
+            .iter()
+            .flat_map(|side| other.queues(*side))
+            .flat_map(|(_, queue)| queue.iter().map(|idx| &other.arena[*idx]))
+            .map(|order| {
+                if self.has_order(order.id) {
+                    return OrderEvent::Rejected {
+                        id: order.id,
+                        reason: RejectReason::DuplicateId,
+                    };
+                }
+                let imported = if order.reserve_qty > 0.0 {
+                    OrderType::Iceberg {
+                        id: order.id,
+                        side: order.side,
+                        qty: order.qty + order.reserve_qty,
+                        price: order.price,
+                        peak_qty: order.peak_qty,
+                    }
+                } else if order.all_or_none {
+                    OrderType::AllOrNone {
+                        id: order.id,
+                        side: order.side,
+                        qty: order.qty,
+                        price: order.price,
+                    }
+                } else {
+                    OrderType::Limit {
+                        id: order.id,
+                        side: order.side,
+                        qty: order.qty,
+                        price: order.price,
+                    }
+                };
+                match order.account {
+                    Some(account) => {
+                        self.execute_for_account(account, imported)
+                    }
+                    None => self.execute(imported),
+                }
+            })
+            .collect()
+    }
+
+    /// Capture the resting orders, the price precision and the stats of the
+    /// order book, to persist it and rebuild it later with [`restore`]
+    /// without replaying its history. Dormant and pegged orders and settings
+    /// (e.g. the tick size or the level cap) are not captured.
+    ///
+    /// [`restore`]: #method.restore
+    pub fn snapshot(&self) -> BookSnapshot {
+        let orders = [Side::Ask, Side::Bid]
+            .iter()
+            .flat_map(|side| self.queues(*side))
+            .flat_map(|(_, queue)| queue.iter())
+            .map(|idx| self.arena[*idx].clone())
+            .collect();
+        BookSnapshot {
+            orders,
+            bid_precision: self.bid_precision.log10().round() as u128,
+            ask_precision: self.ask_precision.log10().round() as u128,
+            track_stats: self.track_stats,
+            last_trade: self.last_trade,
+            last_taker_side: self.last_trade.map(|_| self.last_taker_side),
+            traded_volume: self.traded_volume,
+            trade_count: self.trade_count,
+            version: self.version,
+            last_ts: self.arena.last_ts(),
+        }
+    }
+
+    /// Rebuild an order book from a snapshot taken with [`snapshot`], with
+    /// the resting orders queued in the same priority order and the default
+    /// settings.
+    ///
+    /// [`snapshot`]: #method.snapshot
+    pub fn restore(snapshot: BookSnapshot) -> OrderBook {
+        let mut ob = Self::with_side_precision(
+            DEFAULT_ARENA_CAPACITY.max(snapshot.orders.len()),
+            DEFAULT_QUEUE_CAPACITY,
+            snapshot.bid_precision,
+            snapshot.ask_precision,
+            snapshot.track_stats,
+        );
+        for order in &snapshot.orders {
+            if let Some(expiry) = order.expires_after {
+                ob.expiries.entry(expiry).or_default().push(order.id);
+            }
+        }
+        ob.rest_orders(snapshot.orders);
+        ob.arena.set_last_ts(snapshot.last_ts);
+        ob.last_trade = snapshot.last_trade;
+        if let Some(side) = snapshot.last_taker_side {
+            ob.last_taker_side = side;
+        }
+        ob.traded_volume = snapshot.traded_volume;
+        ob.trade_count = snapshot.trade_count;
+        ob.version = snapshot.version;
+        ob
+    }
+
+    /// Queue orders at their price levels in the given order, keeping all
+    /// their fields, and update the best prices.
+    fn rest_orders(&mut self, orders: Vec<LimitOrder>) {
+        for order in orders {
+            let index = self
+                .arena
+                .insert(order.id, order.side, order.price, order.qty);
+            let queue_capacity = self.default_queue_capacity;
+            let key = self.price_key(order.side, order.price);
+            match order.side {
+                Side::Bid => &mut self.bids,
+                Side::Ask => &mut self.asks,
+            }
+            .entry(key)
+            .or_insert_with(|| Vec::with_capacity(queue_capacity))
+            .push(index);
+            self.arena[index] = order;
+        }
+        self.update_min_ask();
+        self.update_max_bid();
+    }
+
+    /// Capture the state an execution can change, to roll the order book back
+    /// to it with [`roll_back`]. Unlike a copy of the order book, only the
+    /// resting orders are copied, rather than every slot of the arena, and
+    /// the tape is not copied at all.
+    ///
+    /// [`roll_back`]: #method.roll_back
+    fn savepoint(&mut self) -> Savepoint {
+        let snapshot = self.snapshot();
+        let arena = std::mem::replace(&mut self.arena, OrderArena::new(0));
+        let asks = std::mem::take(&mut self.asks);
+        let bids = std::mem::take(&mut self.bids);
+        let tape = std::mem::take(&mut self.tape);
+        let state = Box::new(self.clone());
+        self.arena = arena;
+        self.asks = asks;
+        self.bids = bids;
+        self.tape = tape;
+        Savepoint {
+            state,
+            snapshot,
+            tape_len: self.tape.len(),
+        }
+    }
+
+    /// Roll the order book back to a state captured with [`savepoint`],
+    /// keeping the price level callbacks and the memory of the arena.
+    ///
+    /// [`savepoint`]: #method.savepoint
+    fn roll_back(&mut self, savepoint: Savepoint) {
+        let mut arena = std::mem::replace(&mut self.arena, OrderArena::new(0));
+        let mut tape = std::mem::take(&mut self.tape);
+        let hooks = std::mem::take(&mut self.level_hooks);
+        *self = *savepoint.state;
+        arena.clear();
+        self.arena = arena;
+        self.rest_orders(savepoint.snapshot.orders);
+        self.arena.set_last_ts(savepoint.snapshot.last_ts);
+        tape.truncate(savepoint.tape_len);
+        self.tape = tape;
+        self.level_hooks = hooks;
+    }
+
+    /// Cancel a resting order like a [`Cancel`] order, also returning its
+    /// position in the queue at its price level (`0` being the front) and the
+    /// total quantity ahead of it, as they were right before the cancel.
+    ///
+    /// Return `None` if the order is not resting on the order book.
+    ///
+    /// [`Cancel`]: enum.OrderType.html#variant.Cancel
+    pub fn cancel_with_position(
+        &mut self,
+        id: u128,
+    ) -> Option<(OrderEvent, usize, f64)> {
+        let (position, qty_ahead) = self.queue_position(id)?;
+        let event = self.execute(OrderType::Cancel { id });
+        Some((event, position, qty_ahead))
+    }
+
+    /// Cancel an order like a [`Cancel`] order, returning a [`CancelReport`]
+    /// with the event and the side, price, remaining quantity and queue
+    /// position of the order, as they were right before the cancel. Dormant
+    /// and pegged orders have no price level, so only their side and quantity
+    /// are reported.
+    ///
+    /// If nothing was canceled (e.g. the order is unknown or the cancel was
+    /// rejected), `found` is `false` and the other fields are left to their
+    /// default.
+    ///
+    /// [`Cancel`]: enum.OrderType.html#variant.Cancel
+    /// [`CancelReport`]: struct.CancelReport.html
+    pub fn cancel_report(&mut self, id: u128) -> CancelReport {
+        let resting = self
+            .arena
+            .get(id)
+            .filter(|(_, idx)| self.arena[*idx].qty > 0.0)
+            .map(|(price, idx)| (self.arena[idx].side, price));
+        let side = resting.map(|(side, _)| side).or_else(|| {
+            let trigger = self.triggers.iter().find(|order| order.id == id);
+            let peg = self.pegs.iter().find(|order| order.id == id);
+            trigger.map(|order| order.side).or(peg.map(|order| order.side))
+        });
+        let price = resting.map_or(0.0, |(_, price)| price);
+        let queue_position = self.queue_position(id).map_or(0, |(pos, _)| pos);
+        let event = self.execute(OrderType::Cancel { id });
+        match event {
+            OrderEvent::Canceled {
+                canceled_qty,
+                found: true,
+                ..
+            } => CancelReport {
+                event,
+                found: true,
+                side,
+                price,
+                qty: canceled_qty,
+                queue_position,
+            },
+            event => CancelReport {
+                event,
+                found: false,
+                side: None,
+                price: 0.0,
+                qty: 0.0,
+                queue_position: 0,
+            },
+        }
+    }
+
+    /// Return the position of a resting order in the queue at its price level
+    /// (`0` being the front) and the total quantity ahead of it, or `None` if
+    /// the order is not resting on the order book.
+    fn queue_position(&self, id: u128) -> Option<(usize, f64)> {
+        let (price, idx) = self.arena.get(id)?;
+        let side = self.arena[idx].side;
+        let book = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let queue = book.get(&self.price_key(side, price))?;
+        let position = queue.iter().position(|i| *i == idx)?;
+        let qty_ahead = queue[..position]
+            .iter()
+            .map(|i| self.arena[*i].qty)
+            .sum();
+        Some((position, qty_ahead))
+    }
+
+    /// Reduce the quantity of a resting order in place, keeping its time
+    /// priority at its price level.
+    ///
+    /// Return an [`OrderEvent::Amended`] event with the new quantity, or an
+    /// [`OrderEvent::Rejected`] event if the order is not resting on the order
+    /// book, if `new_qty` is not positive or greater than the resting quantity,
+    /// or if the order book is frozen.
+    ///
+    /// [`OrderEvent::Amended`]: enum.OrderEvent.html#variant.Amended
+    /// [`OrderEvent::Rejected`]: enum.OrderEvent.html#variant.Rejected
+    pub fn reduce_qty(&mut self, id: u128, new_qty: f64) -> OrderEvent {
+        if self.frozen {
+            return OrderEvent::Rejected {
+                id,
+                reason: RejectReason::Frozen,
+            };
+        }
+        let reason = match self.arena.get(id) {
+            Some((_, idx)) if self.arena[idx].qty > 0.0 => {
+                let order = &mut self.arena[idx];
+                if new_qty > 0.0 && new_qty <= order.qty {
+                    order.qty = new_qty;
+                    self.version += 1;
+                    return OrderEvent::Amended { id, new_qty };
+                }
+                RejectReason::InvalidQty
+            }
+            _ => RejectReason::UnknownOrder,
+        };
+        OrderEvent::Rejected { id, reason }
+    }
+
+    /// Replace a resting order with a limit order of the same ID and side at
+    /// `new_price` for `new_qty`, in a single execution: the order loses its
+    /// time priority, and the replacement is matched if it crosses the order
+    /// book.
+    ///
+    /// Return an [`OrderEvent::Replaced`] event if the replacement rests
+    /// without matching, or the fill event of the replacement otherwise.
+    /// Return an [`OrderEvent::Rejected`] event, leaving the order in place,
+    /// if the order book is frozen, if the order is not resting on the order
+    /// book, if the new price or quantity is invalid, or if the order has not
+    /// rested for the minimum resting time (see [`set_min_resting_time`]).
+    ///
+    /// [`OrderEvent::Replaced`]: enum.OrderEvent.html#variant.Replaced
+    /// [`OrderEvent::Rejected`]: enum.OrderEvent.html#variant.Rejected
+    /// [`set_min_resting_time`]: #method.set_min_resting_time
+    pub fn replace(
+        &mut self,
+        id: u128,
+        new_price: f64,
+        new_qty: f64,
+    ) -> OrderEvent {
+        let rejected = |reason| OrderEvent::Rejected { id, reason };
+        if self.frozen {
+            return rejected(RejectReason::Frozen);
+        }
+        let (side, placed_at, account) = match self.arena.get(id) {
+            Some((_, idx)) if self.arena[idx].qty > 0.0 => {
+                let order = &self.arena[idx];
+                (order.side, order.placed_at, order.account)
+            }
+            _ => return rejected(RejectReason::UnknownOrder),
+        };
+        let order = OrderType::Limit {
+            id,
+            side,
+            qty: new_qty,
+            price: new_price,
+        };
+        if let Some(rejected) = Self::validate(&order) {
+            return rejected;
+        }
+        if self.version - placed_at < self.min_resting_time {
+            return rejected(RejectReason::MinRestingTime);
+        }
+        let event = self.execute_with(|ob| {
+            ob.cancel(id);
+            ob.taker_account = account;
+            let event = ob._execute(order);
+            ob.taker_account = None;
+            match event {
+                OrderEvent::Placed { id } => OrderEvent::Replaced { id },
+                event => event,
+            }
+        });
+        self.fire_triggers(&event);
+        event
+    }
+
+    /// Return the sequence number of a resting order at its price level (see
+    /// [`FillMetadata::maker_ts`]), or `None` if the order is not resting on
+    /// the order book. Sequence numbers increase every time an order is
+    /// queued at the back of a level, i.e. when it is placed, when it loses
+    /// its time priority and when an iceberg order releases a new slice.
+    ///
+    /// [`FillMetadata::maker_ts`]: struct.FillMetadata.html#structfield.maker_ts
+    pub fn order_ts(&self, id: u128) -> Option<u64> {
+        match self.arena.get(id) {
+            Some((_, idx)) if self.arena[idx].qty > 0.0 => {
+                Some(self.arena[idx].ts)
+            }
+            _ => None,
+        }
+    }
+
+    /// Change the quantity of a resting order in place. A decrease keeps the
+    /// time priority of the order at its price level, like [`reduce_qty`],
+    /// while an increase moves the order to the back of the queue, like
+    /// [`refresh_priority`].
+    ///
+    /// Return an [`OrderEvent::Amended`] event with the new quantity, or an
+    /// [`OrderEvent::Rejected`] event if the order is not resting on the order
+    /// book, if `new_qty` is not positive and finite, or if the order book is
+    /// frozen.
+    ///
+    /// [`reduce_qty`]: #method.reduce_qty
+    /// [`refresh_priority`]: #method.refresh_priority
+    /// [`OrderEvent::Amended`]: enum.OrderEvent.html#variant.Amended
+    /// [`OrderEvent::Rejected`]: enum.OrderEvent.html#variant.Rejected
+    pub fn amend_qty(&mut self, id: u128, new_qty: f64) -> OrderEvent {
+        let increase = match self.arena.get(id) {
+            Some((_, idx)) => {
+                let qty = self.arena[idx].qty;
+                qty > 0.0 && new_qty > qty && new_qty.is_finite()
+            }
+            None => false,
+        };
+        if !increase {
+            return self.reduce_qty(id, new_qty);
+        }
+        match self.refresh_priority(id) {
+            OrderEvent::Amended { .. } => {
+                // The order is resting, so it's safe to unwrap it
+                let (_, idx) = self.arena.get(id).unwrap();
+                self.arena[idx].qty = new_qty;
+                OrderEvent::Amended { id, new_qty }
+            }
+            rejected => rejected,
+        }
+    }
+
+    /// Move a resting order to the back of the queue at its price level,
+    /// losing its time priority. The order price and quantity are unchanged.
+    ///
+    /// Return an [`OrderEvent::Amended`] event with the resting quantity, or
+    /// an [`OrderEvent::Rejected`] event if the order is not resting on the
+    /// order book.
+    ///
+    /// [`OrderEvent::Amended`]: enum.OrderEvent.html#variant.Amended
+    /// [`OrderEvent::Rejected`]: enum.OrderEvent.html#variant.Rejected
+    pub fn refresh_priority(&mut self, id: u128) -> OrderEvent {
+        if self.frozen {
+            return OrderEvent::Rejected {
+                id,
+                reason: RejectReason::Frozen,
+            };
+        }
+        if let Some((price, idx)) = self.arena.get(id) {
+            let side = self.arena[idx].side;
+            let vect_price = self.price_key(side, price);
+            let book = match side {
+                Side::Bid => &mut self.bids,
+                Side::Ask => &mut self.asks,
+            };
+            if let Some(queue) = book.get_mut(&vect_price) {
+                if let Some(i) = queue.iter().position(|i| *i == idx) {
+                    queue.remove(i);
+                    queue.push(idx);
+                    let ts = self.arena.next_ts();
+                    self.arena[idx].ts = ts;
+                    self.version += 1;
+                    return OrderEvent::Amended {
+                        id,
+                        new_qty: self.arena[idx].qty,
+                    };
+                }
+            }
+        }
+        OrderEvent::Rejected {
+            id,
+            reason: RejectReason::UnknownOrder,
+        }
+    }
+
+    /// Remove a resting or dormant order, returning its remaining quantity.
+    ///
+    /// The queue is searched from the back, since the orders canceled most
+    /// often are the ones placed last. A level left empty is removed, and only
+    /// then is the best price looked up again.
+    fn cancel(&mut self, id: u128) -> f64 {
+        if let Some(i) = self.triggers.iter().position(|order| order.id == id)
+        {
+            return self.triggers.remove(i).qty;
+        }
+        if let Some(i) = self.pegs.iter().position(|order| order.id == id) {
+            return self.pegs.remove(i).qty;
+        }
+        let mut canceled_qty = 0.0;
+        if let Some((price, idx)) = self.arena.get(id) {
+            canceled_qty = self.arena[idx].qty + self.arena[idx].reserve_qty;
+            let side = self.arena[idx].side;
+            let vect_price = self.price_key(side, price);
+            let book = match side {
+                Side::Bid => &mut self.bids,
+                Side::Ask => &mut self.asks,
+            };
+            let mut emptied = false;
+            if let Some(queue) = book.get_mut(&vect_price) {
+                if let Some(i) = queue.iter().rposition(|i| *i == idx) {
+                    queue.remove(i);
+                    emptied = queue.is_empty();
+                    Self::touch_level(
+                        &mut self.touched_levels,
+                        side,
+                        vect_price,
+                    );
+                }
+            }
+            if emptied {
+                book.remove(&vect_price);
+                match side {
+                    Side::Bid => self.update_max_bid(),
+                    Side::Ask => self.update_min_ask(),
+                }
+            }
+        }
+        self.arena.delete(&id);
+        canceled_qty
+    }
+
+    /// Match an order without adding its unfilled quantity to the order book,
+    /// up to the limit price if one is given.
+    fn market(
+        &mut self,
+        id: u128,
+        side: Side,
+        qty: f64,
+        limit_price: Option<f64>,
+    ) -> (Vec<FillMetadata>, bool, f64) {
+        let mut partial = false;
+        let remaining_qty: f64;
+        let mut fills = Vec::new();
+
+        self.start_self_trade(id);
+        match side {
+            Side::Bid => {
+                remaining_qty =
+                    self.match_with_asks(id, qty, &mut fills, limit_price);
+                if remaining_qty > 0.0 {
+                    partial = true;
+                }
+            }
+            Side::Ask => {
+                remaining_qty =
+                    self.match_with_bids(id, qty, &mut fills, limit_price);
+                if remaining_qty > 0.0 {
+                    partial = true;
+                }
+            }
+        }
+        self.finish_self_trade();
+
+        for fill in &mut fills {
+            fill.price_improvement =
+                Self::price_improvement(side, limit_price, fill.price);
+        }
+        self.trade_count += fills.len() as u64;
+        if self.aggregate_fills {
+            fills = Self::aggregate(fills);
+        }
+        let precision = self.precision(side);
+        let filled_qty =
+            (((qty - remaining_qty) * precision) as u64) as f64 / precision;
+        (fills, partial, filled_qty)
+    }
+
+    /// Match a limit order, and add its unfilled quantity to the order book
+    /// unless it was canceled by the self-trade prevention policy, which is
+    /// reported by the last value returned.
+    fn limit(
+        &mut self,
+        id: u128,
+        side: Side,
+        qty: f64,
+        price: f64,
+    ) -> (Vec<FillMetadata>, bool, f64, bool) {
+        let mut partial = false;
+        let remaining_qty: f64;
+        let canceled: bool;
+        let mut fills: Vec<FillMetadata> = Vec::new();
+
+        self.start_self_trade(id);
+        match side {
+            Side::Bid => {
+                remaining_qty =
+                    self.match_with_asks(id, qty, &mut fills, Some(price));
+                canceled = self.finish_self_trade();
+                if remaining_qty > 0.0 {
+                    partial = true;
+                }
+                if partial && !canceled {
+                    let index =
+                        self.arena.insert(id, side, price, remaining_qty);
+                    // Executing the order creates the next revision
+                    self.arena[index].placed_at = self.version + 1;
+                    self.arena[index].account = self.taker_account;
+                    let queue_capacity = self.default_queue_capacity;
+                    let vect_price = self.price_key(side, price);
+                    self.bids
+                        .entry(vect_price)
+                        .or_insert_with(|| Vec::with_capacity(queue_capacity))
+                        .push(index);
+                    Self::touch_level(
+                        &mut self.touched_levels,
+                        side,
+                        vect_price,
+                    );
+                    match self.max_bid {
+                        None => {
+                            self.max_bid = Some(price);
+                        }
+                        Some(b) if price > b => {
+                            self.max_bid = Some(price);
+                        }
+                        _ => {}
+                    };
+                    self.evict_levels(side);
+                }
+            }
+            Side::Ask => {
+                remaining_qty =
+                    self.match_with_bids(id, qty, &mut fills, Some(price));
+                canceled = self.finish_self_trade();
+                if remaining_qty > 0.0 {
+                    partial = true;
+                }
+                if partial && !canceled {
+                    let index =
+                        self.arena.insert(id, side, price, remaining_qty);
+                    // Executing the order creates the next revision
+                    self.arena[index].placed_at = self.version + 1;
+                    self.arena[index].account = self.taker_account;
+                    let queue_capacity = self.default_queue_capacity;
+                    let vect_price = self.price_key(side, price);
+                    self.asks
+                        .entry(vect_price)
+                        .or_insert_with(|| Vec::with_capacity(queue_capacity))
+                        .push(index);
+                    Self::touch_level(
+                        &mut self.touched_levels,
+                        side,
+                        vect_price,
+                    );
+                    match self.min_ask {
+                        None => {
+                            self.min_ask = Some(price);
+                        }
+                        Some(a) if price < a => {
+                            self.min_ask = Some(price);
+                        }
+                        _ => {}
+                    };
+                    self.evict_levels(side);
+                }
+            }
+        }
+
+        for fill in &mut fills {
+            fill.price_improvement =
+                Self::price_improvement(side, Some(price), fill.price);
+        }
+        self.trade_count += fills.len() as u64;
+        if self.aggregate_fills {
+            fills = Self::aggregate(fills);
+        }
+        let precision = self.precision(side);
+        let filled_qty =
+            (((qty - remaining_qty) * precision) as u64) as f64 / precision;
+        (fills, partial, filled_qty, canceled)
+    }
+
+    /// Set up the self-trade prevention for the order about to be matched, if
+    /// a policy is set and the order belongs to an account.
+    fn start_self_trade(&mut self, id: u128) {
+        self.self_trade = match (self.stp_mode, self.taker_account) {
+            (Some(mode), Some(account)) => Some(SelfTradeGuard {
+                id,
+                account,
+                mode,
+                matches: 0,
+                canceled: Vec::new(),
+                taker_canceled: false,
+            }),
+            _ => None,
+        };
+    }
+
+    /// Remove the resting orders canceled by the self-trade prevention while
+    /// matching, queueing a cancel event for each of them, and return whether
+    /// the unfilled quantity of the incoming order was canceled.
+    fn finish_self_trade(&mut self) -> bool {
+        let guard = match self.self_trade.take() {
+            Some(guard) => guard,
+            None => return false,
+        };
+        self.self_match_count += guard.matches;
+        for (id, canceled_qty) in guard.canceled {
+            self.stp_canceled.push(id);
+            if let Some((price, idx)) = self.arena.get(id) {
+                let side = self.arena[idx].side;
+                let key = self.price_key(side, price);
+                Self::touch_level(&mut self.touched_levels, side, key);
+            }
+            self.arena.delete(&id);
+            self.pending_events.push(OrderEvent::Canceled {
+                id,
+                canceled_qty,
+                found: true,
+            });
+        }
+        if guard.taker_canceled {
+            self.stp_canceled.push(guard.id);
+        }
+        guard.taker_canceled
+    }
+
+    fn match_with_asks(
+        &mut self,
+        id: u128,
+        qty: f64,
+        fills: &mut Vec<FillMetadata>,
+        limit_price: Option<f64>,
+    ) -> f64 {
+        let mut remaining_qty =
+            self.match_pegs(id, Side::Bid, qty, limit_price, fills);
+        let matched = fills.len();
+        if matches!(
+            self.self_trade,
+            Some(SelfTradeGuard {
+                taker_canceled: true,
+                ..
+            })
+        ) {
+            return remaining_qty;
+        }
+        let mut update_bid_ask = false;
+        let mut emptied = Vec::new();
+        let mut at_touch = self.matching_mode == MatchingMode::TopOrderProRata
+            && self.self_trade.is_none();
+        let start = self.ask_scan_start();
+        for (vect_ask_price, queue) in self.asks.range_mut(start..) {
+            let ask_price = (*vect_ask_price as f64) / self.ask_precision;
+            if queue.is_empty() {
+                continue;
+            }
+            if (update_bid_ask || self.min_ask.is_none()) && !queue.is_empty() {
+                self.min_ask = Some(ask_price);
+                update_bid_ask = false;
+            }
+            if let Some(lp) = limit_price {
+                if lp < ask_price {
+                    break;
+                }
+            }
+            if remaining_qty == 0.0 {
+                break;
+            }
+            #[cfg(any(test, feature = "debug"))]
+            let traced = fills.len();
+            let unfilled_qty = if at_touch {
+                Self::process_touch_pro_rata(
+                    &mut self.arena,
+                    queue,
+                    remaining_qty,
+                    id,
+                    Side::Bid,
+                    fills,
+                )
+            } else {
+                Self::process_queue(
+                    &mut self.arena,
+                    queue,
+                    remaining_qty,
+                    id,
+                    Side::Bid,
+                    fills,
+                    &mut self.self_trade,
+                )
+            };
+            at_touch = false;
+            #[cfg(any(test, feature = "debug"))]
+            if let Some(trace) = &mut self.trace {
+                trace.extend(fills[traced..].iter().map(|fill| TraceStep {
+                    maker_id: fill.order_2,
+                    level: *vect_ask_price,
+                    qty: fill.qty,
+                }));
+            }
+            let filled_qty = remaining_qty - unfilled_qty;
+            if queue.is_empty() {
+                update_bid_ask = true;
+                emptied.push(*vect_ask_price);
+            }
+            if filled_qty > 0.0 {
+                Self::touch_level(
+                    &mut self.touched_levels,
+                    Side::Ask,
+                    *vect_ask_price,
+                );
+            }
+            if self.track_stats {
+                *self.ask_depletion.entry(*vect_ask_price).or_insert(0.0) +=
+                    filled_qty;
+            }
+            remaining_qty = unfilled_qty;
+            if matches!(
+                self.self_trade,
+                Some(SelfTradeGuard {
+                    taker_canceled: true,
+                    ..
+                })
+            ) {
+                break;
+            }
+        }
+
+        self.record_filled(&fills[matched..]);
+        for key in emptied {
+            self.asks.remove(&key);
+        }
+        self.update_min_ask();
+        remaining_qty
+    }
+
+    fn match_with_bids(
+        &mut self,
+        id: u128,
+        qty: f64,
+        fills: &mut Vec<FillMetadata>,
+        limit_price: Option<f64>,
+    ) -> f64 {
+        let mut remaining_qty =
+            self.match_pegs(id, Side::Ask, qty, limit_price, fills);
+        let matched = fills.len();
+        if matches!(
+            self.self_trade,
+            Some(SelfTradeGuard {
+                taker_canceled: true,
+                ..
+            })
+        ) {
+            return remaining_qty;
+        }
+        let mut update_bid_ask = false;
+        let mut emptied = Vec::new();
+        let mut at_touch = self.matching_mode == MatchingMode::TopOrderProRata
+            && self.self_trade.is_none();
+        let end = self.bid_scan_end();
+        for (vect_bid_price, queue) in self.bids.range_mut(..=end).rev() {
+            let bid_price = (*vect_bid_price as f64) / self.bid_precision;
+            if queue.is_empty() {
+                continue;
+            }
+            if (update_bid_ask || self.max_bid.is_none()) && !queue.is_empty() {
+                self.max_bid = Some(bid_price);
+                update_bid_ask = false;
+            }
+            if let Some(lp) = limit_price {
+                if lp > bid_price {
+                    break;
+                }
+            }
+            if remaining_qty == 0.0 {
+                break;
+            }
+            #[cfg(any(test, feature = "debug"))]
+            let traced = fills.len();
+            let unfilled_qty = if at_touch {
+                Self::process_touch_pro_rata(
+                    &mut self.arena,
+                    queue,
+                    remaining_qty,
+                    id,
+                    Side::Ask,
+                    fills,
+                )
+            } else {
+                Self::process_queue(
+                    &mut self.arena,
+                    queue,
+                    remaining_qty,
+                    id,
+                    Side::Ask,
+                    fills,
+                    &mut self.self_trade,
+                )
+            };
+            at_touch = false;
+            #[cfg(any(test, feature = "debug"))]
+            if let Some(trace) = &mut self.trace {
+                trace.extend(fills[traced..].iter().map(|fill| TraceStep {
+                    maker_id: fill.order_2,
+                    level: *vect_bid_price,
+                    qty: fill.qty,
+                }));
+            }
+            let filled_qty = remaining_qty - unfilled_qty;
+            if queue.is_empty() {
+                update_bid_ask = true;
+                emptied.push(*vect_bid_price);
+            }
+            if filled_qty > 0.0 {
+                Self::touch_level(
+                    &mut self.touched_levels,
+                    Side::Bid,
+                    *vect_bid_price,
+                );
+            }
+            if self.track_stats {
+                *self.bid_depletion.entry(*vect_bid_price).or_insert(0.0) +=
+                    filled_qty;
+            }
+            remaining_qty = unfilled_qty;
+            if matches!(
+                self.self_trade,
+                Some(SelfTradeGuard {
+                    taker_canceled: true,
+                    ..
+                })
+            ) {
+                break;
+            }
+        }
+
+        self.record_filled(&fills[matched..]);
+        for key in emptied {
+            self.bids.remove(&key);
+        }
+        self.update_max_bid();
+        remaining_qty
+    }
+
+    /// Match an incoming order with the orders of the other side pegged to the
+    /// mid price, in time priority, and return its quantity left unfilled.
+    /// They only trade at the price returned by [`peg_price`], if it is within
+    /// the limit price of the order, applying the self-trade prevention.
+    ///
+    /// [`peg_price`]: #method.peg_price
+    fn match_pegs(
+        &mut self,
+        id: u128,
+        side: Side,
+        qty: f64,
+        limit_price: Option<f64>,
+        fills: &mut Vec<FillMetadata>,
+    ) -> f64 {
+        if self.pegs.is_empty() {
+            return qty;
+        }
+        let peg_side = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mid = match self.peg_price(peg_side) {
+            Some(mid) => mid,
+            None => return qty,
+        };
+        let within_limit = match (side, limit_price) {
+            (_, None) => true,
+            (_, Some(lp)) if lp == mid => self.peg_fills_at_mid,
+            (Side::Bid, Some(lp)) => lp > mid,
+            (Side::Ask, Some(lp)) => lp < mid,
+        };
+        if !within_limit {
+            return qty;
+        }
+        let mut remaining_qty = qty;
+        for peg in self.pegs.iter_mut().filter(|peg| peg.side == peg_side) {
+            if remaining_qty == 0.0 {
+                break;
+            }
+            if let Some(guard) = &mut self.self_trade {
+                if peg.account == Some(guard.account) {
+                    guard.matches += 1;
+                    if guard.mode != StpMode::CancelNewest {
+                        guard.canceled.push((peg.id, peg.qty));
+                        peg.qty = 0.0;
+                    }
+                    if guard.mode != StpMode::CancelOldest {
+                        guard.taker_canceled = true;
+                        break;
+                    }
+                    continue;
+                }
+            }
+            let traded_qty = peg.qty.min(remaining_qty);
+            remaining_qty -= traded_qty;
+            peg.qty = (peg.qty - traded_qty).max(0.0);
+            fills.push(FillMetadata {
+                order_1: id,
+                order_2: peg.id,
+                qty: traded_qty,
+                price: mid,
+                taker_side: side,
+                total_fill: peg.qty == 0.0,
+                maker_ts: peg.ts,
+                price_improvement: 0.0,
+            });
+        }
+        self.pegs.retain(|peg| peg.qty > 0.0);
+        remaining_qty
+    }
+
+    /// Return the price the orders of the given side pegged to the mid trade
+    /// at, if it is strictly inside the spread: the mid price rounded to the
+    /// nearest tick of their side. When the mid price falls exactly between
+    /// two ticks, it is rounded in favour of the pegged orders, i.e. down for
+    /// bids and up for asks, so a spread of one tick leaves no room for them.
+    fn peg_price(&self, side: Side) -> Option<f64> {
+        let (bid, ask) = (self.max_bid?, self.min_ask?);
+        let precision = self.precision(side);
+        let mid = (bid + ask) / 2.0 * precision;
+        let ticks = match side {
+            Side::Bid => (mid - 0.5).ceil(),
+            Side::Ask => (mid + 0.5).floor(),
+        };
+        let price = ticks / precision;
+        if bid < price && price < ask {
+            Some(price)
+        } else {
+            None
+        }
+    }
+
+    /// Evict the worst-priced levels of the given side until the number of
+    /// non-empty levels is within the cap, queueing a cancel event for each
+    /// evicted order.
+    fn evict_levels(&mut self, side: Side) {
+        let max_levels = match self.max_levels {
+            Some(max_levels) => max_levels,
+            None => return,
+        };
+        let book = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        while book.values().filter(|q| !q.is_empty()).count() > max_levels {
+            let mut levels = book.iter().filter(|(_, q)| !q.is_empty());
+            let worst = match side {
+                Side::Bid => levels.next(),
+                Side::Ask => levels.next_back(),
+            };
+            // The loop condition guarantees there is a non-empty level
+            let key = *worst.unwrap().0;
+            Self::touch_level(&mut self.touched_levels, side, key);
+            for idx in book.remove(&key).unwrap_or_default() {
+                let id = self.arena[idx].id;
+                let canceled_qty = self.arena[idx].qty;
+                self.arena.delete(&id);
+                self.pending_events.push(OrderEvent::Canceled {
+                    id,
+                    canceled_qty,
+                    found: true,
+                });
+            }
+        }
+        match side {
+            Side::Bid => self.update_max_bid(),
+            Side::Ask => self.update_min_ask(),
+        }
+    }
+
+    /// Return the key the ask levels are scanned from. No ask rests below the
+    /// best one, so the scan can start there.
+    fn ask_scan_start(&self) -> u64 {
+        self.min_ask.map_or(0, |p| self.price_key(Side::Ask, p))
+    }
+
+    /// Return the quantity an order would leave unfilled if it was matched up
+    /// to the limit price, without matching it. The quantity is decreased the
+    /// same way as when matching, so that it is only 0 if matching would fill
+    /// the order.
+    fn unfilled_qty(&self, side: Side, qty: f64, limit_price: f64) -> f64 {
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let precision = self.precision(opposite);
+        let mut remaining_qty = qty;
+        for (key, queue) in self.queues(opposite) {
+            let price = (*key as f64) / precision;
+            let worse = match side {
+                Side::Bid => limit_price < price,
+                Side::Ask => limit_price > price,
+            };
+            if worse || remaining_qty == 0.0 {
+                break;
+            }
+            for idx in queue {
+                let order = &self.arena[*idx];
+                let available_qty = order.qty + order.reserve_qty;
+                if order.all_or_none && remaining_qty < available_qty {
+                    continue;
+                }
+                if remaining_qty >= available_qty {
+                    remaining_qty -= available_qty;
+                } else {
+                    remaining_qty = 0.0;
+                }
+            }
+        }
+        remaining_qty
+    }
+
+    /// Return the key the bid levels are scanned down from. The key of the
+    /// best bid is widened by one to absorb its float rounding.
+    fn bid_scan_end(&self) -> u64 {
+        self.max_bid.map_or(u64::MAX, |p| {
+            self.price_key(Side::Bid, p).saturating_add(1)
+        })
+    }
+
+    /// Return the price levels of one side keyed with the given precision, or
+    /// the price of a level whose orders would be merged with another level.
+    fn rekey(
+        &self,
+        side: Side,
+        precision: f64,
+    ) -> Result<BTreeMap<u64, Vec<usize>>, f64> {
+        let book = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let mut rekeyed: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        // The old level each new level was built from
+        let mut sources: BTreeMap<u64, u64> = BTreeMap::new();
+        for (key, queue) in book {
+            for idx in queue {
+                let new_key = (precision * self.arena[*idx].price) as u64;
+                if *sources.entry(new_key).or_insert(*key) != *key {
+                    return Err((*key as f64) / self.precision(side));
+                }
+                rekeyed
+                    .entry(new_key)
+                    .or_insert_with(|| {
+                        Vec::with_capacity(self.default_queue_capacity)
+                    })
+                    .push(*idx);
+            }
+        }
+        Ok(rekeyed)
+    }
+
+    fn update_min_ask(&mut self) {
+        let mut cur_asks = self
+            .asks
+            .range(self.ask_scan_start()..)
+            .filter(|(_, q)| !q.is_empty());
+        self.min_ask = match cur_asks.next() {
+            None => None,
+            Some((p, _)) => Some((*p as f64) / self.ask_precision),
+        };
+    }
+
+    fn update_max_bid(&mut self) {
+        let mut cur_bids = self
+            .bids
+            .range(..=self.bid_scan_end())
+            .rev()
+            .filter(|(_, q)| !q.is_empty());
+        self.max_bid = match cur_bids.next() {
+            None => None,
+            Some((p, _)) => Some((*p as f64) / self.bid_precision),
+        };
+    }
+
+    /// Iterate over the price level keys and queues of one side in price
+    /// priority (best price first).
+    fn queues(
+        &self,
+        side: Side,
+    ) -> Box<dyn Iterator<Item = (&u64, &Vec<usize>)> + '_> {
+        match side {
+            Side::Bid => Box::new(self.bids.iter().rev()),
+            Side::Ask => Box::new(self.asks.iter()),
+        }
+    }
+
+    /// Iterate over the non-empty price levels of one side in price priority
+    /// (best price first), yielding `(price, total_qty)` pairs.
+    fn levels(
+        &self,
+        side: Side,
+    ) -> Box<dyn Iterator<Item = (f64, f64)> + '_> {
+        let precision = self.precision(side);
+        let level = move |(key, queue): (&u64, &Vec<usize>)| {
+            let qty = queue.iter().map(|idx| self.arena[*idx].qty).sum();
+            ((*key as f64) / precision, qty)
+        };
+        match side {
+            Side::Bid => Box::new(
+                self.bids.iter().rev().map(level).filter(|(_, q)| *q > 0.0),
+            ),
+            Side::Ask => Box::new(
+                self.asks.iter().map(level).filter(|(_, q)| *q > 0.0),
+            ),
+        }
+    }
+
+    /// Round a reported quantity to the display precision, if one is set.
+    #[inline(always)]
+    fn display_qty(&self, qty: f64) -> f64 {
+        match self.display_precision {
+            Some(p) => (qty * p).round() / p,
+            None => qty,
+        }
+    }
+
+    /// Round a reported trade price to the precision of the side opposite to
+    /// the taker, if enabled.
+    #[inline(always)]
+    fn trade_price(&self, taker_side: Side, price: f64) -> f64 {
+        if !self.round_trade_prices {
+            return price;
+        }
+        let p = match taker_side {
+            Side::Bid => self.ask_precision,
+            Side::Ask => self.bid_precision,
+        };
+        (price * p).round() / p
+    }
+
+    /// Return the scaling factor used to convert prices of the given side to
+    /// price levels.
+    #[inline(always)]
+    fn precision(&self, side: Side) -> f64 {
+        match side {
+            Side::Bid => self.bid_precision,
+            Side::Ask => self.ask_precision,
+        }
+    }
+
+    /// Convert a price of the given side to the key of its price level.
+    #[inline(always)]
+    fn price_key(&self, side: Side, price: f64) -> u64 {
+        (self.precision(side) * price) as u64
+    }
+
+    /// Convert a price of the given side to the key of the first price level
+    /// at or above it, unlike [`price_key`], which rounds it down.
+    ///
+    /// [`price_key`]: #method.price_key
+    fn price_key_ceil(&self, side: Side, price: f64) -> u64 {
+        let steps = self.precision(side) * price;
+        // Ignore the floating point noise of prices already on the grid, which
+        // are keyed like their levels
+        if (steps - steps.round()).abs() < 1e-9 {
+            self.price_key(side, price)
+        } else {
+            steps.ceil() as u64
+        }
+    }
+
+    /// Record that the price level of the given side changed, if the order
+    /// being executed reports its changed levels.
+    fn touch_level(touched: &mut Option<TouchedLevels>, side: Side, key: u64) {
+        if let Some((asks, bids)) = touched {
+            match side {
+                Side::Ask => asks.insert(key),
+                Side::Bid => bids.insert(key),
+            };
+        }
+    }
+
+    /// Return how much better than the limit price of the taker order a trade
+    /// at `price` happened, or `0.0` if the taker has no limit price.
+    #[inline(always)]
+    fn price_improvement(
+        taker_side: Side,
+        limit_price: Option<f64>,
+        price: f64,
+    ) -> f64 {
+        match (taker_side, limit_price) {
+            (Side::Bid, Some(limit)) => (limit - price).max(0.0),
+            (Side::Ask, Some(limit)) => (price - limit).max(0.0),
+            (_, None) => 0.0,
+        }
+    }
+
+    /// Merge the consecutive fills at the same price.
+    fn aggregate(fills: Vec<FillMetadata>) -> Vec<FillMetadata> {
+        let mut aggregated: Vec<FillMetadata> = Vec::with_capacity(fills.len());
+        for fill in fills {
+            match aggregated.last_mut() {
+                Some(last) if last.price == fill.price => {
+                    last.qty += fill.qty;
+                    last.total_fill = fill.total_fill;
+                }
+                _ => aggregated.push(fill),
+            }
+        }
+        aggregated
+    }
+
+    /// Match the taker against the orders of a price level, front first,
+    /// and return the taker quantity left unfilled.
+    ///
+    /// The unfilled quantity is tracked directly rather than derived from the
+    /// sum of the fills, which can drift from it by a rounding error: the
+    /// drift would let the taker trade a dust quantity on the next level.
+    fn process_queue(
+        arena: &mut OrderArena,
+        opposite_orders: &mut Vec<usize>,
+        remaining_qty: f64,
+        id: u128,
+        side: Side,
+        fills: &mut Vec<FillMetadata>,
+        self_trade: &mut Option<SelfTradeGuard>,
+    ) -> f64 {
+        let mut qty_to_fill = remaining_qty;
+        let mut refilled = Vec::new();
+        let mut halted = false;
+
+        loop {
+            let mut filled_index = None;
+            let mut skipped = false;
+            for (index, head_order_idx) in
+                opposite_orders.iter_mut().enumerate()
+            {
+                if qty_to_fill == 0.0 || halted {
+                    break;
+                }
+                let head_order = &mut arena[*head_order_idx];
+                let traded_price = head_order.price;
+                let maker_ts = head_order.ts;
+                let available_qty = head_order.qty;
+                if available_qty == 0.0 {
+                    filled_index = Some(index);
+                    continue;
+                }
+                if head_order.all_or_none && qty_to_fill < available_qty {
+                    skipped = true;
+                    continue;
+                }
+                if let Some(guard) = self_trade {
+                    if head_order.account == Some(guard.account) {
+                        guard.matches += 1;
+                        if guard.mode != StpMode::CancelNewest {
+                            let canceled_qty =
+                                available_qty + head_order.reserve_qty;
+                            guard.canceled.push((head_order.id, canceled_qty));
+                            head_order.qty = 0.0;
+                            head_order.reserve_qty = 0.0;
+                            filled_index = Some(index);
+                        }
+                        if guard.mode != StpMode::CancelOldest {
+                            guard.taker_canceled = true;
+                            halted = true;
+                        }
+                        continue;
+                    }
+                }
+                let traded_quantity: f64;
+                let mut filled;
+
+                if qty_to_fill >= available_qty {
+                    traded_quantity = available_qty;
+                    qty_to_fill -= available_qty;
+                    head_order.qty = 0.0;
+                    filled_index = Some(index);
+                    filled = true;
+                } else {
+                    traded_quantity = qty_to_fill;
+                    qty_to_fill = 0.0;
+                    head_order.qty = available_qty - traded_quantity;
+                    filled = false;
+                }
+                // Release the next slice of an iceberg order, which is queued
+                // at the back of the level once this pass is done
+                if filled && head_order.reserve_qty > 0.0 {
+                    let slice = head_order.peak_qty.min(head_order.reserve_qty);
+                    head_order.qty = slice;
+                    head_order.reserve_qty -= slice;
+                    refilled.push(*head_order_idx);
+                    filled = false;
+                }
+                let fill: FillMetadata;
+                fill = FillMetadata {
+                    order_1: id,
+                    order_2: head_order.id,
+                    qty: traded_quantity,
+                    price: traded_price,
+                    taker_side: side,
+                    total_fill: filled,
+                    maker_ts,
+                    price_improvement: 0.0,
+                };
+                fills.push(fill);
+            }
+            match filled_index {
+                // Skipped all-or-none orders keep their place in the queue
+                Some(index) if skipped => {
+                    let mut position = 0;
+                    opposite_orders.retain(|idx| {
+                        position += 1;
+                        position > index + 1
+                            || (arena[*idx].qty > 0.0
+                                && !refilled.contains(idx))
+                    });
+                }
+                Some(index) => {
+                    opposite_orders.drain(0..index + 1);
+                }
+                None => {}
+            }
+            if refilled.is_empty() {
+                break;
+            }
+            for idx in &refilled {
+                let ts = arena.next_ts();
+                arena[*idx].ts = ts;
+            }
+            opposite_orders.append(&mut refilled);
+            if halted {
+                break;
+            }
+        }
+
+        qty_to_fill
+    }
+
+    /// Match an order with the best level in the top-order pro-rata mode: the
+    /// first order of the queue is filled first, then the quantity left is
+    /// allocated among the other orders in proportion to their visible
+    /// quantity. Levels that are taken entirely or that hold all-or-none
+    /// orders are matched in time priority instead.
+    fn process_touch_pro_rata(
+        arena: &mut OrderArena,
+        opposite_orders: &mut Vec<usize>,
+        remaining_qty: f64,
+        id: u128,
+        side: Side,
+        fills: &mut Vec<FillMetadata>,
+    ) -> f64 {
+        let orders = || opposite_orders.iter().map(|idx| &arena[*idx]);
+        let level_qty: f64 = orders().map(|order| order.qty).sum();
+        if remaining_qty >= level_qty || orders().any(|o| o.all_or_none) {
+            return Self::process_queue(
+                arena,
+                opposite_orders,
+                remaining_qty,
+                id,
+                side,
+                fills,
+                &mut None,
+            );
+        }
+
+        // The quantity of each order, the top one first
+        let mut allocations = vec![0.0; opposite_orders.len()];
+        let mut qty_to_fill = remaining_qty;
+        if let Some(top) = opposite_orders.first() {
+            allocations[0] = arena[*top].qty.min(qty_to_fill);
+            qty_to_fill -= allocations[0];
+        }
+        let rest = &opposite_orders[1..];
+        let rest_qty: f64 = rest.iter().map(|idx| arena[*idx].qty).sum();
+        let mut allocated = 0.0;
+        for (i, idx) in rest.iter().enumerate() {
+            let qty = arena[*idx].qty;
+            // The last order gets what is left, so that no quantity is lost
+            // to rounding
+            allocations[i + 1] = if i + 1 == rest.len() {
+                (qty_to_fill - allocated).min(qty).max(0.0)
+            } else {
+                qty_to_fill * qty / rest_qty
+            };
+            allocated += allocations[i + 1];
+        }
+
+        let mut queue = Vec::with_capacity(opposite_orders.len());
+        let mut refilled = Vec::new();
+        for (idx, traded_quantity) in opposite_orders.drain(..).zip(allocations)
+        {
+            let order = &mut arena[idx];
+            if traded_quantity > 0.0 {
+                order.qty = (order.qty - traded_quantity).max(0.0);
+                fills.push(FillMetadata {
+                    order_1: id,
+                    order_2: order.id,
+                    qty: traded_quantity,
+                    price: order.price,
+                    taker_side: side,
+                    total_fill: order.qty == 0.0 && order.reserve_qty == 0.0,
+                    maker_ts: order.ts,
+                    price_improvement: 0.0,
+                });
+            }
+            if order.qty > 0.0 {
+                queue.push(idx);
+            } else if order.reserve_qty > 0.0 {
+                // Release the next slice of an iceberg order at the back
+                let slice = order.peak_qty.min(order.reserve_qty);
+                order.qty = slice;
+                order.reserve_qty -= slice;
+                refilled.push(idx);
+            }
+        }
+        for idx in &refilled {
+            let ts = arena.next_ts();
+            arena[*idx].ts = ts;
+        }
+        queue.append(&mut refilled);
+        *opposite_orders = queue;
+
+        // The level holds more than the remaining quantity, so it is filled
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        AuctionResult, Bbo, BookDepth, BookLevel, CancelReport,
+        ExecutionReport, FillMetadata, LimitOrder, MatchingMode, OrderBook,
+        OrderEvent, OrderStatus, OrderType, RejectReason, RestingOrder,
+        SessionSummary, Side, Signals, StpMode, TraceStep, Trade,
+        SYNTHETIC_COUNTERPARTY,
+    };
+    use std::collections::BTreeMap;
+
+    const DEFAULT_QUEUE_SIZE: usize = 10;
+    const BID_ASK_COMBINATIONS: [(Side, Side); 2] =
+        [(Side::Bid, Side::Ask), (Side::Ask, Side::Bid)];
+
+    // In general, floating point values cannot be compared for equality. That's
+    // why we don't derive PartialEq in lobster::models, but we do it here for
+    // our tests in some very specific cases.
+    impl PartialEq for Trade {
+        fn eq(&self, other: &Self) -> bool {
+            self.total_qty == other.total_qty
+                && (self.avg_price - other.avg_price).abs() < 1.0e-6
+                && self.last_qty == other.last_qty
+                && self.last_price == other.last_price
+        }
+    }
+
+    fn init_ob(events: Vec<OrderType>) -> (OrderBook, Vec<OrderEvent>) {
+        let mut ob = OrderBook::default();
+        ob.track_stats(true);
+        let mut results = Vec::new();
+        for e in events {
+            results.push(ob.execute(e));
+        }
+        (ob, results)
+    }
+
+    fn init_book(orders: Vec<(u64, u128)>) -> BTreeMap<u64, Vec<u128>> {
+        let mut bk = BTreeMap::new();
+        for (p, id) in orders {
+            bk.entry(p)
+                .or_insert_with(|| Vec::with_capacity(DEFAULT_QUEUE_SIZE))
+                .push(id);
+        }
+        bk
+    }
+
+    #[test]
+    fn empty_book() {
+        let (ob, results) = init_ob(Vec::new());
+        assert_eq!(results, Vec::new());
+        assert_eq!(ob.min_ask(), None);
+        assert_eq!(ob.max_bid(), None);
+        assert_eq!(ob._asks(), BTreeMap::new());
+        assert_eq!(ob._bids(), BTreeMap::new());
+        assert_eq!(ob.spread(), None);
+        assert_eq!(ob.traded_volume(), 0.0);
+        assert_eq!(
+            ob.depth(2),
+            BookDepth {
+                levels: 2,
+                asks: Vec::new(),
+                bids: Vec::new()
+            }
+        );
+        assert_eq!(ob.last_trade(), None);
+    }
+
+    #[test]
+    fn first_resting_ask_sets_min_ask() {
+        let mut ob = OrderBook::default();
+        ob.execute(OrderType::limit(0, Side::Ask, 1.0, 396.0));
+        assert_eq!(ob.min_ask(), Some(396.0));
+        ob.execute(OrderType::limit(1, Side::Ask, 1.0, 397.0));
+        assert_eq!(ob.min_ask(), Some(396.0));
+        ob.execute(OrderType::limit(2, Side::Ask, 1.0, 395.0));
+        assert_eq!(ob.min_ask(), Some(395.0));
+
+        // The best ask is set again once the side was emptied
+        ob.execute(OrderType::market(3, Side::Bid, 3.0));
+        assert_eq!(ob.min_ask(), None);
+        ob.execute(OrderType::limit(4, Side::Ask, 1.0, 398.0));
+        assert_eq!(ob.min_ask(), Some(398.0));
+    }
+
+    #[test]
+    fn one_resting_order() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (ob, results) = init_ob(vec![OrderType::Limit {
+                id: 0,
+                side: *bid_ask,
+                qty: 12.0,
+                price: 395.0,
+            }]);
+            assert_eq!(results, vec![OrderEvent::Placed { id: 0 }]);
+            if *bid_ask == Side::Bid {
+                assert_eq!(ob.min_ask(), None);
+                assert_eq!(ob.max_bid(), Some(395.0));
+                assert_eq!(ob._asks(), BTreeMap::new());
+                assert_eq!(ob._bids(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob.spread(), None);
+                assert_eq!(ob.traded_volume(), 0.0);
+                assert_eq!(
+                    ob.depth(3),
+                    BookDepth {
+                        levels: 3,
+                        asks: Vec::new(),
+                        bids: vec![BookLevel {
+                            price: 395.0,
+                            qty: 12.0
+                        }],
+                    }
+                );
+                assert_eq!(ob.last_trade(), None);
+            } else {
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob._bids(), BTreeMap::new());
+                assert_eq!(ob.spread(), None);
+                assert_eq!(ob.traded_volume(), 0.0);
+                assert_eq!(
+                    ob.depth(4),
+                    BookDepth {
+                        levels: 4,
+                        asks: vec![BookLevel {
+                            price: 395.0,
+                            qty: 12.0
+                        }],
+                        bids: Vec::new()
+                    }
+                );
+                assert_eq!(ob.last_trade(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn two_resting_orders() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 }
+                    ]
+                );
+                assert_eq!(ob.min_ask(), Some(398.0));
+                assert_eq!(ob.max_bid(), Some(395.0));
+                assert_eq!(ob._asks(), init_book(vec![(39800000000, 1)]));
+                assert_eq!(ob._bids(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob.spread(), Some(3.0));
+                assert_eq!(ob.traded_volume(), 0.0);
+                assert_eq!(
+                    ob.depth(4),
+                    BookDepth {
+                        levels: 4,
+                        asks: vec![BookLevel { price: 398.0, qty: 2.0 }],
+                        bids: vec![BookLevel {
+                            price: 395.0,
+                            qty: 12.0
+                        }],
+                    }
+                );
+                assert_eq!(ob.last_trade(), None);
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 3.0,
+                            }],
+                        }
+                    ]
+                );
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+                assert_eq!(ob.traded_volume(), 2.0);
+                assert_eq!(
+                    ob.depth(4),
+                    BookDepth {
+                        levels: 4,
+                        asks: vec![BookLevel {
+                            price: 395.0,
+                            qty: 10.0,
+                        }],
+                        bids: Vec::new(),
+                    }
+                );
+                assert_eq!(
+                    ob.last_trade(),
+                    Some(Trade {
+                        total_qty: 2.0,
+                        avg_price: 395.0,
+                        last_qty: 2.0,
+                        last_price: 395.0,
+                    })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn two_resting_orders_merged() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 395.0,
+                },
+            ]);
+            assert_eq!(
+                results,
+                vec![
+                    OrderEvent::Placed { id: 0 },
+                    OrderEvent::Placed { id: 1 }
+                ]
+            );
+            if *bid_ask == Side::Bid {
+                assert_eq!(ob.min_ask(), None);
+                assert_eq!(ob.max_bid(), Some(395.0));
+                assert_eq!(ob._asks(), BTreeMap::new());
+                assert_eq!(
+                    ob._bids(),
+                    init_book(vec![(39500000000, 0), (39500000000, 1)])
+                );
+                assert_eq!(ob.spread(), None);
+                assert_eq!(ob.traded_volume(), 0.0);
+                assert_eq!(
+                    ob.depth(3),
+                    BookDepth {
+                        levels: 3,
+                        asks: Vec::new(),
+                        bids: vec![BookLevel {
+                            price: 395.0,
+                            qty: 14.0
+                        }],
+                    }
+                );
+                assert_eq!(ob.last_trade(), None);
+            } else {
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39500000000, 1)])
+                );
+                assert_eq!(ob._bids(), BTreeMap::new());
+                assert_eq!(ob.spread(), None);
+                assert_eq!(ob.traded_volume(), 0.0);
+                assert_eq!(
+                    ob.depth(3),
+                    BookDepth {
+                        levels: 3,
+                        asks: vec![BookLevel {
+                            price: 395.0,
+                            qty: 14.0
+                        }],
+                        bids: Vec::new(),
+                    }
+                );
+                assert_eq!(ob.last_trade(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn two_resting_orders_stacked() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            assert_eq!(
+                results,
+                vec![
+                    OrderEvent::Placed { id: 0 },
+                    OrderEvent::Placed { id: 1 }
+                ]
+            );
+            if *bid_ask == Side::Bid {
+                assert_eq!(ob.min_ask(), None);
+                assert_eq!(ob.max_bid(), Some(398.0));
+                assert_eq!(ob._asks(), BTreeMap::new());
+                assert_eq!(
+                    ob._bids(),
+                    init_book(vec![(39500000000, 0), (39800000000, 1)])
+                );
+                assert_eq!(ob.spread(), None);
+            } else {
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39800000000, 1)])
+                );
+                assert_eq!(ob._bids(), BTreeMap::new());
+                assert_eq!(ob.spread(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn three_resting_orders_stacked() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(ob.min_ask(), Some(399.0));
+                assert_eq!(ob.max_bid(), Some(398.0));
+                assert_eq!(ob._asks(), init_book(vec![(39900000000, 1)]));
+                assert_eq!(
+                    ob._bids(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob.spread(), Some(1.0));
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_limit_order_partial() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            let result = ob.execute(OrderType::Limit {
+                id: 3,
+                side: *ask_bid,
+                qty: 1.0,
+                price: 397.0,
+            });
+
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 1.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 2,
+                            qty: 1.0,
+                            price: 398.0,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 3,
+                            price_improvement: 1.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(399.0));
+                assert_eq!(ob.max_bid(), Some(398.0));
+                assert_eq!(ob._asks(), init_book(vec![(39900000000, 1)]));
+                assert_eq!(
+                    ob._bids(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob.spread(), Some(1.0));
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 1.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 0,
+                            qty: 1.0,
+                            price: 395.0,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 1,
+                            price_improvement: 2.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_limit_order_matching() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            let result = ob.execute(OrderType::Limit {
+                id: 3,
+                side: *ask_bid,
+                qty: 2.0,
+                price: 397.0,
+            });
+
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 2.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 2,
+                            qty: 2.0,
+                            price: 398.0,
+                            taker_side: *ask_bid,
+                            total_fill: true,
+                            maker_ts: 3,
+                            price_improvement: 1.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(399.0));
+                assert_eq!(ob.max_bid(), Some(395.0));
+                assert_eq!(ob._asks(), init_book(vec![(39900000000, 1)]));
+                assert_eq!(ob._bids(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob.spread(), Some(4.0));
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 2.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 0,
+                            qty: 2.0,
+                            price: 395.0,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 1,
+                            price_improvement: 2.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_limit_order_over() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            let result = ob.execute(OrderType::Limit {
+                id: 3,
+                side: *ask_bid,
+                qty: 5.0,
+                price: 397.0,
+            });
+
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::PartiallyFilled {
+                        id: 3,
+                        filled_qty: 2.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 2,
+                            qty: 2.0,
+                            price: 398.0,
+                            taker_side: *ask_bid,
+                            total_fill: true,
+                            maker_ts: 3,
+                            price_improvement: 1.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(397.0));
+                assert_eq!(ob.max_bid(), Some(395.0));
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39700000000, 3), (39900000000, 1)])
+                );
+                assert_eq!(ob._bids(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob.spread(), Some(2.0));
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 5.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 0,
+                            qty: 5.0,
+                            price: 395.0,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 1,
+                            price_improvement: 2.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn market_order_unfilled() {
+        for (_, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            let result = ob.execute(OrderType::Market {
+                id: 0,
+                side: *ask_bid,
+                qty: 5.0,
+            });
+
+            assert_eq!(result, OrderEvent::Unfilled { id: 0 });
+        }
+    }
+
+    #[test]
+    fn market_order_partially_filled() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            let result = ob.execute(OrderType::Market {
+                id: 3,
+                side: *ask_bid,
+                qty: 15.0,
+            });
+
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::PartiallyFilled {
+                        id: 3,
+                        filled_qty: 14.0,
+                        fills: vec![
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 2,
+                                qty: 2.0,
+                                price: 398.0,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 3,
+                                price_improvement: 0.0,
+                            },
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 0,
+                                qty: 12.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 1,
+                                price_improvement: 0.0,
+                            }
+                        ]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(399.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![(39900000000, 1)]));
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::PartiallyFilled {
+                        id: 3,
+                        filled_qty: 12.0,
+                        fills: vec![
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 0,
+                                qty: 10.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 1,
+                                price_improvement: 0.0,
+                            },
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 2,
+                                qty: 2.0,
+                                price: 398.0,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 2,
+                                price_improvement: 0.0,
+                            }
+                        ]
+                    }
+                );
+                assert_eq!(ob.min_ask(), None);
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![]));
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn market_order_partially_filled_floating_points() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.1357,
+                    price: 395.521,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.2345,
+                    price: 399.987,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.789,
+                    price: 398.421,
+                },
+            ]);
+            let result = ob.execute(OrderType::Market {
+                id: 3,
+                side: *ask_bid,
+                qty: 18.931,
+            });
+
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::PartiallyFilled {
+                        id: 3,
+                        filled_qty: 14.9247,
+                        fills: vec![
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 2,
+                                qty: 2.789,
+                                price: 398.421,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 3,
+                                price_improvement: 0.0,
+                            },
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 0,
+                                qty: 12.1357,
+                                price: 395.521,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 1,
+                                price_improvement: 0.0,
+                            }
+                        ]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(399.987));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![(39998700000, 1)]));
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.2345,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.2345,
+                                price: 395.521,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 399.987 - 395.521,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::PartiallyFilled {
+                        id: 3,
+                        filled_qty: 12.6902,
+                        fills: vec![
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 0,
+                                qty: 9.9012,
+                                price: 395.521,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 1,
+                                price_improvement: 0.0,
+                            },
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 2,
+                                qty: 2.789,
+                                price: 398.421,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 2,
+                                price_improvement: 0.0,
+                            }
+                        ]
+                    }
+                );
+                assert_eq!(ob.min_ask(), None);
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![]));
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn market_order_filled() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            let result = ob.execute(OrderType::Market {
+                id: 3,
+                side: *ask_bid,
+                qty: 7.0,
+            });
+
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 7.0,
+                        fills: vec![
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 2,
+                                qty: 2.0,
+                                price: 398.0,
+                                taker_side: *ask_bid,
+                                total_fill: true,
+                                maker_ts: 3,
+                                price_improvement: 0.0,
+                            },
+                            FillMetadata {
+                                order_1: 3,
+                                order_2: 0,
+                                qty: 5.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 0.0,
+                            }
+                        ]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(399.0));
+                assert_eq!(ob.max_bid(), Some(395.0));
+                assert_eq!(ob._asks(), init_book(vec![(39900000000, 1)]));
+                assert_eq!(ob._bids(), init_book(vec![(39500000000, 0)]));
+                assert_eq!(ob.spread(), Some(4.0));
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Filled {
+                        id: 3,
+                        filled_qty: 7.0,
+                        fills: vec![FillMetadata {
+                            order_1: 3,
+                            order_2: 0,
+                            qty: 7.0,
+                            price: 395.0,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 1,
+                            price_improvement: 0.0,
+                        }]
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(395.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(
+                    ob._asks(),
+                    init_book(vec![(39500000000, 0), (39800000000, 2)])
+                );
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn cancel_found() {
+        let canceled = |id, canceled_qty, found| OrderEvent::Canceled {
+            id,
+            canceled_qty,
+            found,
+        };
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Bid, 2.0, 395.0),
+            OrderType::StopMarket {
+                id: 1,
+                side: Side::Ask,
+                qty: 1.0,
+                stop_price: 390.0,
+            },
+            OrderType::limit(2, Side::Ask, 1.0, 396.0),
+            OrderType::market(3, Side::Bid, 1.0),
+        ]);
+        // Resting and dormant orders are found, once
+        assert_eq!(ob.execute(OrderType::cancel(0)), canceled(0, 2.0, true));
+        assert_eq!(ob.execute(OrderType::cancel(0)), canceled(0, 0.0, false));
+        assert_eq!(ob.execute(OrderType::cancel(1)), canceled(1, 1.0, true));
+        // Filled and unknown orders are not
+        assert_eq!(ob.execute(OrderType::cancel(2)), canceled(2, 0.0, false));
+        assert_eq!(ob.execute(OrderType::cancel(9)), canceled(9, 0.0, false));
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn cancel_non_existing_order() {
+        let (mut ob, _) = init_ob(vec![]);
+        let result = ob.execute(OrderType::Cancel { id: 0 });
+        assert_eq!(
+            result,
+            OrderEvent::Canceled {
+                id: 0,
+                canceled_qty: 0.0,
+                found: false
+            }
+        );
+        assert_eq!(ob.min_ask(), None);
+        assert_eq!(ob.max_bid(), None);
+        assert_eq!(ob._asks(), BTreeMap::new());
+        assert_eq!(ob._bids(), BTreeMap::new());
+        assert_eq!(ob.spread(), None);
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let duplicate = |id| OrderEvent::Rejected {
+            id,
+            reason: RejectReason::DuplicateId,
+        };
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
+                OrderType::limit(0, *bid_ask, 12.0, 395.0),
+                OrderType::StopMarket {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 1.0,
+                    stop_price: 390.0,
+                },
+            ]);
+            for order in [
+                OrderType::limit(0, *bid_ask, 1.0, 394.0),
+                OrderType::Iceberg {
+                    id: 0,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 400.0,
+                    peak_qty: 1.0,
+                },
+                OrderType::AllOrNone {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 394.0,
+                },
+            ] {
+                let id = match order {
+                    OrderType::AllOrNone { id, .. } => id,
+                    _ => 0,
+                };
+                assert_eq!(ob.execute(order), duplicate(id));
+            }
+            let book = match *bid_ask {
+                Side::Bid => ob._bids(),
+                Side::Ask => ob._asks(),
+            };
+            assert_eq!(book, init_book(vec![(39500000000, 0)]));
+
+            // The original order is still cancelable, then its ID is free
+            assert_eq!(
+                ob.execute(OrderType::cancel(0)),
+                OrderEvent::Canceled {
+                    id: 0,
+                    canceled_qty: 12.0,
+                    found: true
+                }
+            );
+            assert_eq!(
+                ob.execute(OrderType::limit(0, *bid_ask, 1.0, 394.0)),
+                OrderEvent::Placed { id: 0 }
+            );
+            // So is the ID of a filled order
+            ob.execute(OrderType::market(2, *ask_bid, 1.0));
+            assert_eq!(
+                ob.execute(OrderType::limit(0, *bid_ask, 1.0, 394.0)),
+                OrderEvent::Placed { id: 0 }
+            );
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn cancel_resting_order() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![OrderType::Limit {
+                id: 0,
+                side: *bid_ask,
+                qty: 12.0,
+                price: 395.0,
+            }]);
+            let result = ob.execute(OrderType::Cancel { id: 0 });
+            assert_eq!(results, vec![OrderEvent::Placed { id: 0 }]);
+            assert_eq!(
+                result,
+                OrderEvent::Canceled {
+                    id: 0,
+                    canceled_qty: 12.0,
+                    found: true
+                }
+            );
+            assert_eq!(ob.min_ask(), None);
+            assert_eq!(ob.max_bid(), None);
+            if *bid_ask == Side::Bid {
+                assert_eq!(ob._asks(), BTreeMap::new());
+                assert_eq!(ob._bids(), init_book(vec![]));
+            } else {
+                assert_eq!(ob._asks(), init_book(vec![]));
+                assert_eq!(ob._bids(), BTreeMap::new());
+            }
+            assert_eq!(ob.spread(), None);
+        }
+    }
+
+    #[test]
+    fn empty_levels_removed() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let mut ob = OrderBook::default();
+            ob.execute(OrderType::limit(0, *bid_ask, 1.0, 395.0));
+            ob.execute(OrderType::limit(1, *bid_ask, 1.0, 396.0));
+            ob.execute(OrderType::cancel(0));
+            let book = match *bid_ask {
+                Side::Bid => ob._bids(),
+                Side::Ask => ob._asks(),
+            };
+            assert_eq!(book, init_book(vec![(39600000000, 1)]));
+            assert!(!book.contains_key(&39500000000));
+
+            // Levels drained by a fill are removed as well
+            ob.execute(OrderType::market(2, *ask_bid, 1.0));
+            assert_eq!(ob._asks(), BTreeMap::new());
+            assert_eq!(ob._bids(), BTreeMap::new());
+            assert_eq!(ob.min_ask(), None);
+            assert_eq!(ob.max_bid(), None);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn cancel_resting_order_of_many() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *ask_bid,
+                    qty: 2.0,
+                    price: 399.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 398.0,
+                },
+            ]);
+            let result = ob.execute(OrderType::Cancel { id: 0 });
+            if *bid_ask == Side::Bid {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Placed { id: 1 },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Canceled {
+                        id: 0,
+                        canceled_qty: 12.0,
+                        found: true
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(399.0));
+                assert_eq!(ob.max_bid(), Some(398.0));
+                assert_eq!(ob._asks(), init_book(vec![(39900000000, 1)]));
+                assert_eq!(ob._bids(), init_book(vec![(39800000000, 2)]));
+                assert_eq!(ob.spread(), Some(1.0));
+            } else {
+                assert_eq!(
+                    results,
+                    vec![
+                        OrderEvent::Placed { id: 0 },
+                        OrderEvent::Filled {
+                            id: 1,
+                            filled_qty: 2.0,
+                            fills: vec![FillMetadata {
+                                order_1: 1,
+                                order_2: 0,
+                                qty: 2.0,
+                                price: 395.0,
+                                taker_side: *ask_bid,
+                                total_fill: false,
+                                maker_ts: 1,
+                                price_improvement: 4.0,
+                            }],
+                        },
+                        OrderEvent::Placed { id: 2 }
+                    ]
+                );
+                assert_eq!(
+                    result,
+                    OrderEvent::Canceled {
+                        id: 0,
+                        canceled_qty: 10.0,
+                        found: true
+                    }
+                );
+                assert_eq!(ob.min_ask(), Some(398.0));
+                assert_eq!(ob.max_bid(), None);
+                assert_eq!(ob._asks(), init_book(vec![(39800000000, 2)]));
+                assert_eq!(ob._bids(), init_book(vec![]));
+                assert_eq!(ob.spread(), None);
+            }
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn market_order_crossed_book() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            assert!(!ob.is_crossed());
+            let (best, worse) = if *bid_ask == Side::Bid {
+                (401.0, 398.0)
+            } else {
+                (398.0, 401.0)
+            };
+            ob._rest(0, *ask_bid, 5.0, 400.0);
+            ob._rest(1, *ask_bid, 5.0, 399.0);
+            ob._rest(2, *bid_ask, 2.0, best);
+            ob._rest(3, *bid_ask, 3.0, worse);
+            assert!(ob.is_crossed());
+
+            // The market order sweeps the opposite side in price priority and
+            // ignores the crossing orders on its own side.
+            let result = ob.execute(OrderType::Market {
+                id: 4,
+                side: *ask_bid,
+                qty: 4.0,
+            });
+            assert_eq!(
+                result,
+                OrderEvent::Filled {
+                    id: 4,
+                    filled_qty: 4.0,
+                    fills: vec![
+                        FillMetadata {
+                            order_1: 4,
+                            order_2: 2,
+                            qty: 2.0,
+                            price: best,
+                            taker_side: *ask_bid,
+                            total_fill: true,
+                            maker_ts: 3,
+                            price_improvement: 0.0,
+                        },
+                        FillMetadata {
+                            order_1: 4,
+                            order_2: 3,
+                            qty: 2.0,
+                            price: worse,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 4,
+                            price_improvement: 0.0,
+                        }
+                    ]
+                }
+            );
+            assert_eq!(
+                ob.depth(2),
+                if *bid_ask == Side::Bid {
+                    BookDepth {
+                        levels: 2,
+                        asks: vec![
+                            BookLevel { price: 399.0, qty: 5.0 },
+                            BookLevel { price: 400.0, qty: 5.0 }
+                        ],
+                        bids: vec![BookLevel { price: 398.0, qty: 1.0 }],
+                    }
+                } else {
+                    BookDepth {
+                        levels: 2,
+                        asks: vec![BookLevel { price: 401.0, qty: 1.0 }],
+                        bids: vec![
+                            BookLevel { price: 400.0, qty: 5.0 },
+                            BookLevel { price: 399.0, qty: 5.0 }
+                        ],
+                    }
+                }
+            );
+            // Sweeping through the crossing level uncrosses the book.
+            assert!(!ob.is_crossed());
+        }
+    }
+
+    #[test]
+    fn asymmetric_precision() {
+        let mut ob = OrderBook::with_side_precision(10, 10, 2, 4, false);
+        let results: Vec<OrderEvent> = vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 99.123,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 3.0,
+                price: 99.1234,
+            },
+        ]
+        .into_iter()
+        .map(|o| ob.execute(o))
+        .collect();
+        assert_eq!(
+            results,
+            vec![OrderEvent::Placed { id: 0 }, OrderEvent::Placed { id: 1 }]
+        );
+        assert_eq!(ob._bids(), init_book(vec![(9912, 0)]));
+        assert_eq!(ob._asks(), init_book(vec![(991234, 1)]));
+        assert_eq!(
+            ob.depth(1),
+            BookDepth {
+                levels: 1,
+                asks: vec![BookLevel {
+                    price: 99.1234,
+                    qty: 3.0
+                }],
+                bids: vec![BookLevel {
+                    price: 99.12,
+                    qty: 2.0
+                }],
+            }
+        );
+
+        // The bid level is quoted at 99.12 on its coarser grid, so an ask at
+        // that price crosses it.
+        assert_eq!(
+            ob.execute(OrderType::Limit {
+                id: 2,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 99.12,
+            }),
+            OrderEvent::Filled {
+                id: 2,
+                filled_qty: 1.0,
+                fills: vec![FillMetadata {
+                    order_1: 2,
+                    order_2: 0,
+                    qty: 1.0,
+                    price: 99.123,
+                    taker_side: Side::Ask,
+                    total_fill: false,
+                    maker_ts: 1,
+                    price_improvement: 99.123 - 99.12,
+                }],
+            }
+        );
+        assert_eq!(
+            ob.execute(OrderType::Market {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.0,
+            }),
+            OrderEvent::Filled {
+                id: 3,
+                filled_qty: 1.0,
+                fills: vec![FillMetadata {
+                    order_1: 3,
+                    order_2: 1,
+                    qty: 1.0,
+                    price: 99.1234,
+                    taker_side: Side::Bid,
+                    total_fill: false,
+                    maker_ts: 2,
+                    price_improvement: 0.0,
+                }],
+            }
+        );
+
+        ob.execute(OrderType::Cancel { id: 0 });
+        ob.execute(OrderType::Cancel { id: 1 });
+        assert_eq!(ob._bids(), init_book(vec![]));
+        assert_eq!(ob._asks(), init_book(vec![]));
+        assert_eq!(ob.max_bid(), None);
+        assert_eq!(ob.min_ask(), None);
+    }
+
+    #[test]
+    fn quotes() {
+        let (mut ob, _) = init_ob(vec![]);
+        assert_eq!(ob.quotes(), (None, None));
+        ob.execute(OrderType::Limit {
+            id: 0,
+            side: Side::Bid,
+            qty: 12.0,
+            price: 395.0,
+        });
+        assert_eq!(ob.quotes(), (ob.max_bid(), ob.min_ask()));
+        assert_eq!(ob.quotes(), (Some(395.0), None));
+        ob.execute(OrderType::Limit {
+            id: 1,
+            side: Side::Ask,
+            qty: 2.0,
+            price: 398.0,
+        });
+        assert_eq!(ob.quotes(), (ob.max_bid(), ob.min_ask()));
+        assert_eq!(ob.quotes(), (Some(395.0), Some(398.0)));
+    }
+
+    #[test]
+    fn bbo() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Ask, 1.0, 396.0),
+            OrderType::limit(1, Side::Ask, 2.5, 396.0),
+            OrderType::limit(2, Side::Ask, 4.0, 397.0),
+        ]);
+        assert_eq!(ob.bbo(), None);
+        ob.execute(OrderType::limit(3, Side::Bid, 2.0, 394.0));
+        ob.execute(OrderType::limit(4, Side::Bid, 3.0, 393.0));
+        assert_eq!(
+            ob.bbo(),
+            Some(Bbo {
+                bid_px: 394.0,
+                bid_qty: 2.0,
+                ask_px: 396.0,
+                ask_qty: 3.5,
+            })
+        );
+
+        // Partial fills are reflected, and emptied levels move the prices
+        ob.execute(OrderType::market(5, Side::Bid, 2.0));
+        ob.execute(OrderType::market(6, Side::Ask, 2.0));
+        assert_eq!(
+            ob.bbo(),
+            Some(Bbo {
+                bid_px: 393.0,
+                bid_qty: 3.0,
+                ask_px: 396.0,
+                ask_qty: 1.5,
+            })
+        );
+        ob.execute(OrderType::market(7, Side::Ask, 3.0));
+        assert_eq!(ob.bbo(), None);
+    }
+
+    #[test]
+    fn mid_price() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            assert_eq!(ob.mid_price(), None);
+            ob.execute(OrderType::limit(0, *bid_ask, 1.0, 395.0));
+            assert_eq!(ob.mid_price(), None);
+            let price = match *bid_ask {
+                Side::Bid => 398.0,
+                Side::Ask => 392.0,
+            };
+            ob.execute(OrderType::limit(1, *ask_bid, 1.0, price));
+            assert_eq!(ob.mid_price(), Some((395.0 + price) / 2.0));
+            ob.execute(OrderType::cancel(0));
+            assert_eq!(ob.mid_price(), None);
+        }
+    }
+
+    #[test]
+    fn cumulative_depth() {
+        let (ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 12.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 396.0,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Bid,
+                qty: 3.0,
+                price: 396.0,
+            },
+            OrderType::Limit {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 390.0,
+            },
+            OrderType::Limit {
+                id: 4,
+                side: Side::Ask,
+                qty: 4.0,
+                price: 399.0,
+            },
+            OrderType::Limit {
+                id: 5,
+                side: Side::Ask,
+                qty: 1.5,
+                price: 398.0,
+            },
+        ]);
+        assert_eq!(
+            ob.cumulative_depth(10),
+            (
+                vec![(398.0, 1.5), (399.0, 5.5)],
+                vec![(396.0, 5.0), (395.0, 17.0), (390.0, 18.0)]
+            )
+        );
+        assert_eq!(
+            ob.cumulative_depth(2),
+            (
+                vec![(398.0, 1.5), (399.0, 5.5)],
+                vec![(396.0, 5.0), (395.0, 17.0)]
+            )
+        );
+        assert_eq!(ob.cumulative_depth(0), (vec![], vec![]));
+    }
+
+    #[test]
+    fn notional_depth() {
+        let mut ob = OrderBook::default();
+        for (id, side, qty, price) in [
+            (0, Side::Bid, 12.0, 395.0),
+            (1, Side::Bid, 2.0, 396.0),
+            (2, Side::Bid, 3.0, 396.0),
+            (3, Side::Bid, 1.0, 390.0),
+            (4, Side::Ask, 4.0, 399.0),
+            (5, Side::Ask, 1.5, 398.0),
+        ] {
+            ob.execute(OrderType::limit(id, side, qty, price));
+        }
+        assert_eq!(
+            ob.notional_depth(10),
+            (
+                vec![(398.0, 597.0), (399.0, 2193.0)],
+                vec![(396.0, 1980.0), (395.0, 6720.0), (390.0, 7110.0)]
+            )
+        );
+        let (asks, bids) = ob.notional_depth(2);
+        let depth = ob.depth(2);
+        for (cumulative, levels) in [(asks, depth.asks), (bids, depth.bids)] {
+            let mut total = 0.0;
+            assert_eq!(cumulative.len(), levels.len());
+            for ((price, notional), level) in cumulative.iter().zip(levels) {
+                total += level.price * level.qty;
+                assert_eq!((*price, *notional), (level.price, total));
+            }
+        }
+        assert_eq!(ob.notional_depth(0), (vec![], vec![]));
+    }
+
+    #[test]
+    fn display_precision() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Ask,
+                qty: 1.23456,
+                price: 398.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 399.0,
+            },
+        ]);
+        ob.set_display_precision(Some(2));
+        assert_eq!(
+            ob.depth(2),
+            BookDepth {
+                levels: 2,
+                asks: vec![
+                    BookLevel {
+                        price: 398.0,
+                        qty: 1.23
+                    },
+                    BookLevel {
+                        price: 399.0,
+                        qty: 2.0
+                    }
+                ],
+                bids: Vec::new(),
+            }
+        );
+        assert_eq!(
+            ob.cumulative_depth(2),
+            (vec![(398.0, 1.23), (399.0, 3.23)], vec![])
+        );
+
+        // Matching still consumes the full-precision quantity.
+        let result = ob.execute(OrderType::Market {
+            id: 2,
+            side: Side::Bid,
+            qty: 1.23456,
+        });
+        assert_eq!(
+            result,
+            OrderEvent::Filled {
+                id: 2,
+                filled_qty: 1.23456,
+                fills: vec![FillMetadata {
+                    order_1: 2,
+                    order_2: 0,
+                    qty: 1.23456,
+                    price: 398.0,
+                    taker_side: Side::Bid,
+                    total_fill: true,
+                    maker_ts: 1,
+                    price_improvement: 0.0,
+                }],
+            }
+        );
+        assert_eq!(
+            ob.last_trade(),
+            Some(Trade {
+                total_qty: 1.23,
+                avg_price: 398.0,
+                last_qty: 1.23,
+                last_price: 398.0,
+            })
+        );
+        assert_eq!(ob.traded_volume(), 1.23);
+        assert_eq!(ob.min_ask(), Some(399.0));
+
+        // The stats keep the full-precision quantities
+        ob.set_display_precision(None);
+        assert_eq!(ob.last_trade().unwrap().total_qty, 1.23456);
+        assert_eq!(ob.traded_volume(), 1.23456);
+        ob.execute(OrderType::Market {
+            id: 3,
+            side: Side::Bid,
+            qty: 0.123456,
+        });
+        assert_eq!(
+            ob.depth(1).asks,
+            vec![BookLevel {
+                price: 399.0,
+                qty: 1.876544
+            }]
+        );
+    }
+
+    #[test]
+    fn refresh_priority() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 3.0,
+                    price: 395.0,
+                },
+            ]);
+            assert_eq!(
+                ob.refresh_priority(0),
+                OrderEvent::Amended { id: 0, new_qty: 2.0 }
+            );
+            assert_eq!(
+                ob.refresh_priority(2),
+                OrderEvent::Rejected {
+                    id: 2,
+                    reason: RejectReason::UnknownOrder
+                }
+            );
+            let result = ob.execute(OrderType::Market {
+                id: 2,
+                side: *ask_bid,
+                qty: 4.0,
+            });
+            assert_eq!(
+                result,
+                OrderEvent::Filled {
+                    id: 2,
+                    filled_qty: 4.0,
+                    fills: vec![
+                        FillMetadata {
+                            order_1: 2,
+                            order_2: 1,
+                            qty: 3.0,
+                            price: 395.0,
+                            taker_side: *ask_bid,
+                            total_fill: true,
+                            maker_ts: 2,
+                            price_improvement: 0.0,
+                        },
+                        FillMetadata {
+                            order_1: 2,
+                            order_2: 0,
+                            qty: 1.0,
+                            price: 395.0,
+                            taker_side: *ask_bid,
+                            total_fill: false,
+                            maker_ts: 3,
+                            price_improvement: 0.0,
+                        }
+                    ]
+                }
+            );
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn order_ts() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
+                },
+            ]);
+            let ts_0 = ob.order_ts(0).unwrap();
+            let ts_1 = ob.order_ts(1).unwrap();
+            assert!(ts_0 < ts_1);
+            assert_eq!(ob.order_ts(2), None);
+
+            // The older order fills first and the fill carries its timestamp
+            let result = ob.execute(OrderType::Market {
+                id: 2,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            assert_eq!(
+                result,
+                OrderEvent::Filled {
+                    id: 2,
+                    filled_qty: 1.0,
+                    fills: vec![FillMetadata {
+                        order_1: 2,
+                        order_2: 0,
+                        qty: 1.0,
+                        price: 395.0,
+                        taker_side: *ask_bid,
+                        total_fill: true,
+                        maker_ts: ts_0,
+                        price_improvement: 0.0,
+                    }],
+                }
+            );
+            assert_eq!(ob.order_ts(0), None);
+            assert_eq!(ob.order_ts(1), Some(ts_1));
+
+            // Losing time priority assigns a newer timestamp
+            ob.refresh_priority(1);
+            assert!(ob.order_ts(1).unwrap() > ts_1);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn volume_in_range() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            let prices = [394.0, 395.0, 396.0, 397.0, 398.0];
+            for (id, price) in prices.iter().enumerate() {
+                ob.execute(OrderType::Limit {
+                    id: id as u128,
+                    side: *bid_ask,
+                    qty: (id + 1) as f64,
+                    price: *price,
+                });
+            }
+            // The cap applies to each side separately
+            ob.execute(OrderType::Limit {
+                id: 5,
+                side: *bid_ask,
+                qty: 0.5,
+                price: 396.0,
+            });
+            assert_eq!(ob.volume_in_range(*bid_ask, 394.5, 397.0), 9.5);
+            assert_eq!(ob.volume_in_range(*bid_ask, 396.0, 396.0), 3.5);
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 15.5);
+            assert_eq!(ob.volume_in_range(*bid_ask, 398.5, 400.0), 0.0);
+            assert_eq!(ob.volume_in_range(*bid_ask, 397.0, 394.0), 0.0);
+            assert_eq!(ob.volume_in_range(*ask_bid, 390.0, 400.0), 0.0);
+
+            // Bounds off the grid only include the levels within them
+            let mut ob = OrderBook::new(10, 10, 0, false);
+            ob.execute(OrderType::limit(0, *bid_ask, 1.0, 395.0));
+            ob.execute(OrderType::limit(1, *bid_ask, 2.0, 396.0));
+            ob.execute(OrderType::limit(2, *bid_ask, 4.0, 400.0));
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.5, 400.0), 6.0);
+            assert_eq!(ob.volume_in_range(*bid_ask, 394.5, 399.5), 3.0);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.2, 395.8), 0.0);
+        }
+    }
+
+    #[test]
+    fn qty_at_price() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
+                OrderType::limit(0, *bid_ask, 1.0, 395.0),
+                OrderType::limit(1, *bid_ask, 2.5, 395.0),
+                OrderType::limit(2, *bid_ask, 4.0, 396.0),
+            ]);
+            assert_eq!(ob.qty_at_price(*bid_ask, 395.0), 3.5);
+            assert_eq!(ob.qty_at_price(*bid_ask, 396.0), 4.0);
+            assert_eq!(ob.qty_at_price(*bid_ask, 397.0), 0.0);
+            assert_eq!(ob.qty_at_price(*ask_bid, 395.0), 0.0);
+            ob.execute(OrderType::cancel(0));
+            assert_eq!(ob.qty_at_price(*bid_ask, 395.0), 2.5);
+            ob.execute(OrderType::cancel(1));
+            assert_eq!(ob.qty_at_price(*bid_ask, 395.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn order_count() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (best, worst) = match bid_ask {
+                Side::Bid => (397.0, 395.0),
+                Side::Ask => (395.0, 397.0),
+            };
+            let (mut ob, _) = init_ob(vec![
+                OrderType::limit(0, *bid_ask, 1.0, best),
+                OrderType::limit(1, *bid_ask, 2.0, best),
+                OrderType::limit(2, *bid_ask, 1.0, 396.0),
+                OrderType::limit(3, *bid_ask, 1.0, worst),
+            ]);
+            assert_eq!(ob.order_count(*bid_ask), 4);
+            assert_eq!(ob.price_level_count(*bid_ask), 3);
+            assert_eq!(ob.order_count(*ask_bid), 0);
+            assert_eq!(ob.price_level_count(*ask_bid), 0);
+
+            // Fill the first order of the best level, and part of the second
+            ob.execute(OrderType::limit(4, *ask_bid, 1.5, best));
+            assert_eq!(ob.order_count(*bid_ask), 3);
+            assert_eq!(ob.price_level_count(*bid_ask), 3);
+
+            ob.execute(OrderType::cancel(2));
+            assert_eq!(ob.order_count(*bid_ask), 2);
+            assert_eq!(ob.price_level_count(*bid_ask), 2);
+            assert_eq!(ob.order_count(*ask_bid), 0);
+        }
+    }
+
+    #[test]
+    fn reset_stats() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Ask, 5.0, 396.0),
+            OrderType::limit(1, Side::Bid, 5.0, 394.0),
+        ]);
+        ob.execute(OrderType::market(2, Side::Bid, 2.0));
+        assert_eq!(ob.traded_volume(), 2.0);
+
+        ob.reset_stats();
+        assert_eq!(ob.traded_volume(), 0.0);
+        assert_eq!(ob.last_trade(), None);
+        assert_eq!(ob.resting_volume(Side::Ask), 3.0);
+        assert_eq!(ob.resting_volume(Side::Bid), 5.0);
+
+        // Only the trades of the new interval are counted
+        ob.execute(OrderType::market(3, Side::Ask, 1.5));
+        ob.execute(OrderType::market(4, Side::Bid, 1.0));
+        assert_eq!(ob.traded_volume(), 2.5);
+        assert_eq!(
+            ob.last_trade(),
+            Some(Trade {
+                total_qty: 1.0,
+                avg_price: 396.0,
+                last_price: 396.0,
+                last_qty: 1.0,
+            })
+        );
+    }
+
+    #[test]
+    fn session_summary() {
+        let empty = SessionSummary {
+            traded_volume: 0.0,
+            order_count: 0,
+            fill_count: 0,
+            high: None,
+            low: None,
+            vwap: None,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+        };
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Ask, 2.0, 396.0),
+            OrderType::limit(1, Side::Ask, 2.0, 397.0),
+            OrderType::limit(2, Side::Bid, 3.0, 394.0),
+        ]);
+        assert_eq!(ob.session_summary(), empty);
+
+        ob.execute(OrderType::market(3, Side::Bid, 3.0));
+        ob.execute(OrderType::limit(4, Side::Ask, 1.0, 393.0));
+        ob.execute(OrderType::limit(5, Side::Bid, 1.0, 395.0));
+        ob.execute(OrderType::cancel(5));
+        ob.execute(OrderType::market(6, Side::Ask, 1.0));
+        assert_eq!(
+            ob.session_summary(),
+            SessionSummary {
+                traded_volume: 5.0,
+                order_count: 3,
+                fill_count: 4,
+                high: Some(397.0),
+                low: Some(394.0),
+                vwap: Some((2.0 * 396.0 + 397.0 + 2.0 * 394.0) / 5.0),
+                buy_volume: 3.0,
+                sell_volume: 2.0,
+            }
+        );
+
+        ob.reset_stats();
+        assert_eq!(ob.session_summary(), empty);
+        ob.execute(OrderType::market(7, Side::Ask, 1.0));
+        assert_eq!(ob.session_summary().fill_count, 1);
+        assert_eq!(ob.session_summary().vwap, Some(394.0));
+    }
+
+    #[test]
+    fn iter_orders() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (best, worst) = match bid_ask {
+                Side::Bid => (396.0, 395.0),
+                Side::Ask => (395.0, 396.0),
+            };
+            let (mut ob, _) = init_ob(vec![
+                OrderType::limit(0, *bid_ask, 1.0, worst),
+                OrderType::limit(1, *bid_ask, 1.0, best),
+                OrderType::limit(2, *bid_ask, 2.0, worst),
+                OrderType::limit(3, *bid_ask, 2.0, best),
+            ]);
+            let ids = |ob: &OrderBook| -> Vec<u128> {
+                ob.iter_orders(*bid_ask).map(|order| order.id).collect()
+            };
+            assert_eq!(ids(&ob), vec![1, 3, 0, 2]);
+            assert_eq!(ob.iter_orders(*ask_bid).count(), 0);
+
+            // The filled order is skipped, and the partially filled one keeps
+            // its place with its quantity left
+            ob.execute(OrderType::market(4, *ask_bid, 1.5));
+            assert_eq!(ids(&ob), vec![3, 0, 2]);
+            assert_eq!(
+                ob.iter_orders(*bid_ask).next(),
+                Some(RestingOrder {
+                    id: 3,
+                    side: *bid_ask,
+                    price: best,
+                    qty: 1.5,
+                })
+            );
+
+            // The orders are matched in iteration order
+            let expected = ids(&ob);
+            let event = ob.execute(OrderType::market(5, *ask_bid, 4.5));
+            let matched = match event {
+                OrderEvent::Filled { fills, .. } => {
+                    fills.iter().map(|fill| fill.order_2).collect::<Vec<_>>()
+                }
+                event => panic!("unexpected event {:?}", event),
+            };
+            assert_eq!(matched, expected);
+            assert_eq!(ob.iter_orders(*bid_ask).count(), 0);
+        }
+    }
+
+    #[test]
+    fn resting_volume() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (best, worst) = match bid_ask {
+                Side::Bid => (396.0, 395.0),
+                Side::Ask => (395.0, 396.0),
+            };
+            let (mut ob, _) = init_ob(vec![
+                OrderType::limit(0, *bid_ask, 1.0, best),
+                OrderType::limit(1, *bid_ask, 2.0, best),
+                OrderType::limit(2, *bid_ask, 4.0, worst),
+            ]);
+            assert_eq!(ob.resting_volume(*bid_ask), 7.0);
+            assert_eq!(ob.resting_volume(*ask_bid), 0.0);
+
+            // Fill the first order, and part of the second
+            ob.execute(OrderType::market(3, *ask_bid, 1.5));
+            assert_eq!(ob.resting_volume(*bid_ask), 5.5);
+            assert_eq!(ob.traded_volume(), 1.5);
+
+            ob.execute(OrderType::cancel(2));
+            assert_eq!(ob.resting_volume(*bid_ask), 1.5);
+            ob.execute(OrderType::limit(4, *ask_bid, 2.0, worst));
+            assert_eq!(ob.resting_volume(*bid_ask), 0.0);
+            assert_eq!(ob.resting_volume(*ask_bid), 0.5);
+        }
+    }
+
+    #[test]
+    fn liquidity_within_bps() {
+        let (mut ob, _) = init_ob(vec![]);
+        assert_eq!(ob.liquidity_within_bps(100.0), (0.0, 0.0));
+        let ladder = [
+            (Side::Ask, 1.0, 101.0),
+            (Side::Ask, 2.0, 101.5),
+            (Side::Ask, 4.0, 102.0),
+            (Side::Bid, 1.0, 99.0),
+            (Side::Bid, 2.0, 98.5),
+            (Side::Bid, 3.0, 98.0),
+        ];
+        for (id, (side, qty, price)) in ladder.iter().enumerate() {
+            ob.execute(OrderType::limit(id as u128, *side, *qty, *price));
+            if id == 0 {
+                assert_eq!(ob.liquidity_within_bps(100.0), (0.0, 0.0));
+            }
+        }
+        assert_eq!(ob.liquidity_within_bps(50.0), (0.0, 0.0));
+        assert_eq!(ob.liquidity_within_bps(100.0), (1.0, 1.0));
+        assert_eq!(ob.liquidity_within_bps(150.0), (3.0, 3.0));
+        assert_eq!(ob.liquidity_within_bps(200.0), (7.0, 6.0));
+        assert_eq!(ob.liquidity_within_bps(1_000.0), (7.0, 6.0));
+
+        // The band does not reach the levels past its edges off the grid
+        let mut ob = OrderBook::new(10, 10, 0, false);
+        ob.execute(OrderType::limit(0, Side::Ask, 1.0, 101.0));
+        ob.execute(OrderType::limit(1, Side::Ask, 2.0, 102.0));
+        ob.execute(OrderType::limit(2, Side::Bid, 1.0, 99.0));
+        ob.execute(OrderType::limit(3, Side::Bid, 2.0, 98.0));
+        assert_eq!(ob.liquidity_within_bps(150.0), (1.0, 1.0));
+    }
+
+    #[test]
+    fn invalid_orders() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) =
+                init_ob(vec![OrderType::limit(0, *bid_ask, 1.0, 395.0)]);
+            let invalid = [
+                OrderType::limit(1, *bid_ask, 0.0, 395.0),
+                OrderType::limit(1, *bid_ask, 12.0, -395.0),
+                OrderType::market(1, *bid_ask, f64::NAN),
+                OrderType::limit(1, *bid_ask, 12.0, f64::INFINITY),
+            ];
+            // Transactions are rolled back at the first invalid order
+            let valid = OrderType::limit(2, *bid_ask, 1.0, 394.0);
+            for order in invalid.iter() {
+                assert_eq!(ob.execute_atomic(&[valid, *order]), Err(1));
+            }
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 1.0);
+            assert_eq!(ob.version(), 1);
+
+            // Outside of a transaction, they are executed as given
+            assert_eq!(
+                ob.execute(OrderType::market(3, *bid_ask, 0.0)),
+                OrderEvent::Unfilled { id: 3 }
+            );
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn execute_atomic() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: 394.0,
+                },
+            ]);
+            let asks = ob._asks();
+            let bids = ob._bids();
+            let result = ob.execute_atomic(&[
+                OrderType::Market {
+                    id: 2,
+                    side: *ask_bid,
+                    qty: 2.0,
+                },
+                OrderType::Limit {
+                    id: 3,
+                    side: *bid_ask,
+                    qty: -1.0,
+                    price: 396.0,
+                },
+                OrderType::Cancel { id: 1 },
+            ]);
+            assert_eq!(result, Err(1));
+            assert_eq!(ob._asks(), asks);
+            assert_eq!(ob._bids(), bids);
+            assert_eq!(ob.traded_volume(), 0.0);
+            assert_eq!(ob.last_trade(), None);
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 14.0);
+            assert_eq!((ob.version(), ob.order_ts(1)), (2, Some(2)));
+            ob.audit_arena().unwrap();
+
+            let result = ob.execute_atomic(&[
+                OrderType::Limit {
+                    id: 4,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 394.0,
+                },
+                OrderType::Cancel { id: 1 },
+            ]);
+            assert_eq!(
+                result,
+                Ok(vec![
+                    OrderEvent::Placed { id: 4 },
+                    OrderEvent::Canceled {
+                        id: 1,
+                        canceled_qty: 2.0,
+                        found: true
+                    }
+                ])
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 13.0);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn midpoint_peg() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Bid, 2.0, 395.0),
+            OrderType::limit(1, Side::Ask, 2.0, 397.0),
+        ]);
+        let depth = ob.depth(10);
+        let peg = |id, side, qty| OrderType::MidpointPeg { id, side, qty };
+        assert_eq!(
+            ob.execute(peg(2, Side::Ask, 3.0)),
+            OrderEvent::Placed { id: 2 }
+        );
+        assert_eq!(ob.depth(10), depth);
+        assert_eq!(ob.quotes(), (Some(395.0), Some(397.0)));
+
+        // Orders that would not trade at the mid do not see it
+        assert_eq!(
+            ob.execute(OrderType::limit(3, Side::Bid, 1.0, 395.5)),
+            OrderEvent::Placed { id: 3 }
+        );
+        ob.execute(OrderType::cancel(3));
+
+        // It trades at the mid, ahead of the displayed orders
+        assert_eq!(
+            ob.execute(OrderType::market(4, Side::Bid, 4.0)),
+            OrderEvent::Filled {
+                id: 4,
+                filled_qty: 4.0,
+                fills: vec![
+                    FillMetadata {
+                        order_1: 4,
+                        order_2: 2,
+                        qty: 3.0,
+                        price: 396.0,
+                        taker_side: Side::Bid,
+                        total_fill: true,
+                        maker_ts: 3,
+                        price_improvement: 0.0,
+                    },
+                    FillMetadata {
+                        order_1: 4,
+                        order_2: 1,
+                        qty: 1.0,
+                        price: 397.0,
+                        taker_side: Side::Bid,
+                        total_fill: false,
+                        maker_ts: 2,
+                        price_improvement: 0.0,
+                    },
+                ],
+            }
+        );
+
+        // Pegged orders of both sides match each other
+        ob.execute(peg(5, Side::Bid, 1.0));
+        assert_eq!(
+            ob.execute(peg(6, Side::Ask, 2.0)),
+            OrderEvent::PartiallyFilled {
+                id: 6,
+                filled_qty: 1.0,
+                fills: vec![FillMetadata {
+                    order_1: 6,
+                    order_2: 5,
+                    qty: 1.0,
+                    price: 396.0,
+                    taker_side: Side::Ask,
+                    total_fill: true,
+                    maker_ts: 5,
+                    price_improvement: 0.0,
+                }],
+            }
+        );
+        assert_eq!(
+            ob.depth(10).asks,
+            vec![BookLevel {
+                price: 397.0,
+                qty: 1.0
+            }]
+        );
+
+        // Without a mid price, the displayed orders are matched
+        ob.execute(OrderType::cancel(0));
+        assert_eq!(
+            ob.execute(OrderType::market(7, Side::Bid, 1.0)),
+            OrderEvent::Filled {
+                id: 7,
+                filled_qty: 1.0,
+                fills: vec![FillMetadata {
+                    order_1: 7,
+                    order_2: 1,
+                    qty: 1.0,
+                    price: 397.0,
+                    taker_side: Side::Bid,
+                    total_fill: true,
+                    maker_ts: 2,
+                    price_improvement: 0.0,
+                }],
+            }
+        );
+        assert_eq!(
+            ob.execute(peg(6, Side::Ask, 1.0)),
+            OrderEvent::Rejected {
+                id: 6,
+                reason: RejectReason::DuplicateId
+            }
+        );
+        assert_eq!(
+            ob.execute(OrderType::cancel(6)),
+            OrderEvent::Canceled {
+                id: 6,
+                canceled_qty: 1.0,
+                found: true
+            }
+        );
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn midpoint_peg_ticks() {
+        let mut ob = OrderBook::new(10, 10, 0, false);
+        let peg = |id, side| OrderType::MidpointPeg { id, side, qty: 1.0 };
+        let makers = |event: OrderEvent| match event {
+            OrderEvent::Filled { fills, .. } => fills
+                .iter()
+                .map(|fill| (fill.order_2, fill.price))
+                .collect::<Vec<_>>(),
+            _ => panic!("unexpected event {:?}", event),
+        };
+        ob.execute(OrderType::limit(0, Side::Bid, 5.0, 395.0));
+        ob.execute(OrderType::limit(1, Side::Ask, 1.0, 396.0));
+        ob.execute(peg(2, Side::Ask));
+
+        // The mid price of 395.5 rounds up to the best ask for the pegged ask,
+        // which leaves it no room
+        let event = ob.execute(OrderType::market(3, Side::Bid, 1.0));
+        assert_eq!(makers(event), vec![(1, 396.0)]);
+
+        // The mid price of 396.5 rounds to the tick in favour of the pegs
+        ob.execute(OrderType::limit(4, Side::Ask, 5.0, 398.0));
+        let event = ob.execute(OrderType::market(5, Side::Bid, 1.0));
+        assert_eq!(makers(event), vec![(2, 397.0)]);
+        ob.execute(peg(6, Side::Bid));
+        let ioc = |id, price| OrderType::ImmediateOrCancel {
+            id,
+            side: Side::Ask,
+            qty: 1.0,
+            price,
+        };
+        assert_eq!(makers(ob.execute(ioc(7, 396.0))), vec![(6, 396.0)]);
+
+        // Orders priced exactly at the mid can be kept away from the pegs
+        ob.allow_peg_fills_at_mid(false);
+        ob.execute(peg(8, Side::Bid));
+        assert_eq!(ob.execute(ioc(9, 396.0)), OrderEvent::Unfilled { id: 9 });
+        assert_eq!(makers(ob.execute(ioc(10, 395.0))), vec![(8, 396.0)]);
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn midpoint_peg_self_trade() {
+        let peg = |id, side| OrderType::MidpointPeg { id, side, qty: 1.0 };
+        let canceled = |id, canceled_qty| OrderEvent::Canceled {
+            id,
+            canceled_qty,
+            found: true,
+        };
+        let init = |mode| {
+            let mut ob = OrderBook::default();
+            ob.set_stp_mode(Some(mode));
+            ob.execute(OrderType::limit(0, Side::Bid, 5.0, 395.0));
+            ob.execute(OrderType::limit(1, Side::Ask, 5.0, 397.0));
+            ob.execute_for_account(7, peg(2, Side::Ask));
+            ob
+        };
+
+        // The pegged order of the account is canceled, and the taker matches
+        // the displayed orders
+        let mut ob = init(StpMode::CancelOldest);
+        match ob.execute_for_account(7, OrderType::market(3, Side::Bid, 1.0)) {
+            OrderEvent::Filled { fills, .. } => {
+                assert_eq!((fills[0].order_2, fills[0].price), (1, 397.0))
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(ob.drain_events(), vec![canceled(2, 1.0)]);
+        assert_eq!(
+            ob.execute(OrderType::cancel(2)),
+            OrderEvent::Canceled {
+                id: 2,
+                canceled_qty: 0.0,
+                found: false,
+            }
+        );
+
+        // The taker is canceled, and the pegged order stays
+        let mut ob = init(StpMode::CancelNewest);
+        assert_eq!(
+            ob.execute_for_account(7, OrderType::market(3, Side::Bid, 1.0)),
+            OrderEvent::Unfilled { id: 3 }
+        );
+        assert_eq!(
+            ob.execute_for_account(7, peg(4, Side::Bid)),
+            canceled(4, 0.0)
+        );
+        assert_eq!(ob.drain_events(), vec![]);
+        assert_eq!(ob.execute(OrderType::cancel(2)), canceled(2, 1.0));
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn relative_limit() {
+        let (mut ob, results) = init_ob(vec![OrderType::RelativeLimit {
+            id: 0,
+            side: Side::Bid,
+            qty: 1.0,
+            ticks: 2,
+        }]);
+        assert_eq!(
+            results,
+            vec![OrderEvent::Rejected {
+                id: 0,
+                reason: RejectReason::NoReferencePrice
+            }]
+        );
+
+        ob.execute(OrderType::Limit {
+            id: 1,
+            side: Side::Bid,
+            qty: 1.0,
+            price: 395.0,
+        });
+        ob.execute(OrderType::Limit {
+            id: 2,
+            side: Side::Ask,
+            qty: 1.0,
+            price: 396.25,
+        });
+        assert_eq!(ob.mid_price(), Some(395.625));
+        ob.set_tick_size(Some(0.25));
+        let results: Vec<_> = vec![
+            OrderType::RelativeLimit {
+                id: 3,
+                side: Side::Bid,
+                qty: 2.0,
+                ticks: 1,
+            },
+            OrderType::RelativeLimit {
+                id: 4,
+                side: Side::Ask,
+                qty: 3.0,
+                ticks: 1,
+            },
+        ]
+        .into_iter()
+        .map(|order| ob.execute(order))
+        .collect();
+        assert_eq!(
+            results,
+            vec![OrderEvent::Placed { id: 3 }, OrderEvent::Placed { id: 4 }]
+        );
+        assert_eq!(ob.max_bid(), Some(395.25));
+        assert_eq!(ob.min_ask(), Some(396.0));
+        assert_eq!(ob.volume_in_range(Side::Bid, 395.25, 395.25), 2.0);
+        assert_eq!(ob.volume_in_range(Side::Ask, 396.0, 396.0), 3.0);
+
+        // Negative ticks cross the mid price and match like a limit order.
+        let result = ob.execute(OrderType::RelativeLimit {
+            id: 5,
+            side: Side::Ask,
+            qty: 1.0,
+            ticks: -2,
+        });
+        assert_eq!(
+            result,
+            OrderEvent::Filled {
+                id: 5,
+                filled_qty: 1.0,
+                fills: vec![FillMetadata {
+                    order_1: 5,
+                    order_2: 3,
+                    qty: 1.0,
+                    price: 395.25,
+                    taker_side: Side::Ask,
+                    total_fill: false,
+                    maker_ts: 3,
+                    price_improvement: 0.0,
+                }]
+            }
+        );
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn fill_probability() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Bid, 2.0, 395.0),
+            OrderType::limit(1, Side::Bid, 1.0, 395.0),
+            OrderType::limit(2, Side::Bid, 3.0, 395.0),
+            OrderType::limit(3, Side::Bid, 1.0, 394.0),
+        ]);
+        assert_eq!(ob.fill_probability(1), None);
+
+        ob.execute(OrderType::market(4, Side::Ask, 2.0));
+        assert_eq!(ob.depletion(Side::Bid, 395.0), 2.0);
+        assert_eq!(ob.fill_probability(1), Some((-0.5_f64).exp()));
+        assert_eq!(ob.fill_probability(2), Some((-2.0_f64).exp()));
+        // Filled, unknown and never depleted orders have no estimate
+        assert_eq!(ob.fill_probability(0), None);
+        assert_eq!(ob.fill_probability(99), None);
+        assert_eq!(ob.fill_probability(3), None);
+
+        // Cancels ahead of an order move it forward
+        ob.execute(OrderType::cancel(1));
+        assert_eq!(ob.fill_probability(2), Some((-1.5_f64).exp()));
+    }
+
+    #[test]
+    fn depletion() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (front, back) = match *bid_ask {
+                Side::Bid => (396.0, 395.0),
+                Side::Ask => (395.0, 396.0),
+            };
+            let (mut ob, _) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 12.0,
+                    price: back,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 2.0,
+                    price: back,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 5.0,
+                    price: front,
+                },
+                OrderType::Market {
+                    id: 3,
+                    side: *ask_bid,
+                    qty: 3.0,
+                },
+            ]);
+            assert_eq!(ob.depletion(*bid_ask, front), 3.0);
+            assert_eq!(ob.depletion(*bid_ask, back), 0.0);
+            assert_eq!(ob.depletion(*ask_bid, front), 0.0);
+
+            ob.execute(OrderType::Market {
+                id: 4,
+                side: *ask_bid,
+                qty: 4.0,
+            });
+            assert_eq!(ob.depletion(*bid_ask, front), 5.0);
+            assert_eq!(ob.depletion(*bid_ask, back), 2.0);
+
+            ob.track_stats(false);
+            ob.execute(OrderType::Market {
+                id: 5,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            assert_eq!(ob.depletion(*bid_ask, back), 2.0);
+        }
+    }
+
+    #[test]
+    fn improve_or_reject() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 398.0,
+            },
+        ]);
+        let results: Vec<_> = vec![
+            OrderType::ImproveOrReject {
+                id: 2,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::ImproveOrReject {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::ImproveOrReject {
+                id: 4,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 394.0,
+            },
+            OrderType::ImproveOrReject {
+                id: 5,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::ImproveOrReject {
+                id: 6,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 397.5,
+            },
+        ]
+        .into_iter()
+        .map(|order| ob.execute(order))
+        .collect();
+        assert_eq!(
+            results,
+            vec![
+                OrderEvent::Placed { id: 2 },
+                OrderEvent::Rejected {
+                    id: 3,
+                    reason: RejectReason::WouldNotImprove
+                },
+                OrderEvent::Rejected {
+                    id: 4,
+                    reason: RejectReason::WouldNotImprove
+                },
+                OrderEvent::Rejected {
+                    id: 5,
+                    reason: RejectReason::WouldCross
+                },
+                OrderEvent::Placed { id: 6 },
+            ]
+        );
+        assert_eq!(ob.quotes(), (Some(396.0), Some(397.5)));
+    }
+
+    #[test]
+    fn spread_after() {
+        let (ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 398.0,
+            },
+            OrderType::Limit {
+                id: 3,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 399.5,
+            },
+        ]);
+        let sweep = OrderType::Market {
+            id: 4,
+            side: Side::Bid,
+            qty: 3.0,
+        };
+        assert_eq!(ob.spread_after(sweep), Some(4.5));
+        assert_eq!(ob.spread(), Some(1.0));
+        assert_eq!(ob.volume_in_range(Side::Ask, 390.0, 400.0), 5.0);
+        assert_eq!(ob.traded_volume(), 0.0);
+
+        let quote = OrderType::Limit {
+            id: 4,
+            side: Side::Bid,
+            qty: 1.0,
+            price: 395.5,
+        };
+        assert_eq!(ob.spread_after(quote), Some(0.5));
+        let sell_out = OrderType::Market {
+            id: 4,
+            side: Side::Ask,
+            qty: 1.0,
+        };
+        assert_eq!(ob.spread_after(sell_out), None);
+        assert_eq!(ob.spread(), Some(1.0));
+    }
+
+    #[test]
+    fn freeze() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 396.0,
+            },
+        ]);
+        ob.freeze();
+        assert!(ob.is_frozen());
+        let frozen = |id| OrderEvent::Rejected {
+            id,
+            reason: RejectReason::Frozen,
+        };
+        let order = OrderType::Market {
+            id: 2,
+            side: Side::Bid,
+            qty: 1.0,
+        };
+        assert_eq!(ob.execute(order), frozen(2));
+        assert_eq!(
+            ob.execute(OrderType::Limit {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 394.0,
+            }),
+            frozen(3)
+        );
+        assert_eq!(ob.execute(OrderType::Cancel { id: 0 }), frozen(0));
+        assert_eq!(ob.refresh_priority(0), frozen(0));
+        assert_eq!(ob.quotes(), (Some(395.0), Some(396.0)));
+        assert_eq!(ob.volume_in_range(Side::Bid, 390.0, 400.0), 1.0);
+        assert_eq!(ob.volume_in_range(Side::Ask, 390.0, 400.0), 2.0);
+        assert_eq!(ob.traded_volume(), 0.0);
+
+        ob.allow_cancel_while_frozen(true);
+        assert_eq!(
+            ob.execute(OrderType::Cancel { id: 0 }),
+            OrderEvent::Canceled {
+                id: 0,
+                canceled_qty: 1.0,
+                found: true
+            }
+        );
+        assert_eq!(ob.max_bid(), None);
+        assert_eq!(ob.execute(order), frozen(2));
+
+        ob.unfreeze();
+        assert!(!ob.is_frozen());
+        assert_eq!(
+            ob.execute(order),
+            OrderEvent::Filled {
+                id: 2,
+                filled_qty: 1.0,
+                fills: vec![FillMetadata {
+                    order_1: 2,
+                    order_2: 1,
+                    qty: 1.0,
+                    price: 396.0,
+                    taker_side: Side::Bid,
+                    total_fill: false,
+                    maker_ts: 2,
+                    price_improvement: 0.0,
+                }]
+            }
+        );
+    }
+
+    #[test]
+    fn max_levels() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            ob.set_max_levels(Some(2));
+            // Sorted from the best to the worst price of the side
+            let prices = match *bid_ask {
+                Side::Bid => [397.0, 396.0, 395.0, 394.0],
+                Side::Ask => [394.0, 395.0, 396.0, 397.0],
+            };
+            let resting = [(0, prices[1]), (1, prices[1]), (2, prices[2])];
+            for (id, price) in resting {
+                ob.execute(OrderType::Limit {
+                    id,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price,
+                });
+            }
+            assert_eq!(ob.drain_events(), Vec::new());
+
+            let result = ob.execute(OrderType::Limit {
+                id: 3,
+                side: *bid_ask,
+                qty: 1.0,
+                price: prices[0],
+            });
+            assert_eq!(result, OrderEvent::Placed { id: 3 });
+            assert_eq!(
+                ob.drain_events(),
+                vec![OrderEvent::Canceled {
+                    id: 2,
+                    canceled_qty: 1.0,
+                    found: true
+                }]
+            );
+            assert_eq!(ob.drain_events(), Vec::new());
+
+            // A new worst level is evicted right away
+            let result = ob.execute(OrderType::Limit {
+                id: 4,
+                side: *bid_ask,
+                qty: 1.0,
+                price: prices[3],
+            });
+            assert_eq!(result, OrderEvent::Placed { id: 4 });
+            assert_eq!(
+                ob.drain_events(),
+                vec![OrderEvent::Canceled {
+                    id: 4,
+                    canceled_qty: 1.0,
+                    found: true
+                }]
+            );
+
+            // The cap applies to each side separately
+            ob.execute(OrderType::Limit {
+                id: 5,
+                side: *ask_bid,
+                qty: 1.0,
+                price: match *ask_bid {
+                    Side::Bid => 390.0,
+                    Side::Ask => 400.0,
+                },
+            });
+            assert_eq!(ob.drain_events(), Vec::new());
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 3.0);
+            assert_eq!(ob.volume_in_range(*ask_bid, 390.0, 400.0), 1.0);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn realized_vol() {
+        let (mut ob, _) = init_ob(vec![]);
+        let returns: [f64; 4] = [0.1, -0.1, 0.1, 0.2];
+        let mut price: f64 = 100.0;
+        let trade = |ob: &mut OrderBook, id, price| {
+            ob.execute(OrderType::Limit {
+                id,
+                side: Side::Ask,
+                qty: 1.0,
+                price,
+            });
+            ob.execute(OrderType::Market {
+                id: id + 1,
+                side: Side::Bid,
+                qty: 1.0,
+            });
+        };
+        trade(&mut ob, 0, price);
+        assert_eq!(ob.realized_vol(3), None);
+        for (i, r) in returns.iter().enumerate() {
+            price *= r.exp();
+            trade(&mut ob, 2 * (i as u128 + 1), price);
+        }
+        assert_eq!(ob.realized_vol(2), None);
+        assert_eq!(ob.realized_vol(6), None);
+
+        // Returns -0.1, 0.1, 0.2: mean 1/15, sample variance 7/300
+        let vol = ob.realized_vol(4).unwrap();
+        assert!((vol - (7.0_f64 / 300.0).sqrt()).abs() < 1e-6);
+        let vol = ob.realized_vol(5).unwrap();
+        let mean: f64 = 0.3 / 4.0;
+        let var =
+            returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / 3.0;
+        assert!((vol - var.sqrt()).abs() < 1e-6);
+
+        ob.track_stats(false);
+        trade(&mut ob, 10, price * 2.0);
+        assert!((ob.realized_vol(5).unwrap() - var.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn execute_with_levels() {
+        // Levels whose quantity differs between two depth snapshots, with
+        // their new quantity, sorted by ascending price
+        fn changed(
+            before: &[BookLevel],
+            after: &[BookLevel],
+        ) -> Vec<BookLevel> {
+            let mut levels: BTreeMap<u64, (f64, f64)> = BTreeMap::new();
+            for l in before {
+                levels.entry((l.price * 100.0) as u64).or_default().0 = l.qty;
+            }
+            for l in after {
+                levels.entry((l.price * 100.0) as u64).or_default().1 = l.qty;
+            }
+            levels
+                .into_iter()
+                .filter(|(_, (b, a))| b != a)
+                .map(|(p, (_, qty))| BookLevel {
+                    price: p as f64 / 100.0,
+                    qty,
+                })
+                .collect()
+        }
+
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 397.0,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 399.0,
+            },
+            OrderType::Limit {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+        ]);
+        let orders = vec![
+            OrderType::Limit {
+                id: 4,
+                side: Side::Bid,
+                qty: 4.0,
+                price: 398.0,
+            },
+            OrderType::Cancel { id: 3 },
+            OrderType::Market {
+                id: 5,
+                side: Side::Ask,
+                qty: 0.5,
+            },
+            OrderType::Cancel { id: 3 },
+        ];
+        for order in orders {
+            let before = ob.depth(10);
+            let report = ob.execute_with_levels(order);
+            let after = ob.depth(10);
+            let mut bids = report.bids.clone();
+            bids.reverse();
+            assert_eq!(report.asks, changed(&before.asks, &after.asks));
+            assert_eq!(bids, changed(&before.bids, &after.bids));
+        }
+
+        let report = ob.execute_with_levels(OrderType::Limit {
+            id: 6,
+            side: Side::Bid,
+            qty: 1.0,
+            price: 397.5,
+        });
+        assert_eq!(
+            report,
+            ExecutionReport {
+                event: OrderEvent::Placed { id: 6 },
+                asks: Vec::new(),
+                bids: vec![BookLevel {
+                    price: 397.5,
+                    qty: 1.0,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn good_for_trades() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::GoodForTrades {
+                    id: 0,
                     side: *bid_ask,
-                    qty: 12.0,
+                    qty: 10.0,
+                    price: 395.0,
+                    trades: 2,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
+                },
+                // A cancel leaves no stale expiry behind for a reused ID
+                OrderType::GoodForTrades {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
+                    trades: 1,
+                },
+                OrderType::Cancel { id: 2 },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 1.0,
                     price: 395.0,
                 },
+            ]);
+            assert_eq!(results[0], OrderEvent::Placed { id: 0 });
+
+            ob.execute(OrderType::Market {
+                id: 3,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            assert_eq!(ob.trade_count(), 1);
+            assert_eq!(ob.drain_events(), Vec::new());
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 11.0);
+
+            ob.execute(OrderType::Market {
+                id: 4,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            assert_eq!(ob.trade_count(), 2);
+            assert_eq!(ob.drain_events(), vec![OrderEvent::Expired { id: 0 }]);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 2.0);
+
+            // Fills of the order itself count towards its expiry
+            let result = ob.execute(OrderType::GoodForTrades {
+                id: 5,
+                side: *ask_bid,
+                qty: 3.0,
+                price: 395.0,
+                trades: 1,
+            });
+            assert!(matches!(result, OrderEvent::PartiallyFilled { .. }));
+            assert_eq!(ob.drain_events(), Vec::new());
+            ob.execute(OrderType::Market {
+                id: 6,
+                side: *bid_ask,
+                qty: 0.5,
+            });
+            assert_eq!(ob.drain_events(), vec![OrderEvent::Expired { id: 5 }]);
+            assert_eq!(ob.volume_in_range(*ask_bid, 390.0, 400.0), 0.0);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn fills_for() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            ob.record_tape(true);
+            let other_price = match *ask_bid {
+                Side::Bid => 394.0,
+                Side::Ask => 396.0,
+            };
+            let orders = vec![
                 OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 3.0,
+                    price: 395.0,
+                },
+                OrderType::Market {
                     id: 1,
                     side: *ask_bid,
-                    qty: 2.0,
-                    price: 398.0,
+                    qty: 1.0,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *ask_bid,
+                    qty: 1.0,
+                    price: other_price,
+                },
+                OrderType::Cancel { id: 0 },
+                // Order 0 was canceled, so ID 0 is free to reuse
+                OrderType::Market {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 1.0,
+                },
+            ];
+            for order in orders {
+                ob.execute(order);
+            }
+            let maker = FillMetadata {
+                order_1: 1,
+                order_2: 0,
+                qty: 1.0,
+                price: 395.0,
+                taker_side: *ask_bid,
+                total_fill: false,
+                maker_ts: 1,
+                price_improvement: 0.0,
+            };
+            let taker = FillMetadata {
+                order_1: 0,
+                order_2: 2,
+                qty: 1.0,
+                price: other_price,
+                taker_side: *bid_ask,
+                total_fill: true,
+                maker_ts: 2,
+                price_improvement: 0.0,
+            };
+            assert_eq!(ob.fills_for(0), vec![maker, taker]);
+            assert_eq!(ob.fills_for(2), vec![taker]);
+            assert_eq!(ob.fills_for(3), Vec::new());
+            assert_eq!(ob.tape(), &[maker, taker]);
+
+            ob.record_tape(false);
+            ob.execute(OrderType::Limit {
+                id: 4,
+                side: *bid_ask,
+                qty: 1.0,
+                price: 395.0,
+            });
+            ob.execute(OrderType::Market {
+                id: 5,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            assert_eq!(ob.fills_for(4), Vec::new());
+        }
+    }
+
+    #[test]
+    fn float_overshoot() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            // 0.2 + 0.7 rounds below 0.9, so the remaining quantity derived
+            // from the sum of the fills would be a positive dust
+            let (near, far) = match *bid_ask {
+                Side::Bid => (396.0, 395.0),
+                Side::Ask => (395.0, 396.0),
+            };
+            let (mut ob, _) = init_ob(vec![
+                OrderType::Limit {
+                    id: 0,
+                    side: *bid_ask,
+                    qty: 0.2,
+                    price: near,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 0.7,
+                    price: near,
+                },
+                OrderType::Limit {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: far,
                 },
             ]);
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 }
-                    ]
-                );
-                assert_eq!(ob.min_ask(), Some(398.0));
-                assert_eq!(ob.max_bid(), Some(395.0));
-                assert_eq!(ob._asks(), init_book(vec![(39800000000, 9998)]));
-                assert_eq!(ob._bids(), init_book(vec![(39500000000, 9999)]));
-                assert_eq!(ob.spread(), Some(3.0));
-                assert_eq!(ob.traded_volume(), 0.0);
-                assert_eq!(
-                    ob.depth(4),
-                    BookDepth {
-                        levels: 4,
-                        asks: vec![BookLevel { price: 398.0, qty: 2.0 }],
-                        bids: vec![BookLevel {
-                            price: 395.0,
-                            qty: 12.0
-                        }],
-                    }
-                );
-                assert_eq!(ob.last_trade(), None);
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        }
-                    ]
-                );
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(ob._asks(), init_book(vec![(39500000000, 9999)]));
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
-                assert_eq!(ob.traded_volume(), 2.0);
-                assert_eq!(
-                    ob.depth(4),
-                    BookDepth {
-                        levels: 4,
-                        asks: vec![BookLevel {
-                            price: 395.0,
-                            qty: 10.0,
-                        }],
-                        bids: Vec::new(),
-                    }
-                );
-                assert_eq!(
-                    ob.last_trade(),
-                    Some(Trade {
-                        total_qty: 2.0,
-                        avg_price: 395.0,
-                        last_qty: 2.0,
-                        last_price: 395.0,
+            let result = ob.execute(OrderType::Market {
+                id: 3,
+                side: *ask_bid,
+                qty: 0.9,
+            });
+            match result {
+                OrderEvent::Filled {
+                    filled_qty, fills, ..
+                } => {
+                    assert_eq!(filled_qty, 0.9);
+                    assert_eq!(fills.len(), 2);
+                    assert_eq!(fills[0].qty, 0.2);
+                    assert!(fills[1].qty <= 0.7);
+                }
+                _ => panic!("unexpected event {:?}", result),
+            }
+            assert_eq!(ob.volume_in_range(*bid_ask, far, far), 1.0);
+
+            // 0.3 + 0.6000000000000001 rounds above 0.9: the maker must keep
+            // a positive quantity
+            ob.execute(OrderType::Limit {
+                id: 4,
+                side: *bid_ask,
+                qty: 0.3,
+                price: near,
+            });
+            ob.execute(OrderType::Limit {
+                id: 5,
+                side: *bid_ask,
+                qty: 0.7,
+                price: near,
+            });
+            let result = ob.execute(OrderType::Market {
+                id: 6,
+                side: *ask_bid,
+                qty: 0.9,
+            });
+            assert!(matches!(result, OrderEvent::Filled { .. }));
+            let depth = ob.depth(10);
+            for level in depth.asks.iter().chain(depth.bids.iter()) {
+                assert!(level.qty > 0.0);
+            }
+            let near_qty = ob.volume_in_range(*bid_ask, near, near);
+            assert!(near_qty > 0.0 && near_qty < 0.1 + 1e-9);
+            assert_eq!(ob.volume_in_range(*bid_ask, far, far), 1.0);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn touch_or_cancel() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            // Sorted from the best to the worst price of the side
+            let prices = match *bid_ask {
+                Side::Bid => [396.0, 395.0, 394.0],
+                Side::Ask => [394.0, 395.0, 396.0],
+            };
+            let (mut ob, _) = init_ob(vec![OrderType::Limit {
+                id: 0,
+                side: *bid_ask,
+                qty: 1.0,
+                price: prices[1],
+            }]);
+            let results: Vec<_> = prices
+                .iter()
+                .enumerate()
+                .map(|(i, price)| {
+                    ob.execute(OrderType::TouchOrCancel {
+                        id: i as u128 + 1,
+                        side: *bid_ask,
+                        qty: 1.0,
+                        price: *price,
                     })
-                );
+                })
+                .collect();
+            assert_eq!(
+                results,
+                vec![
+                    OrderEvent::Placed { id: 1 },
+                    OrderEvent::Canceled {
+                        id: 2,
+                        canceled_qty: 1.0,
+                        found: true
+                    },
+                    OrderEvent::Canceled {
+                        id: 3,
+                        canceled_qty: 1.0,
+                        found: true
+                    },
+                ]
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 2.0);
+
+            // The remainder of an order crossing the book rests at the touch
+            let result = ob.execute(OrderType::TouchOrCancel {
+                id: 4,
+                side: *ask_bid,
+                qty: 1.5,
+                price: prices[0],
+            });
+            assert!(matches!(result, OrderEvent::PartiallyFilled { .. }));
+            assert_eq!(ob.volume_in_range(*ask_bid, 390.0, 400.0), 0.5);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn touch_or_cancel_evicted() {
+        let mut ob = OrderBook::default();
+        ob.set_max_levels(Some(1));
+        ob.execute(OrderType::limit(0, Side::Bid, 1.0, 100.0));
+
+        // The order opens a level beyond the cap, which evicts it, and gets
+        // the single cancel event of the eviction
+        assert_eq!(
+            ob.execute(OrderType::TouchOrCancel {
+                id: 1,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 99.0,
+            }),
+            OrderEvent::Canceled {
+                id: 1,
+                canceled_qty: 1.0,
+                found: true,
+            }
+        );
+        assert_eq!(ob.drain_events(), vec![]);
+        assert_eq!(ob.max_bid(), Some(100.0));
+        assert_eq!(ob.order_count(Side::Bid), 1);
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn audit_arena() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 396.0,
+            },
+        ]);
+        assert_eq!(ob.audit_arena(), Ok(()));
+
+        let mut bad = ob.clone();
+        let idx = bad.bids.get_mut(&39500000000).unwrap().pop().unwrap();
+        bad.bids.entry(39400000000).or_default().push(idx);
+        assert!(bad.audit_arena().unwrap_err().contains("priced at 395"));
+
+        let mut bad = ob.clone();
+        let idx = bad.bids.get_mut(&39500000000).unwrap().pop().unwrap();
+        bad.asks.entry(39500000000).or_default().push(idx);
+        assert!(bad.audit_arena().unwrap_err().contains("holds Bid order 0"));
+
+        ob.execute(OrderType::Cancel { id: 0 });
+        let mut bad = ob.clone();
+        bad.bids.entry(39500000000).or_default().push(9999);
+        assert!(bad.audit_arena().unwrap_err().contains("not live"));
+
+        let mut bad = ob.clone();
+        bad.asks.entry(39600000000).or_default().push(usize::MAX);
+        assert!(bad.audit_arena().unwrap_err().contains("out of the arena"));
+    }
+
+    #[test]
+    fn internalized() {
+        let (mut ob, results) = init_ob(vec![OrderType::Internalized {
+            id: 0,
+            side: Side::Bid,
+            qty: 1.0,
+        }]);
+        assert_eq!(
+            results,
+            vec![OrderEvent::Rejected {
+                id: 0,
+                reason: RejectReason::NoReferencePrice
+            }]
+        );
+
+        ob.execute(OrderType::Limit {
+            id: 1,
+            side: Side::Bid,
+            qty: 1.0,
+            price: 395.0,
+        });
+        ob.execute(OrderType::Limit {
+            id: 2,
+            side: Side::Ask,
+            qty: 2.0,
+            price: 397.0,
+        });
+        let result = ob.execute(OrderType::Internalized {
+            id: 3,
+            side: Side::Bid,
+            qty: 5.0,
+        });
+        assert_eq!(
+            result,
+            OrderEvent::Filled {
+                id: 3,
+                filled_qty: 5.0,
+                fills: vec![
+                    FillMetadata {
+                        order_1: 3,
+                        order_2: 2,
+                        qty: 2.0,
+                        price: 397.0,
+                        taker_side: Side::Bid,
+                        total_fill: true,
+                        maker_ts: 2,
+                        price_improvement: 0.0,
+                    },
+                    FillMetadata {
+                        order_1: 3,
+                        order_2: SYNTHETIC_COUNTERPARTY,
+                        qty: 3.0,
+                        price: 396.0,
+                        taker_side: Side::Bid,
+                        total_fill: true,
+                        maker_ts: 0,
+                        price_improvement: 0.0,
+                    },
+                ]
             }
+        );
+        assert_eq!(ob.min_ask(), None);
+        assert_eq!(ob.max_bid(), Some(395.0));
+        assert_eq!(ob.traded_volume(), 5.0);
+    }
+
+    #[test]
+    fn micro_price() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Bid, 1.0, 395.0),
+            OrderType::limit(1, Side::Bid, 2.0, 395.0),
+            OrderType::limit(2, Side::Bid, 5.0, 394.0),
+        ]);
+        assert_eq!(ob.micro_price(), None);
+        ob.execute(OrderType::limit(3, Side::Ask, 1.0, 398.0));
+        ob.execute(OrderType::limit(4, Side::Ask, 7.0, 399.0));
+        // The bid queue is larger, so the price leans towards the ask
+        assert_eq!(ob.micro_price(), Some((395.0 * 1.0 + 398.0 * 3.0) / 4.0));
+        assert_eq!(ob.micro_price(), ob.signals(1).micro_price);
+        ob.execute(OrderType::market(5, Side::Bid, 1.0));
+        assert_eq!(ob.micro_price(), Some((395.0 * 7.0 + 399.0 * 3.0) / 10.0));
+        ob.execute(OrderType::market(6, Side::Ask, 8.0));
+        assert_eq!(ob.micro_price(), None);
+    }
+
+    #[test]
+    fn weighted_mid() {
+        let (mut ob, _) = init_ob(vec![]);
+        assert_eq!(ob.weighted_mid(1), None);
+        let ladder = [
+            (Side::Bid, 1.0, 395.0),
+            (Side::Bid, 3.0, 394.0),
+            (Side::Ask, 3.0, 396.0),
+            (Side::Ask, 1.0, 397.0),
+            (Side::Ask, 4.0, 398.0),
+        ];
+        for (id, (side, qty, price)) in ladder.iter().enumerate() {
+            ob.execute(OrderType::Limit {
+                id: id as u128,
+                side: *side,
+                qty: *qty,
+                price: *price,
+            });
         }
+        // Micro-price: (395 * 3 + 396 * 1) / (1 + 3)
+        assert_eq!(ob.weighted_mid(1), Some(395.25));
+        // Bid VWAP 394.25 for 4, ask VWAP 396.25 for 4
+        assert_eq!(ob.weighted_mid(2), Some(395.25));
+        // Bid VWAP 394.25 for 4, ask VWAP 397.125 for 8
+        let expected = (394.25 * 8.0 + 397.125 * 4.0) / 12.0;
+        assert!((ob.weighted_mid(3).unwrap() - expected).abs() < 1e-9);
+        assert_eq!(ob.weighted_mid(0), None);
+    }
+
+    #[test]
+    fn market_if_touched() {
+        let (mut ob, results) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 396.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 393.0,
+            },
+            OrderType::MarketIfTouched {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.5,
+                trigger_price: 394.0,
+            },
+            OrderType::MarketIfTouched {
+                id: 4,
+                side: Side::Ask,
+                qty: 1.0,
+                trigger_price: 400.0,
+            },
+        ]);
+        assert_eq!(results[3], OrderEvent::Placed { id: 3 });
+        assert_eq!(results[4], OrderEvent::Placed { id: 4 });
+
+        // A trade above the trigger leaves the buy order dormant
+        ob.execute(OrderType::Market {
+            id: 5,
+            side: Side::Ask,
+            qty: 1.0,
+        });
+        assert_eq!(ob.drain_events(), Vec::new());
+
+        // The market falls through the trigger
+        ob.execute(OrderType::Market {
+            id: 6,
+            side: Side::Ask,
+            qty: 1.0,
+        });
+        assert_eq!(
+            ob.drain_events(),
+            vec![OrderEvent::Filled {
+                id: 3,
+                filled_qty: 1.5,
+                fills: vec![FillMetadata {
+                    order_1: 3,
+                    order_2: 0,
+                    qty: 1.5,
+                    price: 396.0,
+                    taker_side: Side::Bid,
+                    total_fill: false,
+                    maker_ts: 1,
+                    price_improvement: 0.0,
+                }]
+            }]
+        );
+        assert_eq!(ob.volume_in_range(Side::Ask, 396.0, 396.0), 0.5);
+
+        // Dormant orders can be canceled
+        ob.execute(OrderType::Cancel { id: 4 });
+        ob.execute(OrderType::Limit {
+            id: 7,
+            side: Side::Ask,
+            qty: 1.0,
+            price: 400.0,
+        });
+        let result = ob.execute(OrderType::Market {
+            id: 8,
+            side: Side::Bid,
+            qty: 1.5,
+        });
+        assert!(matches!(result, OrderEvent::Filled { .. }));
+        assert_eq!(ob.drain_events(), Vec::new());
+        ob.audit_arena().unwrap();
     }
 
     #[test]
-    fn two_resting_orders_merged() {
-        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
-            let (ob, results) = init_ob(vec![
+    fn reduce_qty() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
                 OrderType::Limit {
                     id: 0,
                     side: *bid_ask,
-                    qty: 12.0,
+                    qty: 3.0,
                     price: 395.0,
                 },
                 OrderType::Limit {
@@ -784,993 +7900,2620 @@ mod test {
                 },
             ]);
             assert_eq!(
-                results,
-                vec![
-                    OrderEvent::Placed { id: 0 },
-                    OrderEvent::Placed { id: 1 }
-                ]
+                ob.reduce_qty(0, 1.0),
+                OrderEvent::Amended {
+                    id: 0,
+                    new_qty: 1.0
+                }
             );
-            if *bid_ask == Side::Bid {
-                assert_eq!(ob.min_ask(), None);
-                assert_eq!(ob.max_bid(), Some(395.0));
-                assert_eq!(ob._asks(), BTreeMap::new());
-                assert_eq!(
-                    ob._bids(),
-                    init_book(vec![(39500000000, 9999), (39500000000, 9998)])
-                );
-                assert_eq!(ob.spread(), None);
-                assert_eq!(ob.traded_volume(), 0.0);
-                assert_eq!(
-                    ob.depth(3),
-                    BookDepth {
-                        levels: 3,
-                        asks: Vec::new(),
-                        bids: vec![BookLevel {
-                            price: 395.0,
-                            qty: 14.0
-                        }],
-                    }
-                );
-                assert_eq!(ob.last_trade(), None);
-            } else {
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39500000000, 9999), (39500000000, 9998)])
-                );
-                assert_eq!(ob._bids(), BTreeMap::new());
-                assert_eq!(ob.spread(), None);
-                assert_eq!(ob.traded_volume(), 0.0);
-                assert_eq!(
-                    ob.depth(3),
-                    BookDepth {
-                        levels: 3,
-                        asks: vec![BookLevel {
-                            price: 395.0,
-                            qty: 14.0
-                        }],
-                        bids: Vec::new(),
-                    }
-                );
-                assert_eq!(ob.last_trade(), None);
+            let rejected = |id, reason| OrderEvent::Rejected { id, reason };
+            assert_eq!(
+                ob.reduce_qty(0, 1.5),
+                rejected(0, RejectReason::InvalidQty)
+            );
+            assert_eq!(
+                ob.reduce_qty(0, 0.0),
+                rejected(0, RejectReason::InvalidQty)
+            );
+            assert_eq!(
+                ob.reduce_qty(2, 1.0),
+                rejected(2, RejectReason::UnknownOrder)
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 3.0);
+
+            // Order 0 is still first in the queue
+            let result = ob.execute(OrderType::Market {
+                id: 2,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            match result {
+                OrderEvent::Filled { fills, .. } => {
+                    assert_eq!(fills.len(), 1);
+                    assert_eq!(fills[0].order_2, 0);
+                    assert!(fills[0].total_fill);
+                }
+                _ => panic!("unexpected event {:?}", result),
             }
+            assert_eq!(
+                ob.reduce_qty(0, 1.0),
+                rejected(0, RejectReason::UnknownOrder)
+            );
+            ob.audit_arena().unwrap();
         }
     }
 
     #[test]
-    fn two_resting_orders_stacked() {
-        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
-            let (ob, results) = init_ob(vec![
+    fn zero_id() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
                 OrderType::Limit {
                     id: 0,
                     side: *bid_ask,
-                    qty: 12.0,
+                    qty: 2.0,
                     price: 395.0,
                 },
+                OrderType::Cancel { id: 0 },
                 OrderType::Limit {
-                    id: 1,
+                    id: 0,
                     side: *bid_ask,
                     qty: 2.0,
-                    price: 398.0,
+                    price: 395.0,
                 },
             ]);
             assert_eq!(
                 results,
                 vec![
                     OrderEvent::Placed { id: 0 },
-                    OrderEvent::Placed { id: 1 }
+                    OrderEvent::Canceled {
+                        id: 0,
+                        canceled_qty: 2.0,
+                        found: true
+                    },
+                    OrderEvent::Placed { id: 0 },
                 ]
             );
-            if *bid_ask == Side::Bid {
-                assert_eq!(ob.min_ask(), None);
-                assert_eq!(ob.max_bid(), Some(398.0));
-                assert_eq!(ob._asks(), BTreeMap::new());
-                assert_eq!(
-                    ob._bids(),
-                    init_book(vec![(39800000000, 9998), (39500000000, 9999)])
-                );
-                assert_eq!(ob.spread(), None);
-            } else {
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39800000000, 9998), (39500000000, 9999)])
-                );
-                assert_eq!(ob._bids(), BTreeMap::new());
-                assert_eq!(ob.spread(), None);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 2.0);
+            assert_eq!(
+                ob.reduce_qty(0, 1.0),
+                OrderEvent::Amended {
+                    id: 0,
+                    new_qty: 1.0
+                }
+            );
+            let result = ob.execute(OrderType::Market {
+                id: 1,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            match result {
+                OrderEvent::Filled { fills, .. } => {
+                    assert_eq!(fills[0].order_2, 0)
+                }
+                _ => panic!("unexpected event {:?}", result),
             }
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 0.0);
+            ob.audit_arena().unwrap();
         }
     }
 
     #[test]
-    fn three_resting_orders_stacked() {
+    fn version() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (ob, results) = init_ob(vec![
+            let mut ob = OrderBook::default();
+            assert_eq!(ob.version(), 0);
+            ob.execute(OrderType::Limit {
+                id: 0,
+                side: *bid_ask,
+                qty: 2.0,
+                price: 395.0,
+            });
+            assert_eq!(ob.version(), 1);
+            ob.execute(OrderType::Limit {
+                id: 1,
+                side: *bid_ask,
+                qty: 2.0,
+                price: 395.0,
+            });
+            assert_eq!(ob.version(), 2);
+            ob.execute(OrderType::Market {
+                id: 2,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            assert_eq!(ob.version(), 3);
+            ob.reduce_qty(1, 1.0);
+            assert_eq!(ob.version(), 4);
+            ob.refresh_priority(0);
+            assert_eq!(ob.version(), 5);
+            ob.freeze();
+            assert_eq!(ob.version(), 6);
+            ob.execute(OrderType::Cancel { id: 0 });
+            ob.freeze();
+            assert_eq!(ob.version(), 6);
+            ob.unfreeze();
+            assert_eq!(ob.version(), 7);
+            ob.execute(OrderType::Cancel { id: 0 });
+            assert_eq!(ob.version(), 8);
+            ob.execute(OrderType::Cancel { id: 42 });
+            ob.execute(OrderType::Market {
+                id: 4,
+                side: *bid_ask,
+                qty: 1.0,
+            });
+            assert_eq!(ob.version(), 8);
+
+            ob.execute(OrderType::Limit {
+                id: 1,
+                side: *bid_ask,
+                qty: 1.0,
+                price: 395.0,
+            });
+            ob.reduce_qty(1, 5.0);
+            ob.refresh_priority(0);
+            ob.min_ask();
+            ob.max_bid();
+            ob.depth(10);
+            ob.mid_price();
+            ob.spread_after(OrderType::Market {
+                id: 3,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            ob.drain_events();
+            assert_eq!(ob.version(), 8);
+        }
+    }
+
+    #[test]
+    fn cancel_partially_filled_order() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
                 OrderType::Limit {
                     id: 0,
                     side: *bid_ask,
                     qty: 12.0,
                     price: 395.0,
                 },
-                OrderType::Limit {
+                OrderType::Market {
                     id: 1,
                     side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
-                },
-                OrderType::Limit {
-                    id: 2,
-                    side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
+                    qty: 4.5,
                 },
             ]);
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(ob.min_ask(), Some(399.0));
-                assert_eq!(ob.max_bid(), Some(398.0));
-                assert_eq!(ob._asks(), init_book(vec![(39900000000, 9998)]));
-                assert_eq!(
-                    ob._bids(),
-                    init_book(vec![(39800000000, 9997), (39500000000, 9999)])
-                );
-                assert_eq!(ob.spread(), Some(1.0));
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39800000000, 9998), (39500000000, 9999)])
-                );
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
+            let result = ob.execute(OrderType::Cancel { id: 0 });
+            assert_eq!(
+                result,
+                OrderEvent::Canceled {
+                    id: 0,
+                    canceled_qty: 7.5,
+                    found: true
+                }
+            );
+            let result = ob.execute(OrderType::Cancel { id: 0 });
+            assert_eq!(
+                result,
+                OrderEvent::Canceled {
+                    id: 0,
+                    canceled_qty: 0.0,
+                    found: false
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn canonical_string() {
+        let orders = vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 12.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 398.5,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Bid,
+                qty: 3.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 3,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 399.0,
+            },
+            OrderType::Limit {
+                id: 4,
+                side: Side::Ask,
+                qty: 1.5,
+                price: 401.25,
+            },
+            OrderType::Market {
+                id: 5,
+                side: Side::Ask,
+                qty: 3.0,
+            },
+            OrderType::Cancel { id: 4 },
+        ];
+        let expected = "Ask 399 3 2\nBid 395 0 11\nBid 395 2 3\n";
+        for arena_capacity in &[0, 1, 10_000] {
+            let mut ob = OrderBook::new(*arena_capacity, 10, 8, false);
+            for order in &orders {
+                ob.execute(*order);
+            }
+            assert_eq!(ob.canonical_string(), expected);
+            assert_eq!(ob._asks(), init_book(vec![(39900000000, 3)]));
+            assert_eq!(
+                ob._bids(),
+                init_book(vec![(39500000000, 0), (39500000000, 2)])
+            );
+        }
+        assert_eq!(OrderBook::default().canonical_string(), "");
+    }
+
+    #[test]
+    fn order_type_constructors() {
+        let (mut ob, results) = init_ob(vec![
+            OrderType::limit(0, Side::Bid, 2.0, 395.0),
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+        ]);
+        assert_eq!(
+            results,
+            vec![OrderEvent::Placed { id: 0 }, OrderEvent::Placed { id: 1 }]
+        );
+        let literal = ob.clone().execute(OrderType::Market {
+            id: 2,
+            side: Side::Ask,
+            qty: 1.0,
+        });
+        assert_eq!(ob.execute(OrderType::market(2, Side::Ask, 1.0)), literal);
+        assert_eq!(
+            ob.execute(OrderType::cancel(1)),
+            OrderEvent::Canceled {
+                id: 1,
+                canceled_qty: 1.0,
+                found: true
             }
+        );
+        assert_eq!(ob.canonical_string(), "Bid 395 0 1\n");
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 12.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 398.5,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Bid,
+                qty: 3.0,
+                price: 395.0,
+            },
+            OrderType::Iceberg {
+                id: 3,
+                side: Side::Ask,
+                qty: 5.0,
+                price: 399.0,
+                peak_qty: 2.0,
+            },
+            OrderType::Limit {
+                id: 4,
+                side: Side::Ask,
+                qty: 1.5,
+                price: 401.25,
+            },
+            OrderType::Market {
+                id: 5,
+                side: Side::Ask,
+                qty: 3.0,
+            },
+        ]);
+        let mut restored = OrderBook::restore(ob.snapshot());
+        assert_eq!(restored.depth(10), ob.depth(10));
+        assert_eq!(restored.quotes(), ob.quotes());
+        assert_eq!(restored.quotes(), (Some(395.0), Some(399.0)));
+        assert_eq!(restored.canonical_string(), ob.canonical_string());
+        assert_eq!(restored.last_trade(), ob.last_trade());
+        assert_eq!(restored.traded_volume(), 3.0);
+        assert_eq!(restored.version(), ob.version());
+        restored.audit_arena().unwrap();
+
+        // The restored order book keeps matching like the original one
+        for order in &[
+            OrderType::Limit {
+                id: 6,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 398.0,
+            },
+            OrderType::Market {
+                id: 7,
+                side: Side::Bid,
+                qty: 4.0,
+            },
+            OrderType::Market {
+                id: 8,
+                side: Side::Ask,
+                qty: 12.0,
+            },
+        ] {
+            assert_eq!(restored.execute(*order), ob.execute(*order));
+        }
+        assert_eq!(restored.canonical_string(), ob.canonical_string());
+        restored.audit_arena().unwrap();
+
+        let restored = OrderBook::restore(OrderBook::default().snapshot());
+        assert_eq!(restored.quotes(), (None, None));
+        assert_eq!(restored.depth(1), OrderBook::default().depth(1));
+    }
+
+    #[test]
+    fn merge() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 399.0,
+            },
+        ]);
+        let (other, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 10,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 394.0,
+            },
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 390.0,
+            },
+            OrderType::Limit {
+                id: 11,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 400.0,
+            },
+            OrderType::Limit {
+                id: 12,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 395.0,
+            },
+        ]);
+        assert_eq!(
+            ob.merge(other),
+            vec![
+                OrderEvent::Filled {
+                    id: 12,
+                    filled_qty: 1.0,
+                    fills: vec![FillMetadata {
+                        order_1: 12,
+                        order_2: 0,
+                        qty: 1.0,
+                        price: 395.0,
+                        taker_side: Side::Ask,
+                        total_fill: false,
+                        maker_ts: 1,
+                        price_improvement: 0.0,
+                    }],
+                },
+                OrderEvent::Placed { id: 11 },
+                OrderEvent::Placed { id: 10 },
+                OrderEvent::Rejected {
+                    id: 0,
+                    reason: RejectReason::DuplicateId,
+                },
+            ]
+        );
+        assert_eq!(
+            ob.canonical_string(),
+            "Ask 399 1 2\nAsk 400 11 2\nBid 395 0 1\nBid 394 10 2\n"
+        );
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn liquidation_value() {
+        let (mut ob, _) = init_ob(vec![]);
+        assert_eq!(ob.liquidation_value(Side::Ask, 1.0), (0.0, 0.0));
+        let ladder = [
+            (Side::Bid, 1.0, 395.0),
+            (Side::Bid, 3.0, 394.0),
+            (Side::Bid, 2.0, 393.0),
+            (Side::Ask, 2.0, 396.0),
+            (Side::Ask, 2.0, 398.0),
+        ];
+        for (id, (side, qty, price)) in ladder.iter().enumerate() {
+            ob.execute(OrderType::Limit {
+                id: id as u128,
+                side: *side,
+                qty: *qty,
+                price: *price,
+            });
         }
+        let proceeds = 395.0 * 1.0 + 394.0 * 3.0 + 393.0 * 1.0;
+        assert_eq!(
+            ob.liquidation_value(Side::Ask, 5.0),
+            (proceeds, proceeds / 5.0)
+        );
+        assert_eq!(ob.liquidation_value(Side::Ask, 5.0).1, 394.0);
+        let cost = 396.0 * 2.0 + 398.0 * 1.0;
+        assert_eq!(ob.liquidation_value(Side::Bid, 3.0), (cost, cost / 3.0));
+
+        // The book cannot absorb the full quantity
+        let proceeds = 395.0 * 1.0 + 394.0 * 3.0 + 393.0 * 2.0;
+        assert_eq!(
+            ob.liquidation_value(Side::Ask, 10.0),
+            (proceeds, proceeds / 6.0)
+        );
+        assert_eq!(ob.volume_in_range(Side::Bid, 393.0, 395.0), 6.0);
     }
 
     #[test]
-    fn crossing_limit_order_partial() {
+    fn vwap_for_qty() {
+        let (mut ob, _) = init_ob(vec![]);
+        assert_eq!(ob.vwap_for_qty(Side::Bid, 1.0), None);
+        let ladder = [
+            (Side::Bid, 1.0, 395.0),
+            (Side::Bid, 3.0, 394.0),
+            (Side::Ask, 2.0, 396.0),
+            (Side::Ask, 1.0, 397.0),
+            (Side::Ask, 2.0, 398.0),
+        ];
+        for (id, (side, qty, price)) in ladder.iter().enumerate() {
+            ob.execute(OrderType::limit(id as u128, *side, *qty, *price));
+        }
+        assert_eq!(ob.vwap_for_qty(Side::Bid, 1.0), Some((396.0, 1.0)));
+        assert_eq!(ob.vwap_for_qty(Side::Bid, 4.0), Some((396.75, 4.0)));
+        assert_eq!(ob.vwap_for_qty(Side::Ask, 2.0), Some((394.5, 2.0)));
+        assert_eq!(ob.vwap_for_qty(Side::Bid, 0.0), None);
+
+        // The book cannot absorb the full quantity
+        assert_eq!(ob.vwap_for_qty(Side::Bid, 10.0), Some((397.0, 5.0)));
+        assert_eq!(ob.vwap_for_qty(Side::Ask, 10.0), Some((394.25, 4.0)));
+
+        // Nothing was executed
+        assert_eq!(ob.depth(10).asks.len(), 3);
+        assert_eq!(ob.depth(10).bids.len(), 2);
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn taker_side() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![
+            let (mut ob, _) = init_ob(vec![
                 OrderType::Limit {
                     id: 0,
                     side: *bid_ask,
-                    qty: 12.0,
+                    qty: 1.0,
                     price: 395.0,
                 },
                 OrderType::Limit {
                     id: 1,
-                    side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
-                },
-                OrderType::Limit {
-                    id: 2,
                     side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
+                    qty: 1.0,
+                    price: 395.0,
                 },
             ]);
-            let result = ob.execute(OrderType::Limit {
-                id: 3,
-                side: *ask_bid,
-                qty: 1.0,
-                price: 397.0,
-            });
-
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
+            let aggressive_price = match ask_bid {
+                Side::Ask => 390.0,
+                Side::Bid => 400.0,
+            };
+            for (id, price) in [(2, 395.0), (3, aggressive_price)].iter() {
+                let result = ob.execute(OrderType::Limit {
+                    id: *id,
+                    side: *ask_bid,
+                    qty: 1.0,
+                    price: *price,
+                });
                 assert_eq!(
                     result,
                     OrderEvent::Filled {
-                        id: 3,
+                        id: *id,
                         filled_qty: 1.0,
                         fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 2,
+                            order_1: *id,
+                            order_2: id - 2,
                             qty: 1.0,
-                            price: 398.0,
+                            price: 395.0,
                             taker_side: *ask_bid,
-                            total_fill: false,
-                        }]
+                            total_fill: true,
+                            maker_ts: *id as u64 - 1,
+                            price_improvement: (price - 395.0).abs(),
+                        }],
                     }
                 );
-                assert_eq!(ob.min_ask(), Some(399.0));
-                assert_eq!(ob.max_bid(), Some(398.0));
-                assert_eq!(ob._asks(), init_book(vec![(39900000000, 9998)]));
-                assert_eq!(
-                    ob._bids(),
-                    init_book(vec![(39800000000, 9997), (39500000000, 9999)])
-                );
-                assert_eq!(ob.spread(), Some(1.0));
-            } else {
+            }
+        }
+    }
+
+    #[test]
+    fn price_improvement() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Ask, 1.0, 396.0),
+            OrderType::limit(1, Side::Ask, 1.0, 397.0),
+            OrderType::limit(2, Side::Bid, 1.0, 394.0),
+            OrderType::MidpointPeg {
+                id: 3,
+                side: Side::Ask,
+                qty: 1.0,
+            },
+        ]);
+        let improvements = |event: OrderEvent| -> Vec<f64> {
+            match event {
+                OrderEvent::Filled { fills, .. }
+                | OrderEvent::PartiallyFilled { fills, .. } => fills
+                    .iter()
+                    .map(|fill| fill.price_improvement)
+                    .collect(),
+                event => panic!("unexpected event {:?}", event),
+            }
+        };
+        // A marketable limit improved by the peg at the mid and both levels
+        let event = ob.execute(OrderType::limit(4, Side::Bid, 3.0, 398.0));
+        assert_eq!(improvements(event), vec![3.0, 2.0, 1.0]);
+
+        // No improvement at the limit price, nor without a limit price
+        ob.execute(OrderType::limit(5, Side::Ask, 2.0, 399.0));
+        let event = ob.execute(OrderType::ImmediateOrCancel {
+            id: 6,
+            side: Side::Bid,
+            qty: 1.0,
+            price: 399.0,
+        });
+        assert_eq!(improvements(event), vec![0.0]);
+        let event = ob.execute(OrderType::market(7, Side::Ask, 1.0));
+        assert_eq!(improvements(event), vec![0.0]);
+    }
+
+    #[test]
+    fn signals() {
+        let (mut ob, _) = init_ob(vec![]);
+        assert_eq!(
+            ob.signals(2),
+            Signals {
+                spread: None,
+                spread_bps: None,
+                mid: None,
+                micro_price: None,
+                imbalance: None,
+                bid_volume: 0.0,
+                ask_volume: 0.0,
+            }
+        );
+        let ladder = [
+            (Side::Bid, 1.0, 395.0),
+            (Side::Bid, 3.0, 394.0),
+            (Side::Bid, 2.0, 393.0),
+            (Side::Ask, 3.0, 396.0),
+            (Side::Ask, 1.0, 397.0),
+        ];
+        for (id, (side, qty, price)) in ladder.iter().enumerate() {
+            ob.execute(OrderType::Limit {
+                id: id as u128,
+                side: *side,
+                qty: *qty,
+                price: *price,
+            });
+        }
+        let signals = ob.signals(2);
+        assert_eq!(signals.spread, ob.spread());
+        assert_eq!(
+            signals.spread_bps,
+            Some(ob.spread().unwrap() / ob.mid_price().unwrap() * 10_000.0)
+        );
+        assert_eq!(signals.mid, ob.mid_price());
+        assert_eq!(signals.micro_price, ob.weighted_mid(1));
+        let bid_volume = ob.volume_in_range(Side::Bid, 394.0, 395.0);
+        assert_eq!(signals.bid_volume, bid_volume);
+        let ask_volume = ob.volume_in_range(Side::Ask, 396.0, 397.0);
+        assert_eq!(signals.ask_volume, ask_volume);
+        assert_eq!(signals.imbalance, Some(0.0));
+        assert_eq!(ob.signals(3).imbalance, Some((6.0 - 4.0) / 10.0));
+    }
+
+    #[test]
+    fn cancel_report() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![
+                OrderType::limit(0, *bid_ask, 1.5, 395.0),
+                OrderType::limit(1, *bid_ask, 2.0, 395.0),
+                OrderType::MidpointPeg {
+                    id: 2,
+                    side: *bid_ask,
+                    qty: 3.0,
+                },
+            ]);
+            assert_eq!(
+                ob.cancel_report(1),
+                CancelReport {
+                    event: OrderEvent::Canceled {
+                        id: 1,
+                        canceled_qty: 2.0,
+                        found: true,
+                    },
+                    found: true,
+                    side: Some(*bid_ask),
+                    price: 395.0,
+                    qty: 2.0,
+                    queue_position: 1,
+                }
+            );
+            // Pegged orders have no price level
+            let report = ob.cancel_report(2);
+            assert_eq!((report.side, report.price), (Some(*bid_ask), 0.0));
+            assert_eq!((report.qty, report.queue_position), (3.0, 0));
+
+            // Nothing is canceled
+            for id in [1, 3] {
                 assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
+                    ob.cancel_report(id),
+                    CancelReport {
+                        event: OrderEvent::Canceled {
+                            id,
+                            canceled_qty: 0.0,
+                            found: false,
                         },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::Filled {
-                        id: 3,
-                        filled_qty: 1.0,
-                        fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 0,
-                            qty: 1.0,
-                            price: 395.0,
-                            taker_side: *ask_bid,
-                            total_fill: false,
-                        }]
+                        found: false,
+                        side: None,
+                        price: 0.0,
+                        qty: 0.0,
+                        queue_position: 0,
                     }
                 );
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39800000000, 9998), (39500000000, 9999)])
-                );
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
             }
+            ob.freeze();
+            let report = ob.cancel_report(0);
+            assert!(!report.found);
+            assert_eq!(report.side, None);
+            assert!(matches!(report.event, OrderEvent::Rejected { .. }));
+        }
+    }
+
+    #[test]
+    fn cancel_with_position() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(
+                [(0, 1.5), (1, 2.0), (2, 3.0)]
+                    .iter()
+                    .map(|(id, qty)| OrderType::Limit {
+                        id: *id,
+                        side: *bid_ask,
+                        qty: *qty,
+                        price: 395.0,
+                    })
+                    .collect(),
+            );
+            assert_eq!(ob.cancel_with_position(3), None);
+            assert_eq!(
+                ob.cancel_with_position(1),
+                Some((
+                    OrderEvent::Canceled {
+                        id: 1,
+                        canceled_qty: 2.0,
+                        found: true
+                    },
+                    1,
+                    1.5
+                ))
+            );
+            assert_eq!(ob.cancel_with_position(1), None);
+            assert_eq!(
+                ob.cancel_with_position(2),
+                Some((
+                    OrderEvent::Canceled {
+                        id: 2,
+                        canceled_qty: 3.0,
+                        found: true
+                    },
+                    1,
+                    1.5
+                ))
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 1.5);
+        }
+    }
+
+    #[test]
+    fn last_trace() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            let ladder =
+                [(1.0, 398.0), (2.0, 395.0), (0.5, 398.0), (1.0, 396.0)];
+            for (id, (qty, price)) in ladder.iter().enumerate() {
+                ob.execute(OrderType::Limit {
+                    id: id as u128,
+                    side: *bid_ask,
+                    qty: *qty,
+                    price: *price,
+                });
+            }
+            let sweep = OrderType::Market {
+                id: 4,
+                side: *ask_bid,
+                qty: 4.0,
+            };
+            ob.clone().execute(sweep);
+            assert_eq!(ob.last_trace(), &[]);
+
+            ob.record_trace(true);
+            ob.execute(sweep);
+            let step = |maker_id, level, qty| TraceStep {
+                maker_id,
+                level,
+                qty,
+            };
+            let expected = match bid_ask {
+                Side::Bid => vec![
+                    step(0, 39800000000, 1.0),
+                    step(2, 39800000000, 0.5),
+                    step(3, 39600000000, 1.0),
+                    step(1, 39500000000, 1.5),
+                ],
+                Side::Ask => vec![
+                    step(1, 39500000000, 2.0),
+                    step(3, 39600000000, 1.0),
+                    step(0, 39800000000, 1.0),
+                ],
+            };
+            assert_eq!(ob.last_trace(), expected.as_slice());
+
+            ob.execute(OrderType::Cancel { id: 1 });
+            assert_eq!(ob.last_trace(), &[]);
+        }
+    }
+
+    #[test]
+    fn l3_in_range() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (ob, _) = init_ob(
+                (0..20)
+                    .map(|i| OrderType::Limit {
+                        id: i,
+                        side: *bid_ask,
+                        qty: 1.0 + i as f64,
+                        // Two orders per level, from 390 to 399
+                        price: 390.0 + (i / 2) as f64,
+                    })
+                    .collect(),
+            );
+            let order = |id: u128| LimitOrder {
+                id,
+                side: *bid_ask,
+                qty: 1.0 + id as f64,
+                price: 390.0 + (id / 2) as f64,
+                reserve_qty: 0.0,
+                peak_qty: 0.0,
+                all_or_none: false,
+                expires_after: None,
+                placed_at: id as u64 + 1,
+                ts: id as u64 + 1,
+                account: None,
+            };
+            let expected = match bid_ask {
+                Side::Bid => vec![order(10), order(11), order(8), order(9)],
+                Side::Ask => vec![order(8), order(9), order(10), order(11)],
+            };
+            assert_eq!(ob.l3_in_range(*bid_ask, 393.5, 395.0), expected);
+            assert_eq!(ob.l3_in_range(*bid_ask, 400.0, 410.0), vec![]);
+            assert_eq!(ob.l3_in_range(*bid_ask, 395.0, 394.0), vec![]);
+
+            // An order below an off-grid low bound is left out
+            let mut ob = OrderBook::new(10, 10, 0, false);
+            ob.execute(OrderType::limit(0, *bid_ask, 1.0, 395.0));
+            ob.execute(OrderType::limit(1, *bid_ask, 1.0, 396.0));
+            let ids = |orders: Vec<LimitOrder>| -> Vec<u128> {
+                orders.iter().map(|order| order.id).collect()
+            };
+            assert_eq!(ids(ob.l3_in_range(*bid_ask, 395.5, 396.0)), vec![1]);
+            assert_eq!(ids(ob.l3_in_range(*bid_ask, 395.5, 395.9)), vec![]);
+        }
+    }
+
+    #[test]
+    fn min_resting_time() {
+        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![OrderType::Limit {
+                id: 0,
+                side: *bid_ask,
+                qty: 1.0,
+                price: 395.0,
+            }]);
+            ob.set_min_resting_time(2);
+            let rejected = OrderEvent::Rejected {
+                id: 0,
+                reason: RejectReason::MinRestingTime,
+            };
+            assert_eq!(ob.execute(OrderType::Cancel { id: 0 }), rejected);
+            for id in 1..3 {
+                ob.execute(OrderType::Limit {
+                    id,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 394.0,
+                });
+            }
+            assert_eq!(
+                ob.execute(OrderType::Cancel { id: 0 }),
+                OrderEvent::Canceled {
+                    id: 0,
+                    canceled_qty: 1.0,
+                    found: true
+                }
+            );
+            assert_eq!(
+                ob.execute(OrderType::Cancel { id: 2 }),
+                OrderEvent::Rejected {
+                    id: 2,
+                    reason: RejectReason::MinRestingTime,
+                }
+            );
+            ob.set_min_resting_time(0);
+            assert_eq!(
+                ob.execute(OrderType::Cancel { id: 2 }),
+                OrderEvent::Canceled {
+                    id: 2,
+                    canceled_qty: 1.0,
+                    found: true
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn auction_info() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Ask,
+                qty: 2.0,
+                price: 397.0,
+            },
+        ]);
+        assert_eq!(ob.auction_info(), None);
+
+        ob._rest(2, Side::Bid, 2.0, 400.0);
+        ob._rest(3, Side::Bid, 3.0, 398.0);
+        ob._rest(4, Side::Ask, 1.0, 396.0);
+        ob._rest(5, Side::Ask, 4.0, 399.0);
+        // At 397 and 398, 5 bids and 3 asks cross: buyers are in excess, so
+        // the highest price clears
+        assert_eq!(
+            ob.auction_info(),
+            Some(AuctionResult {
+                price: 398.0,
+                matched_qty: 3.0,
+                imbalance_qty: 2.0,
+                imbalance_side: Some(Side::Bid),
+            })
+        );
+
+        ob._rest(6, Side::Ask, 2.0, 398.0);
+        assert_eq!(
+            ob.auction_info(),
+            Some(AuctionResult {
+                price: 398.0,
+                matched_qty: 5.0,
+                imbalance_qty: 0.0,
+                imbalance_side: None,
+            })
+        );
+        assert_eq!(ob.canonical_string().lines().count(), 7);
+    }
+
+    #[test]
+    fn reprecision() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(vec![]);
+            let orders =
+                [(0, 395.0), (1, 395.25), (2, 395.0), (3, 395.255), (4, 396.5)];
+            for (id, price) in orders.iter() {
+                ob.execute(OrderType::Limit {
+                    id: *id,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: *price,
+                });
+            }
+            ob.execute(OrderType::Limit {
+                id: 5,
+                side: *ask_bid,
+                qty: 1.0,
+                price: match ask_bid {
+                    Side::Ask => 400.0,
+                    Side::Bid => 390.0,
+                },
+            });
+            let dump = ob.canonical_string();
+
+            assert_eq!(ob.reprecision(3), Ok(()));
+            assert_eq!(ob.canonical_string(), dump);
+            assert_eq!(ob.reprecision(2), Err(395.255));
+            assert_eq!(ob.canonical_string(), dump);
+            assert_eq!(ob.reprecision(10), Ok(()));
+            assert_eq!(ob.canonical_string(), dump);
+            assert_eq!(ob.depth(5).asks.len() + ob.depth(5).bids.len(), 5);
+
+            // Orders keep their time priority at their new level
+            let result = ob.execute(OrderType::Market {
+                id: 6,
+                side: *ask_bid,
+                qty: 1.0,
+            });
+            let maker = match ask_bid {
+                Side::Ask => 4,
+                Side::Bid => 0,
+            };
+            match result {
+                OrderEvent::Filled { fills, .. } => {
+                    assert_eq!(fills[0].order_2, maker)
+                }
+                _ => panic!("unexpected event {:?}", result),
+            }
+            ob.audit_arena().unwrap();
         }
+
+        // Each side keeps its own precision
+        let mut ob = OrderBook::with_side_precision(10, 10, 2, 4, false);
+        ob.execute(OrderType::limit(0, Side::Bid, 1.0, 395.21));
+        ob.execute(OrderType::limit(1, Side::Bid, 1.0, 395.25));
+        ob.execute(OrderType::limit(2, Side::Ask, 1.0, 396.1234));
+        ob.execute(OrderType::limit(3, Side::Ask, 1.0, 396.1239));
+        assert_eq!(ob.reprecision_sides(1, 4), Err(395.25));
+        assert_eq!(ob.reprecision_sides(2, 3), Err(396.1239));
+        assert_eq!(ob.reprecision_sides(3, 5), Ok(()));
+        assert_eq!(ob._bids(), init_book(vec![(395210, 0), (395250, 1)]));
+        assert_eq!(ob._asks(), init_book(vec![(39612340, 2), (39612390, 3)]));
+        assert_eq!(ob.quotes(), (Some(395.25), Some(396.1234)));
+        ob.audit_arena().unwrap();
     }
 
     #[test]
-    fn crossing_limit_order_matching() {
+    fn aggregate_fills() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![
-                OrderType::Limit {
-                    id: 0,
-                    side: *bid_ask,
-                    qty: 12.0,
-                    price: 395.0,
-                },
-                OrderType::Limit {
-                    id: 1,
-                    side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
-                },
-                OrderType::Limit {
-                    id: 2,
-                    side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
-                },
-            ]);
-            let result = ob.execute(OrderType::Limit {
-                id: 3,
+            let (mut ob, _) = init_ob(
+                (0..10)
+                    .map(|id| OrderType::Limit {
+                        id,
+                        side: *bid_ask,
+                        qty: 1.0,
+                        price: if id < 8 { 395.0 } else { 396.0 },
+                    })
+                    .collect(),
+            );
+            let sweep = OrderType::Market {
+                id: 10,
                 side: *ask_bid,
-                qty: 2.0,
-                price: 397.0,
-            });
-
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::Filled {
-                        id: 3,
-                        filled_qty: 2.0,
-                        fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 2,
-                            qty: 2.0,
-                            price: 398.0,
-                            taker_side: *ask_bid,
-                            total_fill: true,
-                        }]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(399.0));
-                assert_eq!(ob.max_bid(), Some(395.0));
-                assert_eq!(ob._asks(), init_book(vec![(39900000000, 9998)]));
-                assert_eq!(
-                    ob._bids(),
-                    init_book_holes(vec![(39500000000, 9999)], vec![39800000000])
-                );
-                assert_eq!(ob.spread(), Some(4.0));
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::Filled {
-                        id: 3,
-                        filled_qty: 2.0,
-                        fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 0,
-                            qty: 2.0,
-                            price: 395.0,
-                            taker_side: *ask_bid,
-                            total_fill: false,
-                        }]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39500000000, 9999), (39800000000, 9998)])
-                );
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
+                qty: 7.5,
+            };
+            match ob.clone().execute(sweep) {
+                OrderEvent::Filled { fills, .. } => assert!(fills.len() > 2),
+                result => panic!("unexpected event {:?}", result),
             }
+
+            ob.aggregate_fills(true);
+            let result = ob.execute(sweep);
+            let fill = |order_2, qty, price, total_fill| FillMetadata {
+                order_1: 10,
+                order_2,
+                qty,
+                price,
+                taker_side: *ask_bid,
+                total_fill,
+                maker_ts: order_2 as u64 + 1,
+                price_improvement: 0.0,
+            };
+            let fills = match bid_ask {
+                Side::Bid => vec![
+                    fill(8, 2.0, 396.0, true),
+                    fill(0, 5.5, 395.0, false),
+                ],
+                Side::Ask => vec![fill(0, 7.5, 395.0, false)],
+            };
+            assert_eq!(
+                result,
+                OrderEvent::Filled {
+                    id: 10,
+                    filled_qty: 7.5,
+                    fills,
+                }
+            );
+            // Every matched maker order still counts as a trade
+            assert_eq!(ob.trade_count(), 8);
         }
     }
 
     #[test]
-    fn crossing_limit_order_over() {
+    fn immediate_or_cancel() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![
-                OrderType::Limit {
-                    id: 0,
-                    side: *bid_ask,
-                    qty: 12.0,
-                    price: 395.0,
-                },
-                OrderType::Limit {
-                    id: 1,
-                    side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
-                },
-                OrderType::Limit {
+            // Sorted from the best to the worst price of the resting side
+            let prices = match *bid_ask {
+                Side::Bid => [396.0, 395.0],
+                Side::Ask => [395.0, 396.0],
+            };
+            let (mut ob, _) = init_ob(
+                (0..2)
+                    .map(|id| OrderType::Limit {
+                        id,
+                        side: *bid_ask,
+                        qty: 1.0,
+                        price: prices[id as usize],
+                    })
+                    .collect(),
+            );
+            let ioc = |id, qty| OrderType::ImmediateOrCancel {
+                id,
+                side: *ask_bid,
+                qty,
+                price: prices[0],
+            };
+            let fill = |order_1, qty, total_fill| FillMetadata {
+                order_1,
+                order_2: 0,
+                qty,
+                price: prices[0],
+                taker_side: *ask_bid,
+                total_fill,
+                maker_ts: 1,
+                price_improvement: 0.0,
+            };
+
+            assert_eq!(
+                ob.execute(ioc(2, 0.5)),
+                OrderEvent::Filled {
                     id: 2,
-                    side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
+                    filled_qty: 0.5,
+                    fills: vec![fill(2, 0.5, false)],
+                }
+            );
+
+            // The remainder is discarded instead of resting at the limit price
+            assert_eq!(
+                ob.execute(ioc(3, 2.0)),
+                OrderEvent::PartiallyFilled {
+                    id: 3,
+                    filled_qty: 0.5,
+                    fills: vec![fill(3, 0.5, true)],
+                }
+            );
+            assert_eq!(ob.volume_in_range(*ask_bid, 390.0, 400.0), 0.0);
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 1.0);
+            assert_eq!(
+                ob.execute(OrderType::Cancel { id: 3 }),
+                OrderEvent::Canceled {
+                    id: 3,
+                    canceled_qty: 0.0,
+                    found: false
+                }
+            );
+
+            // Nothing is left at or better than the limit price
+            assert_eq!(ob.execute(ioc(4, 1.0)), OrderEvent::Unfilled { id: 4 });
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 1.0);
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn recent_events() {
+        let mut ob = OrderBook::default();
+        let limit = |id, side, price| OrderType::Limit {
+            id,
+            side,
+            qty: 1.0,
+            price,
+        };
+        ob.execute(limit(0, Side::Bid, 395.0));
+        assert_eq!(ob.recent_events().count(), 0);
+
+        ob.record_recent_events(3);
+        let results: Vec<_> = vec![
+            limit(1, Side::Bid, 394.0),
+            limit(2, Side::Ask, 396.0),
+            OrderType::Cancel { id: 1 },
+            limit(2, Side::Ask, 397.0),
+            limit(4, Side::Ask, 395.0),
+        ]
+        .into_iter()
+        .map(|order| ob.execute(order))
+        .collect();
+        assert!(matches!(results[4], OrderEvent::Filled { .. }));
+        let recent: Vec<_> = ob.recent_events().cloned().collect();
+        assert_eq!(recent, results[2..].to_vec());
+
+        // Events queued for other orders are kept as well
+        ob.set_max_levels(Some(1));
+        let placed = ob.execute(limit(5, Side::Ask, 395.0));
+        let recent: Vec<_> = ob.recent_events().cloned().collect();
+        assert_eq!(
+            recent,
+            vec![
+                results[4].clone(),
+                OrderEvent::Canceled {
+                    id: 2,
+                    canceled_qty: 1.0,
+                    found: true
                 },
-            ]);
-            let result = ob.execute(OrderType::Limit {
-                id: 3,
-                side: *ask_bid,
-                qty: 5.0,
-                price: 397.0,
-            });
+                placed,
+            ]
+        );
 
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::PartiallyFilled {
-                        id: 3,
-                        filled_qty: 2.0,
-                        fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 2,
-                            qty: 2.0,
-                            price: 398.0,
-                            taker_side: *ask_bid,
-                            total_fill: true,
-                        }]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(397.0));
-                assert_eq!(ob.max_bid(), Some(395.0));
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39900000000, 9998), (39700000000, 9996)])
-                );
-                assert_eq!(
-                    ob._bids(),
-                    init_book_holes(vec![(39500000000, 9999)], vec![39800000000])
-                );
-                assert_eq!(ob.spread(), Some(2.0));
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::Filled {
-                        id: 3,
-                        filled_qty: 5.0,
-                        fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 0,
-                            qty: 5.0,
-                            price: 395.0,
-                            taker_side: *ask_bid,
-                            total_fill: false,
-                        }]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39500000000, 9999), (39800000000, 9998)])
-                );
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
+        ob.record_recent_events(1);
+        assert_eq!(ob.recent_events().count(), 1);
+        ob.record_recent_events(0);
+        assert_eq!(ob.recent_events().count(), 0);
+    }
+
+    #[test]
+    fn order_status() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let orders = vec![
+                OrderType::limit(0, *bid_ask, 1.0, 395.0),
+                OrderType::limit(1, *bid_ask, 1.0, 395.0),
+                OrderType::limit(2, *bid_ask, 2.0, 395.0),
+                OrderType::limit(3, *bid_ask, 1.0, 395.0),
+            ];
+            let resting = |qty| Some(OrderStatus::Resting { qty });
+
+            // Filled orders are unknown by default
+            let (mut ob, _) = init_ob(orders.clone());
+            ob.execute(OrderType::market(4, *ask_bid, 1.0));
+            assert_eq!(ob.order_status(0), None);
+
+            let (mut ob, _) = init_ob(orders);
+            ob.record_filled_orders(2);
+            assert_eq!(ob.order_status(0), resting(1.0));
+            assert_eq!(ob.order_status(9), None);
+
+            // The filled maker is told apart from an unknown order
+            ob.execute(OrderType::market(4, *ask_bid, 1.5));
+            assert_eq!(ob.order_status(0), Some(OrderStatus::Filled));
+            assert_eq!(ob.order_status(1), resting(0.5));
+            ob.execute(OrderType::cancel(1));
+            assert_eq!(ob.order_status(1), None);
+
+            // Only the last filled orders are remembered
+            ob.execute(OrderType::market(5, *ask_bid, 3.0));
+            assert_eq!(ob.order_status(0), None);
+            assert_eq!(ob.order_status(2), Some(OrderStatus::Filled));
+            assert_eq!(ob.order_status(3), Some(OrderStatus::Filled));
+            ob.record_filled_orders(1);
+            assert_eq!(ob.order_status(2), None);
+            assert_eq!(ob.order_status(3), Some(OrderStatus::Filled));
+        }
+    }
+
+    #[test]
+    fn fill_or_kill() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let fok = |id, qty| OrderType::FillOrKill {
+                id,
+                side: *ask_bid,
+                qty,
+                price: 395.5,
+            };
+            let (mut ob, _) = init_ob(Vec::new());
+            assert_eq!(ob.execute(fok(0, 1.0)), OrderEvent::Unfilled { id: 0 });
+
+            // Only the orders at 395.5 or better can fill the order
+            let prices = match *bid_ask {
+                Side::Bid => [396.0, 395.5, 395.0],
+                Side::Ask => [395.0, 395.5, 396.0],
+            };
+            let (mut ob, _) = init_ob(
+                (0..3)
+                    .map(|id| OrderType::Limit {
+                        id,
+                        side: *bid_ask,
+                        qty: 1.0,
+                        price: prices[id as usize],
+                    })
+                    .collect(),
+            );
+            let canonical = ob.canonical_string();
+            assert_eq!(ob.execute(fok(3, 3.0)), OrderEvent::Unfilled { id: 3 });
+            assert_eq!(ob.canonical_string(), canonical);
+
+            match ob.execute(fok(4, 2.0)) {
+                OrderEvent::Filled {
+                    id: 4,
+                    filled_qty,
+                    fills,
+                } => {
+                    let makers: Vec<_> =
+                        fills.iter().map(|fill| fill.order_2).collect();
+                    assert_eq!(filled_qty, 2.0);
+                    assert_eq!(makers, vec![0, 1]);
+                }
+                result => panic!("unexpected event {:?}", result),
             }
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 1.0);
+            ob.audit_arena().unwrap();
         }
     }
 
     #[test]
-    fn market_order_unfilled() {
-        for (_, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, _) = init_ob(vec![]);
-            let result = ob.execute(OrderType::Market {
+    fn resting_stops() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Bid, 1.0, 395.0),
+            OrderType::limit(1, Side::Ask, 1.0, 398.0),
+        ]);
+        let depth = ob.depth(10);
+        assert_eq!(ob.resting_stops(), Vec::new());
+        ob.execute(OrderType::StopMarket {
+            id: 2,
+            side: Side::Ask,
+            qty: 2.0,
+            stop_price: 394.0,
+        });
+        ob.execute(OrderType::MarketIfTouched {
+            id: 3,
+            side: Side::Bid,
+            qty: 1.5,
+            trigger_price: 393.0,
+        });
+        ob.execute(OrderType::StopMarket {
+            id: 4,
+            side: Side::Bid,
+            qty: 3.0,
+            stop_price: 399.0,
+        });
+        assert_eq!(
+            ob.resting_stops(),
+            vec![
+                (2, Side::Ask, 394.0, 2.0),
+                (3, Side::Bid, 393.0, 1.5),
+                (4, Side::Bid, 399.0, 3.0)
+            ]
+        );
+        assert_eq!(ob.depth(10), depth);
+
+        ob.execute(OrderType::cancel(3));
+        assert_eq!(
+            ob.resting_stops(),
+            vec![(2, Side::Ask, 394.0, 2.0), (4, Side::Bid, 399.0, 3.0)]
+        );
+    }
+
+    #[test]
+    fn stops_would_trigger() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::Limit {
                 id: 0,
-                side: *ask_bid,
-                qty: 5.0,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 394.0,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::MarketIfTouched {
+                id: 3,
+                side: Side::Bid,
+                qty: 1.0,
+                trigger_price: 394.0,
+            },
+            OrderType::MarketIfTouched {
+                id: 4,
+                side: Side::Ask,
+                qty: 1.0,
+                trigger_price: 396.0,
+            },
+            OrderType::MarketIfTouched {
+                id: 5,
+                side: Side::Bid,
+                qty: 1.0,
+                trigger_price: 393.0,
+            },
+        ]);
+        let sweep = |qty| OrderType::Market {
+            id: 6,
+            side: Side::Ask,
+            qty,
+        };
+        assert_eq!(ob.stops_would_trigger(sweep(1.0)), Vec::<u128>::new());
+        assert_eq!(OrderBook::default().stops_would_trigger(sweep(1.0)), []);
+
+        // The trade at 394 triggers order 3, whose fill at 396 triggers order
+        // 4 in cascade
+        let predicted = ob.stops_would_trigger(sweep(2.0));
+        assert_eq!(predicted, vec![3, 4]);
+        let canonical = ob.canonical_string();
+        assert_eq!(ob.stops_would_trigger(sweep(2.0)), predicted);
+        assert_eq!(ob.canonical_string(), canonical);
+
+        ob.execute(sweep(2.0));
+        let mut triggered: Vec<_> = ob
+            .drain_events()
+            .iter()
+            .map(|event| match event {
+                OrderEvent::Filled { id, .. } | OrderEvent::Unfilled { id } => {
+                    *id
+                }
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        triggered.sort_unstable();
+        assert_eq!(triggered, predicted);
+    }
+
+    #[test]
+    fn max_cascade_depth() {
+        let (mut ob, _) = init_ob(vec![]);
+        for i in 0..6 {
+            ob.execute(OrderType::limit(i, Side::Ask, 1.0, 401.0 + i as f64));
+        }
+        // Each stop lifts the next ask, whose price triggers the next stop
+        for i in 0..5 {
+            ob.execute(OrderType::StopMarket {
+                id: 10 + i,
+                side: Side::Bid,
+                qty: 1.0,
+                stop_price: 401.0 + i as f64,
             });
+        }
+        let fired = |ob: &mut OrderBook| -> Vec<u128> {
+            ob.drain_events()
+                .iter()
+                .map(|event| match event {
+                    OrderEvent::Filled { id, .. }
+                    | OrderEvent::Unfilled { id } => *id,
+                    event => panic!("unexpected event {:?}", event),
+                })
+                .collect()
+        };
+        ob.set_max_cascade_depth(3);
+        ob.execute(OrderType::market(20, Side::Bid, 1.0));
+        assert_eq!(fired(&mut ob), vec![10, 11, 12]);
+        let dormant: Vec<_> =
+            ob.resting_stops().iter().map(|stop| stop.0).collect();
+        assert_eq!(dormant, vec![13, 14]);
+        assert_eq!(ob.min_ask(), Some(405.0));
 
-            assert_eq!(result, OrderEvent::Unfilled { id: 0 });
+        // The deferred stops are triggered by the next trade, and the last
+        // ask is lifted by the one closest to its price
+        ob.execute(OrderType::limit(21, Side::Bid, 1.0, 405.0));
+        assert_eq!(fired(&mut ob), vec![14, 13]);
+        assert!(ob.resting_stops().is_empty());
+        assert_eq!(ob.min_ask(), None);
+
+        // Nothing is triggered without cascade
+        ob.set_max_cascade_depth(0);
+        ob.execute(OrderType::StopMarket {
+            id: 22,
+            side: Side::Bid,
+            qty: 1.0,
+            stop_price: 410.0,
+        });
+        ob.execute(OrderType::limit(23, Side::Ask, 1.0, 410.0));
+        ob.execute(OrderType::market(24, Side::Bid, 1.0));
+        assert!(ob.drain_events().is_empty());
+        assert_eq!(ob.resting_stops().len(), 1);
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn round_trade_prices() {
+        let mut ob = OrderBook::new(10, 10, 2, true);
+        let sweep = |ob: &mut OrderBook| {
+            for (id, price) in [(0, 395.06), (1, 395.5)] {
+                ob.execute(OrderType::Limit {
+                    id,
+                    side: Side::Ask,
+                    qty: 1.5,
+                    price,
+                });
+            }
+            ob.execute(OrderType::Market {
+                id: 2,
+                side: Side::Bid,
+                qty: 3.0,
+            });
+            ob.last_trade().unwrap()
+        };
+        assert_ne!(sweep(&mut ob).avg_price, 395.28);
+
+        ob.round_trade_prices(true);
+        let trade = sweep(&mut ob);
+        assert_eq!(trade.avg_price, 395.28);
+        assert_eq!(trade.last_price, 395.5);
+
+        // The recorded trade keeps the unrounded average
+        ob.round_trade_prices(false);
+        assert_ne!(ob.last_trade().unwrap().avg_price, 395.28);
+    }
+
+    #[test]
+    fn stop_market() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            // Sorted from the best to the worst price of the resting side,
+            // with the stop price in between
+            let (prices, stop_price) = match *bid_ask {
+                Side::Bid => ([395.0, 394.0], 394.5),
+                Side::Ask => ([395.0, 396.0], 395.5),
+            };
+            let (mut ob, mut results) = init_ob(
+                (0..2)
+                    .map(|id| OrderType::Limit {
+                        id,
+                        side: *bid_ask,
+                        qty: 1.0,
+                        price: prices[id as usize],
+                    })
+                    .collect(),
+            );
+            let limit = |id, qty, price| OrderType::Limit {
+                id,
+                side: *ask_bid,
+                qty,
+                price,
+            };
+            results.push(ob.execute(OrderType::StopMarket {
+                id: 2,
+                side: *ask_bid,
+                qty: 1.0,
+                stop_price,
+            }));
+            assert_eq!(results[2], OrderEvent::Placed { id: 2 });
+
+            // A trade before the stop price leaves the stop dormant
+            ob.execute(limit(3, 1.0, prices[0]));
+            assert_eq!(ob.drain_events(), Vec::new());
+
+            // The market is pushed through the stop price
+            ob.execute(limit(4, 0.5, prices[1]));
+            assert_eq!(
+                ob.drain_events(),
+                vec![OrderEvent::PartiallyFilled {
+                    id: 2,
+                    filled_qty: 0.5,
+                    fills: vec![FillMetadata {
+                        order_1: 2,
+                        order_2: 1,
+                        qty: 0.5,
+                        price: prices[1],
+                        taker_side: *ask_bid,
+                        total_fill: true,
+                        maker_ts: 2,
+                        price_improvement: 0.0,
+                    }]
+                }]
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 0.0);
+            assert_eq!(ob.volume_in_range(*ask_bid, 390.0, 400.0), 0.0);
+            ob.audit_arena().unwrap();
         }
     }
 
     #[test]
-    fn market_order_partially_filled() {
+    fn level_stats() {
+        let (mut ob, _) = init_ob(
+            [(395.0, 1.0), (395.0, 2.5), (394.0, 1.0), (395.0, 0.5)]
+                .iter()
+                .enumerate()
+                .map(|(id, (price, qty))| OrderType::Limit {
+                    id: id as u128,
+                    side: Side::Bid,
+                    qty: *qty,
+                    price: *price,
+                })
+                .chain(std::iter::once(OrderType::Limit {
+                    id: 4,
+                    side: Side::Ask,
+                    qty: 1.0,
+                    price: 396.0,
+                }))
+                .collect(),
+        );
+        assert_eq!(
+            ob.level_stats(Side::Bid, 10),
+            vec![(395.0, 4.0, 3), (394.0, 1.0, 1)]
+        );
+        assert_eq!(ob.level_stats(Side::Bid, 1), vec![(395.0, 4.0, 3)]);
+        assert_eq!(ob.level_stats(Side::Ask, 10), vec![(396.0, 1.0, 1)]);
+
+        // Partially filled orders count, while emptied levels are dropped
+        ob.execute(OrderType::Market {
+            id: 5,
+            side: Side::Ask,
+            qty: 1.5,
+        });
+        ob.execute(OrderType::Cancel { id: 4 });
+        assert_eq!(
+            ob.level_stats(Side::Bid, 10),
+            vec![(395.0, 2.5, 2), (394.0, 1.0, 1)]
+        );
+        assert_eq!(ob.level_stats(Side::Ask, 10), Vec::new());
+    }
+
+    #[test]
+    fn price_for_notional() {
+        let (ob, _) = init_ob(
+            [(395.0, 1.0), (396.0, 2.0), (398.0, 1.0)]
+                .iter()
+                .enumerate()
+                .map(|(id, (price, qty))| OrderType::Limit {
+                    id: id as u128,
+                    side: Side::Ask,
+                    qty: *qty,
+                    price: *price,
+                })
+                .collect(),
+        );
+        assert_eq!(ob.price_for_notional(Side::Bid, 100.0), Some(395.0));
+        assert_eq!(ob.price_for_notional(Side::Bid, 395.0), Some(395.0));
+        assert_eq!(ob.price_for_notional(Side::Bid, 395.5), Some(396.0));
+        assert_eq!(ob.price_for_notional(Side::Bid, 1187.0), Some(396.0));
+        assert_eq!(ob.price_for_notional(Side::Bid, 1500.0), Some(398.0));
+        assert_eq!(ob.price_for_notional(Side::Bid, 1586.0), None);
+        assert_eq!(ob.price_for_notional(Side::Ask, 100.0), None);
+    }
+
+    #[test]
+    fn levels_swept() {
+        let (mut ob, results) = init_ob(
+            [(395.0, 1.0), (396.0, 1.0), (396.0, 2.0), (398.0, 1.0)]
+                .iter()
+                .enumerate()
+                .map(|(id, (price, qty))| OrderType::Limit {
+                    id: id as u128,
+                    side: Side::Ask,
+                    qty: *qty,
+                    price: *price,
+                })
+                .collect(),
+        );
+        assert_eq!(results[0].levels_swept(), 0);
+        let market = |id, qty| OrderType::Market {
+            id,
+            side: Side::Bid,
+            qty,
+        };
+        assert_eq!(ob.execute(market(4, 0.5)).levels_swept(), 1);
+
+        // Two orders at 396.0 count as a single level
+        let event = ob.execute(market(5, 4.5));
+        assert_eq!(event.levels_swept(), 3);
+        assert_eq!(ob.execute(market(6, 1.0)), OrderEvent::Unfilled { id: 6 });
+    }
+
+    #[test]
+    fn cancel_on_disconnect() {
+        let mut ob = OrderBook::default();
+        ob.register_cancel_on_disconnect(1);
+        ob.execute_for_account(1, OrderType::limit(0, Side::Bid, 1.0, 395.0));
+        ob.execute_for_account(1, OrderType::limit(1, Side::Ask, 2.0, 398.0));
+        ob.execute_for_account(2, OrderType::limit(2, Side::Bid, 3.0, 395.0));
+        ob.execute(OrderType::limit(3, Side::Ask, 4.0, 399.0));
+        ob.execute_for_account(1, OrderType::limit(4, Side::Ask, 1.0, 400.0));
+        ob.execute(OrderType::market(5, Side::Bid, 1.0));
+        let peg = |id, qty| OrderType::MidpointPeg {
+            id,
+            side: Side::Bid,
+            qty,
+        };
+        ob.execute_for_account(1, peg(6, 1.5));
+        ob.execute_for_account(2, peg(7, 0.5));
+
+        // Only registered accounts are protected
+        assert_eq!(ob.trigger_disconnect(2), Vec::new());
+        let canceled = |id, canceled_qty| OrderEvent::Canceled {
+            id,
+            canceled_qty,
+            found: true,
+        };
+        ob.freeze();
+        assert_eq!(
+            ob.trigger_disconnect(1),
+            vec![
+                canceled(1, 1.0),
+                canceled(4, 1.0),
+                canceled(0, 1.0),
+                canceled(6, 1.5),
+            ]
+        );
+        assert!(!ob.has_order(6));
+        assert!(ob.has_order(7));
+        assert_eq!(ob._bids(), init_book(vec![(39500000000, 2)]));
+        assert_eq!(ob._asks(), init_book(vec![(39900000000, 3)]));
+        assert_eq!(ob.trigger_disconnect(1), Vec::new());
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn replace_side() {
+        let mut ob = OrderBook::default();
+        for id in 10..15 {
+            let price = 390.0 + id as f64;
+            let order = OrderType::limit(id, Side::Ask, 1.0, price);
+            ob.execute_for_account(7, order);
+        }
+        ob.execute_for_account(8, OrderType::limit(20, Side::Ask, 2.0, 401.0));
+        ob.execute_for_account(7, OrderType::limit(30, Side::Bid, 1.0, 390.0));
+
+        // The cancels are held back by the minimum resting time: the whole
+        // replacement is rolled back
+        let levels: Vec<(u128, f64, f64)> =
+            (31..36).map(|id| (id, 371.0 + id as f64, 0.5)).collect();
+        let snapshot = ob.snapshot();
+        ob.set_min_resting_time(10);
+        assert_eq!(
+            ob.replace_side(Side::Ask, 7, &levels),
+            Err(OrderEvent::Rejected {
+                id: 10,
+                reason: RejectReason::MinRestingTime,
+            })
+        );
+        assert_eq!(ob.snapshot().orders, snapshot.orders);
+        ob.set_min_resting_time(0);
+
+        // So is a ladder with an ID already in use
+        let mut taken = levels.clone();
+        taken[4].0 = 20;
+        assert_eq!(
+            ob.replace_side(Side::Ask, 7, &taken),
+            Err(OrderEvent::Rejected {
+                id: 20,
+                reason: RejectReason::DuplicateId,
+            })
+        );
+        assert_eq!(ob.snapshot().orders, snapshot.orders);
+
+        // The old ladder of the account is canceled, and the new one rests
+        let mut expected: Vec<OrderEvent> = (10..15)
+            .map(|id| OrderEvent::Canceled {
+                id,
+                canceled_qty: 1.0,
+                found: true,
+            })
+            .collect();
+        expected.extend((31..36).map(|id| OrderEvent::Placed { id }));
+        assert_eq!(ob.replace_side(Side::Ask, 7, &levels), Ok(expected));
+        assert_eq!(
+            ob.iter_orders(Side::Ask)
+                .map(|order| (order.id, order.price, order.qty))
+                .collect::<Vec<_>>(),
+            vec![
+                (20, 401.0, 2.0),
+                (31, 402.0, 0.5),
+                (32, 403.0, 0.5),
+                (33, 404.0, 0.5),
+                (34, 405.0, 0.5),
+                (35, 406.0, 0.5),
+            ]
+        );
+        assert_eq!(ob.order_count(Side::Bid), 1);
+
+        // The new orders belong to the account
+        ob.register_cancel_on_disconnect(7);
+        assert_eq!(ob.trigger_disconnect(7).len(), 6);
+        assert_eq!(ob.order_count(Side::Ask), 1);
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn self_trade_prevention() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![
-                OrderType::Limit {
-                    id: 0,
+            // Order 1 of account 7 rests behind order 0, which has no account
+            let init = |mode| {
+                let mut ob = OrderBook::default();
+                ob.set_stp_mode(Some(mode));
+                let limit = |id| OrderType::Limit {
+                    id,
                     side: *bid_ask,
-                    qty: 12.0,
+                    qty: 1.0,
                     price: 395.0,
-                },
-                OrderType::Limit {
-                    id: 1,
-                    side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
-                },
-                OrderType::Limit {
-                    id: 2,
-                    side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
-                },
-            ]);
-            let result = ob.execute(OrderType::Market {
-                id: 3,
+                };
+                ob.execute(limit(0));
+                ob.execute_for_account(7, limit(1));
+                ob
+            };
+            let taker = |id| OrderType::Limit {
+                id,
                 side: *ask_bid,
-                qty: 15.0,
-            });
+                qty: 1.5,
+                price: 395.0,
+            };
+            let fill = FillMetadata {
+                order_1: 2,
+                order_2: 0,
+                qty: 1.0,
+                price: 395.0,
+                taker_side: *ask_bid,
+                total_fill: true,
+                maker_ts: 1,
+                price_improvement: 0.0,
+            };
+            let partially_filled = OrderEvent::PartiallyFilled {
+                id: 2,
+                filled_qty: 1.0,
+                fills: vec![fill],
+            };
+            let canceled = |id, canceled_qty| OrderEvent::Canceled {
+                id,
+                canceled_qty,
+                found: true,
+            };
 
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::PartiallyFilled {
-                        id: 3,
-                        filled_qty: 14.0,
-                        fills: vec![
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 2,
-                                qty: 2.0,
-                                price: 398.0,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            },
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 0,
-                                qty: 12.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            }
-                        ]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(399.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(ob._asks(), init_book(vec![(39900000000, 9998)]));
-                assert_eq!(ob._bids(), init_book_holes(vec![], vec![39500000000, 39800000000]));
-                assert_eq!(ob.spread(), None);
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::PartiallyFilled {
-                        id: 3,
-                        filled_qty: 12.0,
-                        fills: vec![
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 0,
-                                qty: 10.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            },
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 2,
-                                qty: 2.0,
-                                price: 398.0,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            }
-                        ]
-                    }
-                );
-                assert_eq!(ob.min_ask(), None);
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(ob._asks(), init_book_holes(vec![], vec![39500000000, 39800000000]));
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
-            }
+            // The taker stops at order 1, and its unfilled quantity is
+            // canceled instead of resting
+            let mut ob = init(StpMode::CancelNewest);
+            assert_eq!(ob.execute_for_account(7, taker(2)), partially_filled);
+            assert_eq!(ob.drain_events(), vec![]);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 1.0);
+            assert_eq!(ob.volume_in_range(*ask_bid, 395.0, 395.0), 0.0);
+            assert_eq!(
+                ob.execute_for_account(7, taker(3)),
+                canceled(3, 0.0)
+            );
+            ob.audit_arena().unwrap();
+
+            // Order 1 is canceled, and the taker rests
+            let mut ob = init(StpMode::CancelOldest);
+            assert_eq!(ob.execute_for_account(7, taker(2)), partially_filled);
+            assert_eq!(ob.drain_events(), vec![canceled(1, 1.0)]);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 0.0);
+            assert_eq!(ob.volume_in_range(*ask_bid, 395.0, 395.0), 0.5);
+            ob.audit_arena().unwrap();
+
+            // Both orders are canceled
+            let mut ob = init(StpMode::CancelBoth);
+            assert_eq!(ob.execute_for_account(7, taker(2)), partially_filled);
+            assert_eq!(ob.drain_events(), vec![canceled(1, 1.0)]);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 0.0);
+            assert_eq!(ob.volume_in_range(*ask_bid, 395.0, 395.0), 0.0);
+            ob.audit_arena().unwrap();
+
+            // Orders of other accounts trade with each other
+            let mut ob = init(StpMode::CancelBoth);
+            assert_eq!(
+                ob.execute_for_account(8, taker(2)),
+                OrderEvent::Filled {
+                    id: 2,
+                    filled_qty: 1.5,
+                    fills: vec![
+                        fill,
+                        FillMetadata {
+                            order_2: 1,
+                            qty: 0.5,
+                            total_fill: false,
+                            maker_ts: 2,
+                            ..fill
+                        },
+                    ],
+                }
+            );
+            assert_eq!(ob.drain_events(), vec![]);
         }
     }
 
     #[test]
-    fn market_order_partially_filled_floating_points() {
+    fn self_match_stats() {
+        let mut ob = OrderBook::default();
+        ob.set_stp_mode(Some(StpMode::CancelOldest));
+        let canceled = |id, canceled_qty| OrderEvent::Canceled {
+            id,
+            canceled_qty,
+            found: true,
+        };
+        ob.execute_for_account(7, OrderType::limit(0, Side::Bid, 1.0, 395.0));
+        assert_eq!(ob.self_match_stats(), (0, &[][..]));
+
+        // The account crosses its own resting order, which is canceled
+        assert_eq!(
+            ob.execute_for_account(
+                7,
+                OrderType::limit(1, Side::Ask, 2.0, 395.0)
+            ),
+            OrderEvent::Placed { id: 1 }
+        );
+        assert_eq!(ob.drain_events(), vec![canceled(0, 1.0)]);
+        assert_eq!(ob.self_match_stats(), (1, &[0][..]));
+
+        // Other accounts trade with it
+        ob.execute_for_account(8, OrderType::limit(2, Side::Bid, 1.0, 395.0));
+        assert_eq!(ob.self_match_stats(), (1, &[0][..]));
+
+        ob.set_stp_mode(Some(StpMode::CancelNewest));
+        assert_eq!(
+            ob.execute_for_account(
+                7,
+                OrderType::limit(3, Side::Bid, 1.0, 395.0)
+            ),
+            canceled(3, 0.0)
+        );
+        assert_eq!(ob.self_match_stats(), (2, &[0, 3][..]));
+
+        ob.reset_stats();
+        assert_eq!(ob.self_match_stats(), (0, &[][..]));
+        assert_eq!(ob.volume_in_range(Side::Ask, 395.0, 395.0), 1.0);
+    }
+
+    #[test]
+    fn top_order_pro_rata() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let init = |mode| {
+                let mut ob = OrderBook::default();
+                ob.set_matching_mode(mode);
+                let deeper = match bid_ask {
+                    Side::Bid => 394.0,
+                    Side::Ask => 396.0,
+                };
+                let orders = [
+                    (1.0, 395.0),
+                    (2.0, 395.0),
+                    (6.0, 395.0),
+                    (1.0, deeper),
+                    (1.0, deeper),
+                ];
+                for (id, (qty, price)) in orders.iter().enumerate() {
+                    ob.execute(OrderType::Limit {
+                        id: id as u128,
+                        side: *bid_ask,
+                        qty: *qty,
+                        price: *price,
+                    });
+                }
+                (ob, deeper)
+            };
+            let market = |id, qty| OrderType::Market {
+                id,
+                side: *ask_bid,
+                qty,
+            };
+            let fill = |order_1, order_2, qty, price, total_fill| {
+                FillMetadata {
+                    order_1,
+                    order_2,
+                    qty,
+                    price,
+                    taker_side: *ask_bid,
+                    total_fill,
+                    maker_ts: order_2 as u64 + 1,
+                    price_improvement: 0.0,
+                }
+            };
+
+            // Order 0 is filled first, then the 4.0 left is split 1:3
+            // between orders 1 and 2
+            let (mut ob, deeper) = init(MatchingMode::TopOrderProRata);
+            assert_eq!(
+                ob.execute(market(5, 5.0)),
+                OrderEvent::Filled {
+                    id: 5,
+                    filled_qty: 5.0,
+                    fills: vec![
+                        fill(5, 0, 1.0, 395.0, true),
+                        fill(5, 1, 1.0, 395.0, false),
+                        fill(5, 2, 3.0, 395.0, false),
+                    ],
+                }
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 4.0);
+
+            // The touch is taken entirely, and the deeper level is matched in
+            // time priority
+            assert_eq!(
+                ob.execute(market(6, 5.5)),
+                OrderEvent::Filled {
+                    id: 6,
+                    filled_qty: 5.5,
+                    fills: vec![
+                        fill(6, 1, 1.0, 395.0, true),
+                        fill(6, 2, 3.0, 395.0, true),
+                        fill(6, 3, 1.0, deeper, true),
+                        fill(6, 4, 0.5, deeper, false),
+                    ],
+                }
+            );
+            ob.audit_arena().unwrap();
+
+            // Time priority is the default
+            let (mut ob, _) = init(MatchingMode::Fifo);
+            assert_eq!(
+                ob.execute(market(5, 5.0)),
+                OrderEvent::Filled {
+                    id: 5,
+                    filled_qty: 5.0,
+                    fills: vec![
+                        fill(5, 0, 1.0, 395.0, true),
+                        fill(5, 1, 2.0, 395.0, true),
+                        fill(5, 2, 2.0, 395.0, false),
+                    ],
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn iceberg() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
             let (mut ob, results) = init_ob(vec![
-                OrderType::Limit {
+                OrderType::Iceberg {
                     id: 0,
                     side: *bid_ask,
-                    qty: 12.1357,
-                    price: 395.521,
+                    qty: 5.0,
+                    price: 395.0,
+                    peak_qty: 2.0,
                 },
                 OrderType::Limit {
                     id: 1,
-                    side: *ask_bid,
-                    qty: 2.2345,
-                    price: 399.987,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
                 },
-                OrderType::Limit {
+            ]);
+            assert_eq!(results[0], OrderEvent::Placed { id: 0 });
+            assert_eq!(ob.level_stats(*bid_ask, 1), vec![(395.0, 3.0, 2)]);
+
+            // The first slice is filled, and the next one is queued behind
+            // order 1
+            let market = |id, qty| OrderType::Market {
+                id,
+                side: *ask_bid,
+                qty,
+            };
+            let fill = |order_1, order_2, qty, total_fill, maker_ts| {
+                FillMetadata {
+                    order_1,
+                    order_2,
+                    qty,
+                    price: 395.0,
+                    taker_side: *ask_bid,
+                    total_fill,
+                    maker_ts,
+                    price_improvement: 0.0,
+                }
+            };
+            assert_eq!(
+                ob.execute(market(2, 2.5)),
+                OrderEvent::Filled {
                     id: 2,
+                    filled_qty: 2.5,
+                    fills: vec![
+                        fill(2, 0, 2.0, false, 1),
+                        fill(2, 1, 0.5, false, 2),
+                    ],
+                }
+            );
+            assert_eq!(ob.depth(1).levels, 1);
+            assert_eq!(ob.level_stats(*bid_ask, 1), vec![(395.0, 2.5, 2)]);
+
+            // A single order can go through several refills
+            assert_eq!(
+                ob.execute(market(3, 3.5)),
+                OrderEvent::Filled {
+                    id: 3,
+                    filled_qty: 3.5,
+                    fills: vec![
+                        fill(3, 1, 0.5, true, 2),
+                        fill(3, 0, 2.0, false, 3),
+                        fill(3, 0, 1.0, true, 4),
+                    ],
+                }
+            );
+            assert_eq!(ob.traded_volume(), 6.0);
+            assert_eq!(ob.volume_in_range(*bid_ask, 390.0, 400.0), 0.0);
+            assert_eq!(ob.level_stats(*bid_ask, 1), Vec::new());
+
+            // Canceling returns the hidden quantity as well
+            ob.execute(OrderType::Iceberg {
+                id: 4,
+                side: *bid_ask,
+                qty: 3.0,
+                price: 395.0,
+                peak_qty: 1.0,
+            });
+            ob.execute(market(5, 1.5));
+            assert_eq!(ob.level_stats(*bid_ask, 1), vec![(395.0, 0.5, 1)]);
+            assert_eq!(
+                ob.execute(OrderType::Cancel { id: 4 }),
+                OrderEvent::Canceled {
+                    id: 4,
+                    canceled_qty: 1.5,
+                    found: true
+                }
+            );
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn iceberg_depth() {
+        let (mut ob, _) = init_ob(vec![OrderType::Iceberg {
+            id: 0,
+            side: Side::Ask,
+            qty: 5.0,
+            price: 396.0,
+            peak_qty: 2.0,
+        }]);
+        let displayed = |ob: &OrderBook| ob.depth(1).asks[0].qty;
+        assert_eq!(displayed(&ob), 2.0);
+
+        // Each sweep only sees the displayed slice in the depth, while the
+        // hidden quantity is released behind it
+        ob.execute(OrderType::market(1, Side::Bid, 1.5));
+        assert_eq!(displayed(&ob), 0.5);
+        ob.execute(OrderType::market(2, Side::Bid, 1.0));
+        assert_eq!(displayed(&ob), 1.5);
+        ob.execute(OrderType::market(3, Side::Bid, 2.0));
+        assert_eq!(displayed(&ob), 0.5);
+        assert_eq!(ob.traded_volume(), 4.5);
+        ob.execute(OrderType::market(4, Side::Bid, 0.5));
+        assert_eq!(ob.depth(1).asks, Vec::new());
+        ob.audit_arena().unwrap();
+    }
+
+    #[test]
+    fn all_or_none() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, results) = init_ob(vec![
+                OrderType::AllOrNone {
+                    id: 0,
                     side: *bid_ask,
-                    qty: 2.789,
-                    price: 398.421,
+                    qty: 2.0,
+                    price: 395.0,
+                },
+                OrderType::Limit {
+                    id: 1,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
                 },
             ]);
-            let result = ob.execute(OrderType::Market {
-                id: 3,
+            assert_eq!(results[0], OrderEvent::Placed { id: 0 });
+            let market = |id, qty| OrderType::Market {
+                id,
                 side: *ask_bid,
-                qty: 18.931,
+                qty,
+            };
+            let fill = |order_1, order_2, qty, total_fill, maker_ts| {
+                FillMetadata {
+                    order_1,
+                    order_2,
+                    qty,
+                    price: 395.0,
+                    taker_side: *ask_bid,
+                    total_fill,
+                    maker_ts,
+                    price_improvement: 0.0,
+                }
+            };
+
+            // A small order skips the all-or-none order, which keeps its
+            // priority
+            assert_eq!(
+                ob.execute(market(2, 0.5)),
+                OrderEvent::Filled {
+                    id: 2,
+                    filled_qty: 0.5,
+                    fills: vec![fill(2, 1, 0.5, false, 2)],
+                }
+            );
+            assert_eq!(
+                ob.execute(market(3, 1.0)),
+                OrderEvent::PartiallyFilled {
+                    id: 3,
+                    filled_qty: 0.5,
+                    fills: vec![fill(3, 1, 0.5, true, 2)],
+                }
+            );
+            assert_eq!(ob.level_stats(*bid_ask, 1), vec![(395.0, 2.0, 1)]);
+
+            // A large order clears it
+            ob.execute(OrderType::Limit {
+                id: 4,
+                side: *bid_ask,
+                qty: 1.0,
+                price: 395.0,
             });
+            assert_eq!(
+                ob.execute(market(5, 2.5)),
+                OrderEvent::Filled {
+                    id: 5,
+                    filled_qty: 2.5,
+                    fills: vec![
+                        fill(5, 0, 2.0, true, 1),
+                        fill(5, 4, 0.5, false, 3),
+                    ],
+                }
+            );
+            assert_eq!(ob.level_stats(*bid_ask, 1), vec![(395.0, 0.5, 1)]);
+            ob.audit_arena().unwrap();
+        }
+    }
 
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::PartiallyFilled {
-                        id: 3,
-                        filled_qty: 14.9247,
-                        fills: vec![
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 2,
-                                qty: 2.789,
-                                price: 398.421,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            },
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 0,
-                                qty: 12.1357,
-                                price: 395.521,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            }
-                        ]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(399.987));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(ob._asks(), init_book(vec![(39998700000, 9998)]));
-                assert_eq!(ob._bids(), init_book_holes(vec![], vec![39552100000, 39842100000]));
-                assert_eq!(ob.spread(), None);
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.2345,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.2345,
-                                price: 395.521,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
+    #[test]
+    fn trigger_order() {
+        let run = || {
+            let (mut ob, _) = init_ob(
+                (0..4)
+                    .map(|id| OrderType::Limit {
+                        id,
+                        side: Side::Bid,
+                        qty: 1.0,
+                        price: 395.0 - id as f64,
+                    })
+                    .chain([(4, 394.5), (5, 394.8), (6, 392.5)].iter().map(
+                        |(id, stop_price)| OrderType::StopMarket {
+                            id: *id,
+                            side: Side::Ask,
+                            qty: 1.0,
+                            stop_price: *stop_price,
                         },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::PartiallyFilled {
-                        id: 3,
-                        filled_qty: 12.6902,
-                        fills: vec![
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 0,
-                                qty: 9.9012,
-                                price: 395.521,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            },
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 2,
-                                qty: 2.789,
-                                price: 398.421,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            }
-                        ]
-                    }
-                );
-                assert_eq!(ob.min_ask(), None);
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(ob._asks(), init_book_holes(vec![], vec![39552100000, 39842100000]));
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
+                    ))
+                    .collect(),
+            );
+            ob.execute(OrderType::Market {
+                id: 7,
+                side: Side::Ask,
+                qty: 2.0,
+            });
+            ob.drain_events()
+        };
+        let fill = |order_1, order_2, price| FillMetadata {
+            order_1,
+            order_2,
+            qty: 1.0,
+            price,
+            taker_side: Side::Ask,
+            total_fill: true,
+            maker_ts: order_2 as u64 + 1,
+            price_improvement: 0.0,
+        };
+
+        // The trades at 395 and 394 trigger the stop closest to 395 first,
+        // and the fill of order 4 triggers order 6 in cascade
+        let events = run();
+        assert_eq!(
+            events,
+            vec![
+                OrderEvent::Filled {
+                    id: 5,
+                    filled_qty: 1.0,
+                    fills: vec![fill(5, 2, 393.0)],
+                },
+                OrderEvent::Filled {
+                    id: 4,
+                    filled_qty: 1.0,
+                    fills: vec![fill(4, 3, 392.0)],
+                },
+                OrderEvent::Unfilled { id: 6 },
+            ]
+        );
+        assert_eq!(run(), events);
+    }
+
+    #[test]
+    fn amend_qty() {
+        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
+            let (mut ob, _) = init_ob(
+                (0..3)
+                    .map(|id| OrderType::Limit {
+                        id,
+                        side: *bid_ask,
+                        qty: 2.0,
+                        price: 395.0,
+                    })
+                    .collect(),
+            );
+            let amended = |id, new_qty| OrderEvent::Amended { id, new_qty };
+            let rejected = |id, reason| OrderEvent::Rejected { id, reason };
+            assert_eq!(ob.amend_qty(0, 1.0), amended(0, 1.0));
+            assert_eq!(ob.amend_qty(1, 3.0), amended(1, 3.0));
+            assert_eq!(
+                ob.amend_qty(2, 0.0),
+                rejected(2, RejectReason::InvalidQty)
+            );
+            assert_eq!(
+                ob.amend_qty(2, f64::INFINITY),
+                rejected(2, RejectReason::InvalidQty)
+            );
+            assert_eq!(
+                ob.amend_qty(3, 1.0),
+                rejected(3, RejectReason::UnknownOrder)
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 6.0);
+
+            // Order 0 kept its priority, while order 1 lost it to order 2
+            match ob.execute(OrderType::Market {
+                id: 4,
+                side: *ask_bid,
+                qty: 6.0,
+            }) {
+                OrderEvent::Filled { fills, .. } => {
+                    let makers: Vec<_> =
+                        fills.iter().map(|fill| fill.order_2).collect();
+                    assert_eq!(makers, vec![0, 2, 1]);
+                }
+                result => panic!("unexpected event {:?}", result),
             }
+            ob.freeze();
+            assert_eq!(ob.amend_qty(0, 1.0), rejected(0, RejectReason::Frozen));
+            ob.audit_arena().unwrap();
+        }
+    }
+
+    #[test]
+    fn depth_n() {
+        let (ob, _) = init_ob(vec![
+            OrderType::Limit {
+                id: 0,
+                side: Side::Ask,
+                qty: 1.0,
+                price: 396.0,
+            },
+            OrderType::Limit {
+                id: 1,
+                side: Side::Bid,
+                qty: 1.0,
+                price: 394.0,
+            },
+            OrderType::Limit {
+                id: 2,
+                side: Side::Bid,
+                qty: 2.0,
+                price: 395.0,
+            },
+            OrderType::Limit {
+                id: 3,
+                side: Side::Bid,
+                qty: 0.5,
+                price: 395.0,
+            },
+        ]);
+        let level = |price, qty| Some(BookLevel { price, qty });
+        assert_eq!(
+            ob.depth_n::<5>(),
+            (
+                [level(396.0, 1.0), None, None, None, None],
+                [level(395.0, 2.5), level(394.0, 1.0), None, None, None],
+            )
+        );
+        assert_eq!(
+            ob.depth_n::<1>(),
+            ([level(396.0, 1.0)], [level(395.0, 2.5)])
+        );
+        assert_eq!(ob.depth_n::<0>(), ([], []));
+        assert_eq!(OrderBook::default().depth_n::<2>(), ([None; 2], [None; 2]));
+    }
+
+    #[test]
+    fn depth_truncates_levels() {
+        let mut ob = OrderBook::default();
+        for (id, price) in [396.0, 397.0, 398.0].iter().enumerate() {
+            ob.execute(OrderType::limit(id as u128, Side::Ask, 1.0, *price));
+        }
+        for (id, price) in [393.0, 394.0, 395.0].iter().enumerate() {
+            let id = id as u128 + 3;
+            ob.execute(OrderType::limit(id, Side::Bid, 2.0, *price));
         }
+        // A level holding only a filled maker is skipped, not counted.
+        ob.execute(OrderType::market(6, Side::Bid, 1.0));
+        let level = |price, qty| BookLevel { price, qty };
+        assert_eq!(
+            ob.depth(2),
+            BookDepth {
+                levels: 2,
+                asks: vec![level(397.0, 1.0), level(398.0, 1.0)],
+                bids: vec![level(395.0, 2.0), level(394.0, 2.0)],
+            }
+        );
+        assert_eq!(
+            ob.depth(0),
+            BookDepth {
+                levels: 0,
+                asks: Vec::new(),
+                bids: Vec::new(),
+            }
+        );
+        assert_eq!(ob.depth(10).asks.len(), 2);
+        assert_eq!(ob.depth(10).bids.len(), 3);
     }
 
     #[test]
-    fn market_order_filled() {
-        for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![
-                OrderType::Limit {
-                    id: 0,
-                    side: *bid_ask,
-                    qty: 12.0,
-                    price: 395.0,
-                },
-                OrderType::Limit {
-                    id: 1,
-                    side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
-                },
-                OrderType::Limit {
-                    id: 2,
-                    side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
-                },
-            ]);
-            let result = ob.execute(OrderType::Market {
+    fn clear() {
+        let orders = [
+            OrderType::limit(0, Side::Ask, 2.0, 396.0),
+            OrderType::limit(1, Side::Ask, 1.0, 397.0),
+            OrderType::limit(2, Side::Bid, 2.0, 394.0),
+            OrderType::StopMarket {
                 id: 3,
-                side: *ask_bid,
-                qty: 7.0,
-            });
+                side: Side::Bid,
+                qty: 1.0,
+                stop_price: 397.0,
+            },
+            OrderType::MidpointPeg {
+                id: 4,
+                side: Side::Bid,
+                qty: 1.0,
+            },
+            OrderType::market(5, Side::Bid, 2.5),
+        ];
+        let fresh = || {
+            let mut ob = OrderBook::new(2, 10, 2, true);
+            ob.record_recent_events(10);
+            ob
+        };
+        let mut ob = fresh();
+        for order in orders {
+            ob.execute(order);
+        }
+        ob.freeze();
+        assert!(ob.traded_volume() > 0.0);
+        let version = ob.version();
 
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::Filled {
-                        id: 3,
-                        filled_qty: 7.0,
-                        fills: vec![
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 2,
-                                qty: 2.0,
-                                price: 398.0,
-                                taker_side: *ask_bid,
-                                total_fill: true,
-                            },
-                            FillMetadata {
-                                order_1: 3,
-                                order_2: 0,
-                                qty: 5.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }
-                        ]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(399.0));
-                assert_eq!(ob.max_bid(), Some(395.0));
-                assert_eq!(ob._asks(), init_book(vec![(39900000000, 9998)]));
-                assert_eq!(
-                    ob._bids(),
-                    init_book_holes(vec![(39500000000, 9999)], vec![39800000000])
-                );
-                assert_eq!(ob.spread(), Some(4.0));
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(
-                    result,
-                    OrderEvent::Filled {
-                        id: 3,
-                        filled_qty: 7.0,
-                        fills: vec![FillMetadata {
-                            order_1: 3,
-                            order_2: 0,
-                            qty: 7.0,
-                            price: 395.0,
-                            taker_side: *ask_bid,
-                            total_fill: false,
-                        }]
-                    }
-                );
-                assert_eq!(ob.min_ask(), Some(395.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book(vec![(39500000000, 9999), (39800000000, 9998)])
-                );
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
-            }
+        ob.clear();
+        let mut new = fresh();
+        assert_eq!(ob.canonical_string(), new.canonical_string());
+        assert!(!ob.is_frozen());
+        assert_eq!(ob.version(), version + 1);
+        assert_eq!(ob.traded_volume(), 0.0);
+        assert_eq!(ob.trade_count(), 0);
+        assert_eq!(ob.last_trade(), None);
+        assert_eq!((ob.min_ask(), ob.max_bid()), (None, None));
+        assert!(ob.resting_stops().is_empty());
+        assert_eq!(ob.recent_events().count(), 0);
+        assert!(ob.drain_events().is_empty());
+        ob.audit_arena().unwrap();
+
+        // The cleared book behaves like a new one, settings included
+        for order in orders {
+            assert_eq!(ob.execute(order), new.execute(order));
+            assert_eq!(ob.drain_events(), new.drain_events());
         }
+        assert_eq!(ob.canonical_string(), new.canonical_string());
+        assert!(ob.recent_events().eq(new.recent_events()));
+        assert_eq!(ob.last_trade(), new.last_trade());
+        assert_eq!(ob.order_ts(2), new.order_ts(2));
+        assert_eq!(ob.version(), version + 1 + new.version());
     }
 
     #[test]
-    fn cancel_non_existing_order() {
-        let (mut ob, _) = init_ob(vec![]);
-        let result = ob.execute(OrderType::Cancel { id: 0 });
-        assert_eq!(result, OrderEvent::Canceled { id: 0 });
-        assert_eq!(ob.min_ask(), None);
-        assert_eq!(ob.max_bid(), None);
-        assert_eq!(ob._asks(), BTreeMap::new());
-        assert_eq!(ob._bids(), BTreeMap::new());
-        assert_eq!(ob.spread(), None);
+    fn clone_is_independent() {
+        let (mut ob, _) = init_ob(vec![
+            OrderType::limit(0, Side::Ask, 2.0, 396.0),
+            OrderType::limit(1, Side::Bid, 2.0, 394.0),
+            OrderType::StopMarket {
+                id: 2,
+                side: Side::Bid,
+                qty: 1.0,
+                stop_price: 396.0,
+            },
+        ]);
+        let mut fork = ob.clone();
+        assert_eq!(fork.canonical_string(), ob.canonical_string());
+
+        fork.execute(OrderType::market(3, Side::Bid, 1.0));
+        fork.execute(OrderType::cancel(1));
+        fork.execute(OrderType::limit(4, Side::Bid, 1.0, 395.0));
+        assert_eq!(fork.traded_volume(), 2.0);
+        assert!(fork.resting_stops().is_empty());
+        assert_eq!(fork.max_bid(), Some(395.0));
+
+        // The original is untouched
+        assert_eq!(ob.traded_volume(), 0.0);
+        assert_eq!(ob.last_trade(), None);
+        assert_eq!(ob.resting_stops().len(), 1);
+        assert_eq!(ob.qty_at_price(Side::Ask, 396.0), 2.0);
+        assert_eq!(ob.max_bid(), Some(394.0));
+        assert!(ob.drain_events().is_empty());
+
+        // And diverges on its own
+        ob.execute(OrderType::limit(4, Side::Ask, 1.0, 397.0));
+        assert_eq!(ob.depth(10).asks.len(), 2);
+        assert_eq!(fork.depth(10).asks.len(), 0);
+        ob.audit_arena().unwrap();
+        fork.audit_arena().unwrap();
     }
 
     #[test]
-    fn cancel_resting_order() {
-        for (bid_ask, _) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![OrderType::Limit {
-                id: 0,
-                side: *bid_ask,
-                qty: 12.0,
-                price: 395.0,
-            }]);
-            let result = ob.execute(OrderType::Cancel { id: 0 });
-            assert_eq!(results, vec![OrderEvent::Placed { id: 0 }]);
-            assert_eq!(result, OrderEvent::Canceled { id: 0 });
-            assert_eq!(ob.min_ask(), None);
-            assert_eq!(ob.max_bid(), None);
-            if *bid_ask == Side::Bid {
-                assert_eq!(ob._asks(), BTreeMap::new());
-                assert_eq!(ob._bids(), init_book_holes(vec![], vec![39500000000]));
-            } else {
-                assert_eq!(ob._asks(), init_book_holes(vec![], vec![39500000000]));
-                assert_eq!(ob._bids(), BTreeMap::new());
-            }
-            assert_eq!(ob.spread(), None);
+    fn depth_until_qty() {
+        let mut ob = OrderBook::default();
+        assert!(ob.depth_until_qty(Side::Ask, 1.0).is_empty());
+        for (id, price) in [396.0, 397.0, 398.0].iter().enumerate() {
+            let qty = id as f64 + 1.0;
+            ob.execute(OrderType::limit(id as u128, Side::Ask, qty, *price));
         }
+        for (id, price) in [393.0, 394.0, 395.0].iter().enumerate() {
+            let id = id as u128 + 3;
+            ob.execute(OrderType::limit(id, Side::Bid, 2.0, *price));
+        }
+        let level = |price, qty| BookLevel { price, qty };
+        assert_eq!(ob.depth_until_qty(Side::Ask, 1.0), [level(396.0, 1.0)]);
+        // The last level is only partially needed
+        assert_eq!(
+            ob.depth_until_qty(Side::Ask, 2.5),
+            [level(396.0, 1.0), level(397.0, 2.0)]
+        );
+        assert_eq!(ob.depth_until_qty(Side::Ask, 100.0).len(), 3);
+        assert_eq!(
+            ob.depth_until_qty(Side::Bid, 4.0),
+            [level(395.0, 2.0), level(394.0, 2.0)]
+        );
+        assert!(ob.depth_until_qty(Side::Bid, 0.0).is_empty());
     }
 
     #[test]
-    fn cancel_resting_order_of_many() {
+    fn replace() {
         for (bid_ask, ask_bid) in &BID_ASK_COMBINATIONS {
-            let (mut ob, results) = init_ob(vec![
+            let crossing_price = match *bid_ask {
+                Side::Bid => 397.0,
+                Side::Ask => 393.0,
+            };
+            let (mut ob, _) = init_ob(vec![
                 OrderType::Limit {
                     id: 0,
                     side: *bid_ask,
-                    qty: 12.0,
+                    qty: 1.0,
                     price: 395.0,
                 },
                 OrderType::Limit {
                     id: 1,
-                    side: *ask_bid,
-                    qty: 2.0,
-                    price: 399.0,
+                    side: *bid_ask,
+                    qty: 1.0,
+                    price: 395.0,
                 },
                 OrderType::Limit {
                     id: 2,
-                    side: *bid_ask,
-                    qty: 2.0,
-                    price: 398.0,
+                    side: *ask_bid,
+                    qty: 1.0,
+                    price: crossing_price,
                 },
             ]);
-            let result = ob.execute(OrderType::Cancel { id: 0 });
-            if *bid_ask == Side::Bid {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Placed { id: 1 },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(result, OrderEvent::Canceled { id: 0 });
-                assert_eq!(ob.min_ask(), Some(399.0));
-                assert_eq!(ob.max_bid(), Some(398.0));
-                assert_eq!(ob._asks(), init_book(vec![(39900000000, 9998)]));
-                assert_eq!(
-                    ob._bids(),
-                    init_book_holes(vec![(39800000000, 9997)], vec![39500000000])
-                );
-                assert_eq!(ob.spread(), Some(1.0));
-            } else {
-                assert_eq!(
-                    results,
-                    vec![
-                        OrderEvent::Placed { id: 0 },
-                        OrderEvent::Filled {
-                            id: 1,
-                            filled_qty: 2.0,
-                            fills: vec![FillMetadata {
-                                order_1: 1,
-                                order_2: 0,
-                                qty: 2.0,
-                                price: 395.0,
-                                taker_side: *ask_bid,
-                                total_fill: false,
-                            }],
-                        },
-                        OrderEvent::Placed { id: 2 }
-                    ]
-                );
-                assert_eq!(result, OrderEvent::Canceled { id: 0 });
-                assert_eq!(ob.min_ask(), Some(398.0));
-                assert_eq!(ob.max_bid(), None);
-                assert_eq!(
-                    ob._asks(),
-                    init_book_holes(vec![(39800000000, 9998)], vec![39500000000])
-                );
-                assert_eq!(ob._bids(), init_book(vec![]));
-                assert_eq!(ob.spread(), None);
-            }
+            let rejected = |id, reason| OrderEvent::Rejected { id, reason };
+            assert_eq!(
+                ob.replace(3, 395.0, 1.0),
+                rejected(3, RejectReason::UnknownOrder)
+            );
+            assert_eq!(
+                ob.replace(0, 395.0, -1.0),
+                rejected(0, RejectReason::InvalidQty)
+            );
+
+            // Order 0 rests behind order 1 after being replaced
+            let version = ob.version();
+            assert_eq!(
+                ob.replace(0, 395.0, 2.0),
+                OrderEvent::Replaced { id: 0 }
+            );
+            assert_eq!(ob.version(), version + 1);
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 3.0);
+            let (_, position, _) = ob.cancel_with_position(1).unwrap();
+            assert_eq!(position, 0);
+
+            // The replacement of order 0 crosses the order book
+            assert_eq!(
+                ob.replace(0, crossing_price, 1.5),
+                OrderEvent::PartiallyFilled {
+                    id: 0,
+                    filled_qty: 1.0,
+                    fills: vec![FillMetadata {
+                        order_1: 0,
+                        order_2: 2,
+                        qty: 1.0,
+                        price: crossing_price,
+                        taker_side: *bid_ask,
+                        total_fill: true,
+                        maker_ts: 3,
+                        price_improvement: 0.0,
+                    }],
+                }
+            );
+            assert_eq!(
+                ob.volume_in_range(*bid_ask, crossing_price, crossing_price),
+                0.5
+            );
+            assert_eq!(ob.volume_in_range(*bid_ask, 395.0, 395.0), 0.0);
+            ob.audit_arena().unwrap();
         }
     }
+
+    #[test]
+    fn level_callbacks() {
+        use std::sync::{Arc, Mutex};
+
+        let mut ob = OrderBook::default();
+        ob.execute(OrderType::limit(0, Side::Ask, 1.0, 400.0));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let added = Arc::clone(&log);
+        ob.on_level_added(move |side, price| {
+            added.lock().unwrap().push((true, side, price))
+        });
+        let removed = Arc::clone(&log);
+        ob.on_level_removed(move |side, price| {
+            removed.lock().unwrap().push((false, side, price))
+        });
+        let take = || std::mem::take(&mut *log.lock().unwrap());
+
+        // The first order at a price adds the level, the last one removes it.
+        ob.execute(OrderType::limit(1, Side::Bid, 1.0, 395.0));
+        assert_eq!(take(), vec![(true, Side::Bid, 395.0)]);
+        ob.execute(OrderType::limit(2, Side::Bid, 2.0, 395.0));
+        ob.execute(OrderType::cancel(1));
+        assert_eq!(take(), Vec::new());
+        ob.execute(OrderType::cancel(2));
+        assert_eq!(take(), vec![(false, Side::Bid, 395.0)]);
+
+        // Levels emptied by fills are removed, in ascending price order.
+        ob.execute(OrderType::limit(3, Side::Ask, 1.0, 401.0));
+        assert_eq!(take(), vec![(true, Side::Ask, 401.0)]);
+        ob.execute(OrderType::limit(4, Side::Bid, 3.0, 401.0));
+        assert_eq!(
+            take(),
+            vec![
+                (false, Side::Ask, 400.0),
+                (false, Side::Ask, 401.0),
+                (true, Side::Bid, 401.0)
+            ]
+        );
+
+        // Rolled back transactions and copies of the book notify nothing.
+        let orders = [OrderType::limit(5, Side::Ask, 1.0, 402.0)];
+        let rejected = [orders[0], OrderType::market(6, Side::Bid, -1.0)];
+        assert_eq!(ob.execute_atomic(&rejected), Err(1));
+        ob.clone().execute(orders[0]);
+        assert_eq!(take(), Vec::new());
+        assert!(ob.execute_atomic(&orders).is_ok());
+        assert_eq!(take(), vec![(true, Side::Ask, 402.0)]);
+
+        // Clearing the book removes all the levels.
+        ob.clear();
+        assert_eq!(
+            take(),
+            vec![(false, Side::Ask, 402.0), (false, Side::Bid, 401.0)]
+        );
+        ob.execute(OrderType::limit(5, Side::Ask, 1.0, 402.0));
+        assert_eq!(take(), vec![(true, Side::Ask, 402.0)]);
+
+        ob.clear_level_callbacks();
+        ob.execute(OrderType::cancel(5));
+        assert_eq!(take(), Vec::new());
+    }
 }
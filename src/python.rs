@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
+use pyo3::exceptions::{PyKeyError, PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 
-use crate::orderbook::OrderBook as RustOrderBook;
+use crate::orderbook::{CumulativeDepth, OrderBook as RustOrderBook};
 use crate::models::{
-    BookDepth, FillMetadata, OrderEvent as RustOrderEvent, OrderType as RustOrderType, Side, Trade,
+    Bbo, BookDepth, CancelReport as RustCancelReport, FillMetadata, OrderEvent as RustOrderEvent, OrderType as RustOrderType, RejectReason, Side, Trade,
 };
 
 /// Python wrappers around rust classes and return types, as we need
@@ -76,6 +79,17 @@ pub enum OrderEventType {
     /// Indicating that the corresponding order was filled completely. It is
     /// sent in response to market or limit orders.
     Filled,
+    /// Indicating that the corresponding resting order was modified in place.
+    Amended,
+    /// Indicating that the corresponding resting order was removed from the
+    /// order book because it expired.
+    Expired,
+    /// Indicating that the corresponding resting order was replaced by a new
+    /// order resting on the order book.
+    Replaced,
+    /// Indicating that the request was refused and the order book was left
+    /// untouched.
+    Rejected,
 }
 
 /// An event resulting from the execution of an order.
@@ -90,20 +104,148 @@ pub struct OrderEvent {
     /// A vector with information on the order fills.
     #[pyo3(get, set)]
     pub fills: Vec<FillMetadata>,
+    /// The quantity left on the order book when the order was canceled.
+    #[pyo3(get, set)]
+    pub canceled_qty: f64,
+    /// Whether the canceled order was found on the order book.
+    #[pyo3(get, set)]
+    pub found: bool,
     /// Type of order event
     #[pyo3(get, set)]
     pub event_type: OrderEventType,
+    /// The number of distinct price levels consumed by the order.
+    #[pyo3(get, set)]
+    pub levels_swept: usize,
 }
 
 #[pymethods]
 impl OrderEvent {
     #[new]
+    #[args(canceled_qty = "0.0", levels_swept = "0", found = "false")]
     fn py_new(
         id: u128,
         filled_qty: f64,
         fills: Vec<FillMetadata>,
-        event_type: OrderEventType) -> PyResult<Self> {
-            Ok(OrderEvent { id, filled_qty, fills, event_type })
+        event_type: OrderEventType,
+        canceled_qty: f64,
+        levels_swept: usize,
+        found: bool) -> PyResult<Self> {
+            Ok(OrderEvent { id, filled_qty, fills, canceled_qty, found, event_type, levels_swept })
+    }
+}
+
+impl From<RustOrderEvent> for OrderEvent {
+    fn from(event: RustOrderEvent) -> Self {
+        let mut canceled_qty = 0.0;
+        let mut found = false;
+        let levels_swept = event.levels_swept();
+        let (id, filled_qty, fills, event_type) = match event {
+            RustOrderEvent::Unfilled { id } => {
+                (id, 0.0, Vec::new(), OrderEventType::Unfilled)
+            }
+            RustOrderEvent::Placed { id } => {
+                (id, 0.0, Vec::new(), OrderEventType::Placed)
+            }
+            RustOrderEvent::Canceled { id, canceled_qty: qty, found: was_found } => {
+                canceled_qty = qty;
+                found = was_found;
+                (id, 0.0, Vec::new(), OrderEventType::Canceled)
+            }
+            RustOrderEvent::PartiallyFilled { id, filled_qty, fills } => {
+                (id, filled_qty, fills, OrderEventType::PartiallyFilled)
+            }
+            RustOrderEvent::Filled { id, filled_qty, fills } => {
+                (id, filled_qty, fills, OrderEventType::Filled)
+            }
+            RustOrderEvent::Amended { id, .. } => {
+                (id, 0.0, Vec::new(), OrderEventType::Amended)
+            }
+            RustOrderEvent::Expired { id } => {
+                (id, 0.0, Vec::new(), OrderEventType::Expired)
+            }
+            RustOrderEvent::Replaced { id } => {
+                (id, 0.0, Vec::new(), OrderEventType::Replaced)
+            }
+            RustOrderEvent::Rejected { id, .. } => {
+                (id, 0.0, Vec::new(), OrderEventType::Rejected)
+            }
+        };
+        OrderEvent { id, filled_qty, fills, canceled_qty, found, event_type, levels_swept }
+    }
+}
+
+/// The result of a cancel, with the state of the canceled order right before
+/// it was removed.
+#[derive(Debug, PartialEq, Clone)]
+#[pyclass]
+pub struct CancelReport {
+    /// The event resulting from the cancel.
+    #[pyo3(get)]
+    pub event: OrderEvent,
+    /// Whether a resting or dormant order was canceled.
+    #[pyo3(get)]
+    pub found: bool,
+    /// The side of the canceled order, or `None` if nothing was canceled.
+    #[pyo3(get)]
+    pub side: Option<Side>,
+    /// The price of the canceled resting order, or `0.0` otherwise.
+    #[pyo3(get)]
+    pub price: f64,
+    /// The quantity of the order left when it was canceled.
+    #[pyo3(get)]
+    pub qty: f64,
+    /// The position of the canceled resting order in the queue at its price
+    /// level, `0` being the front.
+    #[pyo3(get)]
+    pub queue_position: usize,
+}
+
+impl From<RustCancelReport> for CancelReport {
+    fn from(report: RustCancelReport) -> Self {
+        CancelReport {
+            event: report.event.into(),
+            found: report.found,
+            side: report.side,
+            price: report.price,
+            qty: report.qty,
+            queue_position: report.queue_position,
+        }
+    }
+}
+
+/// Convert an event for Python, raising an exception if the order was
+/// rejected.
+fn event_result(event: RustOrderEvent) -> PyResult<OrderEvent> {
+    match event {
+        RustOrderEvent::Rejected { id, reason } => {
+            Err(rejection_error(id, reason))
+        }
+        event => Ok(event.into()),
+    }
+}
+
+/// Execute an order, rejecting it up front if its quantity or price is
+/// invalid, which the Rust order book only checks in `execute_atomic`.
+fn execute_checked(orderbook: &mut RustOrderBook, order: RustOrderType) -> RustOrderEvent {
+    match RustOrderBook::validate(&order) {
+        Some(rejected) => rejected,
+        None => orderbook.execute(order),
+    }
+}
+
+/// Map a rejected order to the Python exception matching the reject reason.
+fn rejection_error(id: u128, reason: RejectReason) -> PyErr {
+    let msg = format!("order {} rejected: {:?}", id, reason);
+    match reason {
+        RejectReason::UnknownOrder => PyKeyError::new_err(msg),
+        RejectReason::Frozen => PyRuntimeError::new_err(msg),
+        RejectReason::InvalidQty
+        | RejectReason::InvalidPrice
+        | RejectReason::NoReferencePrice
+        | RejectReason::WouldCross
+        | RejectReason::WouldNotImprove
+        | RejectReason::DuplicateId
+        | RejectReason::MinRestingTime => PyValueError::new_err(msg),
     }
 }
 
@@ -143,6 +285,26 @@ impl OrderBook {
         Ok(self_.orderbook.max_bid().clone())
     }
 
+    /// Return the average of the lowest ask and highest bid, if both are
+    /// present.
+    #[inline(always)]
+    pub fn mid_price(self_: PyRef<'_, Self>) -> PyResult<Option<f64>> {
+        Ok(self_.orderbook.mid_price())
+    }
+
+    /// Return the highest bid and the lowest ask prices as a
+    /// `(max_bid, min_ask)` tuple.
+    #[inline(always)]
+    pub fn quotes(self_: PyRef<'_, Self>) -> PyResult<(Option<f64>, Option<f64>)> {
+        Ok(self_.orderbook.quotes())
+    }
+
+    /// Return the best bid and offer with the total quantity resting at each
+    /// price as a `Bbo` object, or `None` if either side is empty.
+    pub fn bbo(self_: PyRef<'_, Self>) -> PyResult<Option<Bbo>> {
+        Ok(self_.orderbook.bbo())
+    }
+
     /// Return the last trade recorded while stats tracking was active as a
     /// [`Trade`] object, if present.
     ///
@@ -163,12 +325,90 @@ impl OrderBook {
         Ok(self_.orderbook.depth(levels).clone())
     }
 
+    /// Return the cumulative depth up to the specified level, as lists of
+    /// `(price, cumulative_qty)` tuples for the ask and the bid side.
+    pub fn cumulative_depth(self_: PyRef<'_, Self>, levels: usize) -> PyResult<CumulativeDepth> {
+        Ok(self_.orderbook.cumulative_depth(levels))
+    }
+
+    /// Return up to `levels` price levels of the given side, best price first,
+    /// as a list of `(price, total_qty, order_count)` tuples.
+    pub fn level_stats(self_: PyRef<'_, Self>, side: Side, levels: usize) -> PyResult<Vec<(f64, f64, usize)>> {
+        Ok(self_.orderbook.level_stats(side, levels))
+    }
+
+    /// Return the signals of the order book computed over the top `levels`
+    /// levels, as a dict with the `spread`, `spread_bps`, `mid`,
+    /// `micro_price`, `imbalance`, `bid_volume` and `ask_volume` keys. Missing
+    /// values are `None`.
+    pub fn signals(self_: PyRef<'_, Self>, levels: usize) -> PyResult<HashMap<&'static str, Option<f64>>> {
+        let signals = self_.orderbook.signals(levels);
+        Ok(HashMap::from([
+            ("spread", signals.spread),
+            ("spread_bps", signals.spread_bps),
+            ("mid", signals.mid),
+            ("micro_price", signals.micro_price),
+            ("imbalance", signals.imbalance),
+            ("bid_volume", Some(signals.bid_volume)),
+            ("ask_volume", Some(signals.ask_volume)),
+        ]))
+    }
+
+    /// Return the statistics of the trades recorded while stats tracking was
+    /// active, as a dict with the `traded_volume`, `order_count`,
+    /// `fill_count`, `high`, `low`, `vwap`, `buy_volume` and `sell_volume`
+    /// keys. The counts are integers and missing values are `None`.
+    pub fn session_summary(
+        self_: PyRef<'_, Self>,
+        py: Python<'_>,
+    ) -> PyResult<HashMap<&'static str, PyObject>> {
+        let summary = self_.orderbook.session_summary();
+        Ok(HashMap::from([
+            ("traded_volume", summary.traded_volume.into_py(py)),
+            ("order_count", summary.order_count.into_py(py)),
+            ("fill_count", summary.fill_count.into_py(py)),
+            ("high", summary.high.into_py(py)),
+            ("low", summary.low.into_py(py)),
+            ("vwap", summary.vwap.into_py(py)),
+            ("buy_volume", summary.buy_volume.into_py(py)),
+            ("sell_volume", summary.sell_volume.into_py(py)),
+        ]))
+    }
+
+    /// Return the revision number of the order book, incremented by every
+    /// operation that changes its state.
+    #[inline(always)]
+    pub fn version(self_: PyRef<'_, Self>) -> PyResult<u64> {
+        Ok(self_.orderbook.version())
+    }
+
+    /// Return the time priority sequence number of a resting order, or `None`
+    /// if the order is not resting on the order book.
+    pub fn order_ts(self_: PyRef<'_, Self>, id: u128) -> PyResult<Option<u64>> {
+        Ok(self_.orderbook.order_ts(id))
+    }
+
+    /// Return a rough estimate of the probability that a resting order is
+    /// filled entirely, or `None` if the order is not resting on the order book
+    /// or its price level has no depletion data.
+    pub fn fill_probability(self_: PyRef<'_, Self>, id: u128) -> PyResult<Option<f64>> {
+        Ok(self_.orderbook.fill_probability(id))
+    }
+
     /// Toggle the stats tracking on or off, depending on the `track` parameter.
     pub fn track_stats(mut self_: PyRefMut<Self>, track: bool) {
         self_.orderbook.track_stats(track)
     }
 
-    /// Batch submit orders, to avoid memory allocation overhead in Python
+    /// Reset the traded volume to `0.0` and forget the last trade, leaving the
+    /// resting orders untouched.
+    pub fn reset_stats(mut self_: PyRefMut<Self>) {
+        self_.orderbook.reset_stats()
+    }
+
+    /// Batch submit orders, to avoid memory allocation overhead in Python.
+    /// Raise an exception on the first rejected order: the orders before it
+    /// stay applied, and the ones after it are not submitted.
     pub fn submit_batch(mut self_: PyRefMut<Self>, ids: Vec<u128>, qtys: Vec<f64>, prices: Vec<f64>, sides: Vec<Side>) -> PyResult<Vec<OrderEvent>> {
         let mut i = 0;
         let len = ids.len();
@@ -180,11 +420,10 @@ impl OrderBook {
             let price = prices[i];
             let side = sides[i];
             let event: RustOrderEvent;
-            let result: OrderEvent;
 
             if qty > 0.0 {
                 if price > 0.0 {
-                    event = self_.orderbook.execute(RustOrderType::Limit {
+                    event = execute_checked(&mut self_.orderbook, RustOrderType::Limit {
                         id: id,
                         qty: qty,
                         price: price,
@@ -192,7 +431,7 @@ impl OrderBook {
                     });
                 }
                 else {
-                    event = self_.orderbook.execute(RustOrderType::Market {
+                    event = execute_checked(&mut self_.orderbook, RustOrderType::Market {
                         id: id,
                         qty: qty,
                         side: side
@@ -205,197 +444,115 @@ impl OrderBook {
                 });
             }
 
-            match event {
-                RustOrderEvent::Unfilled { id } => {
-                    result = OrderEvent {
-                        id: id,
-                        filled_qty: 0.0,
-                        fills: Vec::new(),
-                        event_type: OrderEventType::Unfilled
-                    }
-                }
-                RustOrderEvent::Placed { id } => {
-                    result = OrderEvent {
-                        id: id,
-                        filled_qty: 0.0,
-                        fills: Vec::new(),
-                        event_type: OrderEventType::Placed
-                    }
-                }
-                RustOrderEvent::Canceled { id } => {
-                    result = OrderEvent {
-                        id: id,
-                        filled_qty: 0.0,
-                        fills: Vec::new(),
-                        event_type: OrderEventType::Canceled
-                    }
-                }
-                RustOrderEvent::PartiallyFilled { id, filled_qty, fills } => {
-                    result = OrderEvent {
-                        id: id,
-                        filled_qty: filled_qty,
-                        fills: fills.clone(),
-                        event_type: OrderEventType::PartiallyFilled
-                    }
-                }
-                RustOrderEvent::Filled { id, filled_qty, fills } => {
-                    result = OrderEvent {
-                        id: id,
-                        filled_qty: filled_qty,
-                        fills: fills.clone(),
-                        event_type: OrderEventType::Filled
-                    }
-                }
-            }
-
-            results.push(result);
+            results.push(event_result(event)?);
             i = i + 1;
         }
         Ok(results)
     }
 
-    /// Submit a limit order
+    /// Submit a limit order, raising `ValueError` if it is invalid or
+    /// `RuntimeError` if the order book is frozen.
     pub fn submit_limit(mut self_: PyRefMut<Self>, id: u128, qty: f64, price: f64, side: Side) -> PyResult<OrderEvent> {
-        let event: RustOrderEvent;
-        let result: OrderEvent;
-
-        event = self_.orderbook.execute(RustOrderType::Limit {
-            id: id,
-            qty: qty,
-            price: price,
-            side: side
+        let event = execute_checked(&mut self_.orderbook, RustOrderType::Limit {
+            id,
+            qty,
+            price,
+            side
         });
-
-        match event {
-            RustOrderEvent::Unfilled { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Unfilled
-                }
-            }
-            RustOrderEvent::Placed { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Placed
-                }
-            }
-            RustOrderEvent::Canceled { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Canceled
-                }
-            }
-            RustOrderEvent::PartiallyFilled { id, filled_qty, fills } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: filled_qty,
-                    fills: fills.clone(),
-                    event_type: OrderEventType::PartiallyFilled
-                }
-            }
-            RustOrderEvent::Filled { id, filled_qty, fills } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: filled_qty,
-                    fills: fills.clone(),
-                    event_type: OrderEventType::Filled
-                }
-            }
-        }
-        Ok(result)
+        event_result(event)
     }
 
-    /// Submit a limit order
+    /// Submit a market order, raising `ValueError` if it is invalid or
+    /// `RuntimeError` if the order book is frozen.
     pub fn submit_market(mut self_: PyRefMut<Self>, id: u128, qty: f64, side: Side) -> PyResult<OrderEvent> {
-        let event: RustOrderEvent;
-        let result: OrderEvent;
+        let event = execute_checked(&mut self_.orderbook, RustOrderType::Market {
+            id,
+            qty,
+            side
+        });
+        event_result(event)
+    }
 
-        event = self_.orderbook.execute(RustOrderType::Market {
-            id: id,
-            qty: qty,
-            side: side
+    /// Submit an iceberg order, which only shows up to `display_qty` of its
+    /// unfilled quantity in the depth of the order book, replenished from the
+    /// hidden quantity at the back of the price level as it is filled. Raise
+    /// `ValueError` if it is invalid or `RuntimeError` if the order book is
+    /// frozen.
+    pub fn submit_iceberg(mut self_: PyRefMut<Self>, id: u128, qty: f64, display_qty: f64, price: f64, side: Side) -> PyResult<OrderEvent> {
+        let event = execute_checked(&mut self_.orderbook, RustOrderType::Iceberg {
+            id,
+            side,
+            qty,
+            price,
+            peak_qty: display_qty
         });
+        event_result(event)
+    }
 
-        match event {
-            RustOrderEvent::Unfilled { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Unfilled
-                }
-            }
-            RustOrderEvent::Placed { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Placed
-                }
-            }
-            RustOrderEvent::Canceled { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Canceled
-                }
-            }
-            RustOrderEvent::PartiallyFilled { id, filled_qty, fills } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: filled_qty,
-                    fills: fills.clone(),
-                    event_type: OrderEventType::PartiallyFilled
-                }
-            }
-            RustOrderEvent::Filled { id, filled_qty, fills } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: filled_qty,
-                    fills: fills.clone(),
-                    event_type: OrderEventType::Filled
-                }
-            }
-        }
-        Ok(result)
+    /// Reduce the quantity of a resting order in place, keeping its queue
+    /// position, and return an `Amended` event. Raise `KeyError` if the order
+    /// is not resting, `ValueError` if `new_qty` is not positive or greater
+    /// than the resting quantity, or `RuntimeError` if the order book is
+    /// frozen.
+    pub fn reduce_qty(mut self_: PyRefMut<Self>, id: u128, new_qty: f64) -> PyResult<OrderEvent> {
+        let event = self_.orderbook.reduce_qty(id, new_qty);
+        event_result(event)
+    }
+
+    /// Replace a resting order with a limit order of the same ID and side at a
+    /// new price and quantity, and return a `Replaced` event, or the fill
+    /// event if the replacement crosses the order book. Raise an exception if
+    /// the request is rejected, like `reduce_qty`.
+    pub fn replace(mut self_: PyRefMut<Self>, id: u128, new_price: f64, new_qty: f64) -> PyResult<OrderEvent> {
+        let event = self_.orderbook.replace(id, new_price, new_qty);
+        event_result(event)
     }
 
-    /// Submit a cancel
+    /// Change the quantity of a resting order in place, keeping its queue
+    /// position on a decrease and moving it to the back of the queue on an
+    /// increase, and return an `Amended` event. Raise `KeyError` if the order
+    /// is not resting, `ValueError` if `new_qty` is not positive and finite,
+    /// or `RuntimeError` if the order book is frozen.
+    pub fn amend_qty(mut self_: PyRefMut<Self>, id: u128, new_qty: f64) -> PyResult<OrderEvent> {
+        let event = self_.orderbook.amend_qty(id, new_qty);
+        event_result(event)
+    }
+
+    /// Submit a cancel, raising `RuntimeError` if the order book is frozen. The
+    /// `found` attribute of the event is `False` if the order was not on the
+    /// order book, in which case nothing was canceled.
     pub fn submit_cancel(mut self_: PyRefMut<Self>, id: u128) -> PyResult<OrderEvent> {
-        self_.orderbook.execute(RustOrderType::Cancel {
-            id: id
-        });
+        let event = self_.orderbook.execute(RustOrderType::Cancel { id });
+        event_result(event)
+    }
 
-        Ok(OrderEvent {
-            id: id,
-            filled_qty: 0.0,
-            fills: Vec::new(),
-            event_type: OrderEventType::Canceled
-        })
+    /// Submit a cancel like `submit_cancel`, returning a `CancelReport` with
+    /// the side, price, remaining quantity and queue position of the order
+    /// right before it was canceled. They are left to their default if the
+    /// order was not on the order book.
+    pub fn cancel_report(mut self_: PyRefMut<Self>, id: u128) -> PyResult<CancelReport> {
+        let report = self_.orderbook.cancel_report(id);
+        if let RustOrderEvent::Rejected { id, reason } = report.event {
+            return Err(rejection_error(id, reason));
+        }
+        Ok(report.into())
     }
 
-    /// Execute an order, returning immediately an event indicating the result.
+    /// Execute an order, returning immediately an event indicating the result,
+    /// or raising an exception if the order is rejected (see `submit_limit`).
     pub fn execute(mut self_: PyRefMut<Self>, order: Order) -> PyResult<OrderEvent> {
         let event: RustOrderEvent;
-        let result: OrderEvent;
 
         match order.order_type {
             OrderType::Market => {
-                event = self_.orderbook.execute(RustOrderType::Market {
+                event = execute_checked(&mut self_.orderbook, RustOrderType::Market {
                     id: order.id,
                     qty: order.qty,
                     side: order.side
                 });
             }
             OrderType::Limit => {
-                event = self_.orderbook.execute(RustOrderType::Limit {
+                event = execute_checked(&mut self_.orderbook, RustOrderType::Limit {
                     id: order.id,
                     qty: order.qty,
                     price: order.price,
@@ -409,48 +566,176 @@ impl OrderBook {
             }
         }
 
-        match event {
-            RustOrderEvent::Unfilled { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Unfilled
-                }
-            }
-            RustOrderEvent::Placed { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Placed
-                }
-            }
-            RustOrderEvent::Canceled { id } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: 0.0,
-                    fills: Vec::new(),
-                    event_type: OrderEventType::Canceled
-                }
-            }
-            RustOrderEvent::PartiallyFilled { id, filled_qty, fills } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: filled_qty,
+        event_result(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{execute_checked, CancelReport, OrderEvent, OrderEventType};
+    use crate::{
+        CancelReport as RustCancelReport, FillMetadata,
+        OrderBook as RustOrderBook, OrderEvent as RustOrderEvent,
+        OrderType as RustOrderType, RejectReason, Side,
+    };
+
+    #[test]
+    fn event_conversion() {
+        let fills = vec![FillMetadata {
+            order_1: 7,
+            order_2: 3,
+            qty: 2.5,
+            price: 398.0,
+            taker_side: Side::Ask,
+            total_fill: true,
+            maker_ts: 0,
+            price_improvement: 0.0,
+        }];
+        let empty = |id, event_type| OrderEvent {
+            id,
+            filled_qty: 0.0,
+            fills: Vec::new(),
+            canceled_qty: 0.0,
+            found: false,
+            event_type,
+            levels_swept: 0,
+        };
+        let cases = vec![
+            (
+                RustOrderEvent::Unfilled { id: 0 },
+                empty(0, OrderEventType::Unfilled),
+            ),
+            (
+                RustOrderEvent::Placed { id: 1 },
+                empty(1, OrderEventType::Placed),
+            ),
+            (
+                RustOrderEvent::Canceled {
+                    id: 2,
+                    canceled_qty: 1.5,
+                    found: true,
+                },
+                OrderEvent {
+                    canceled_qty: 1.5,
+                    found: true,
+                    ..empty(2, OrderEventType::Canceled)
+                },
+            ),
+            (
+                RustOrderEvent::PartiallyFilled {
+                    id: 7,
+                    filled_qty: 2.5,
                     fills: fills.clone(),
-                    event_type: OrderEventType::PartiallyFilled
-                }
-            }
-            RustOrderEvent::Filled { id, filled_qty, fills } => {
-                result = OrderEvent {
-                    id: id,
-                    filled_qty: filled_qty,
+                },
+                OrderEvent {
+                    id: 7,
+                    filled_qty: 2.5,
                     fills: fills.clone(),
-                    event_type: OrderEventType::Filled
+                    canceled_qty: 0.0,
+                    found: false,
+                    event_type: OrderEventType::PartiallyFilled,
+                    levels_swept: 1,
+                },
+            ),
+            (
+                RustOrderEvent::Filled {
+                    id: 7,
+                    filled_qty: 2.5,
+                    fills: fills.clone(),
+                },
+                OrderEvent {
+                    id: 7,
+                    filled_qty: 2.5,
+                    fills,
+                    canceled_qty: 0.0,
+                    found: false,
+                    event_type: OrderEventType::Filled,
+                    levels_swept: 1,
+                },
+            ),
+            (
+                RustOrderEvent::Amended {
+                    id: 3,
+                    new_qty: 1.5,
+                },
+                empty(3, OrderEventType::Amended),
+            ),
+            (
+                RustOrderEvent::Expired { id: 4 },
+                empty(4, OrderEventType::Expired),
+            ),
+            (
+                RustOrderEvent::Replaced { id: 6 },
+                empty(6, OrderEventType::Replaced),
+            ),
+            (
+                RustOrderEvent::Rejected {
+                    id: 5,
+                    reason: RejectReason::InvalidQty,
+                },
+                empty(5, OrderEventType::Rejected),
+            ),
+        ];
+        for (event, expected) in cases {
+            assert_eq!(OrderEvent::from(event), expected);
+        }
+    }
+
+    #[test]
+    fn cancel_report_conversion() {
+        let report = CancelReport::from(RustCancelReport {
+            event: RustOrderEvent::Canceled {
+                id: 2,
+                canceled_qty: 1.5,
+                found: true,
+            },
+            found: true,
+            side: Some(Side::Ask),
+            price: 395.0,
+            qty: 1.5,
+            queue_position: 1,
+        });
+        assert_eq!(report.event.event_type, OrderEventType::Canceled);
+        assert_eq!(report.event.canceled_qty, 1.5);
+        assert!(report.found);
+        assert_eq!(report.side, Some(Side::Ask));
+        assert_eq!((report.price, report.qty), (395.0, 1.5));
+        assert_eq!(report.queue_position, 1);
+    }
+
+    #[test]
+    fn checked_execution() {
+        let mut ob = RustOrderBook::default();
+        let invalid = [
+            (
+                RustOrderType::market(0, Side::Bid, 0.0),
+                RejectReason::InvalidQty,
+            ),
+            (
+                RustOrderType::limit(1, Side::Ask, f64::NAN, 395.0),
+                RejectReason::InvalidQty,
+            ),
+            (
+                RustOrderType::limit(2, Side::Ask, 1.0, -395.0),
+                RejectReason::InvalidPrice,
+            ),
+        ];
+        for (id, (order, reason)) in (0..).zip(invalid.iter()) {
+            assert_eq!(
+                execute_checked(&mut ob, *order),
+                RustOrderEvent::Rejected {
+                    id,
+                    reason: *reason,
                 }
-            }
+            );
         }
-        Ok(result)
+        assert_eq!((ob.min_ask(), ob.max_bid()), (None, None));
+        assert_eq!(ob.version(), 0);
+
+        let order = RustOrderType::limit(3, Side::Ask, 1.0, 395.0);
+        assert_eq!(
+            execute_checked(&mut ob, order),
+            RustOrderEvent::Placed { id: 3 }
+        );
     }
-}
\ No newline at end of file
+}
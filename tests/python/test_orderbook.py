@@ -0,0 +1,109 @@
+"""Tests of the Python bindings, run against the extension module built with
+`maturin develop` (see the `test-python` target of the Makefile)."""
+
+import math
+import unittest
+
+from lobster import Order, OrderBook, OrderEventType, OrderType, Side
+
+
+class RejectionTest(unittest.TestCase):
+    def test_invalid_orders_raise_value_error(self):
+        ob = OrderBook.default()
+        with self.assertRaises(ValueError):
+            ob.submit_limit(0, 0.0, 395.0, Side.Ask)
+        with self.assertRaises(ValueError):
+            ob.submit_limit(1, 1.0, -395.0, Side.Ask)
+        with self.assertRaises(ValueError):
+            ob.submit_market(2, 0.0, Side.Bid)
+        with self.assertRaises(ValueError):
+            ob.execute(Order(id=3, side=Side.Bid, qty=math.nan, price=395.0,
+                             order_type=OrderType.Limit))
+        self.assertEqual(ob.quotes(), (None, None))
+        self.assertEqual(ob.version(), 0)
+
+    def test_duplicate_id_raises_value_error(self):
+        ob = OrderBook.default()
+        ob.submit_limit(0, 1.0, 395.0, Side.Ask)
+        with self.assertRaises(ValueError):
+            ob.submit_limit(0, 1.0, 396.0, Side.Ask)
+        self.assertEqual(ob.min_ask(), 395.0)
+
+    def test_unknown_order_raises_key_error(self):
+        ob = OrderBook.default()
+        with self.assertRaises(KeyError):
+            ob.amend_qty(0, 1.0)
+
+    def test_batch_stops_at_the_first_rejection(self):
+        ob = OrderBook.default()
+        with self.assertRaises(ValueError):
+            ob.submit_batch([0, 0, 1], [1.0, 1.0, 1.0],
+                            [395.0, 396.0, 397.0],
+                            [Side.Ask, Side.Ask, Side.Ask])
+        # The order before the rejected one stays, the one after it is not
+        # submitted
+        self.assertEqual(ob.min_ask(), 395.0)
+        self.assertEqual(ob.depth(10).asks[0].qty, 1.0)
+        self.assertEqual(len(ob.depth(10).asks), 1)
+
+    def test_valid_order_returns_an_event(self):
+        ob = OrderBook.default()
+        event = ob.submit_limit(0, 1.0, 395.0, Side.Ask)
+        self.assertEqual(event.event_type, OrderEventType.Placed)
+
+
+class ReduceQtyTest(unittest.TestCase):
+    def test_reduction_keeps_queue_position(self):
+        ob = OrderBook.default()
+        ob.submit_limit(0, 2.0, 395.0, Side.Ask)
+        ob.submit_limit(1, 2.0, 395.0, Side.Ask)
+        event = ob.reduce_qty(0, 1.0)
+        self.assertEqual(event.event_type, OrderEventType.Amended)
+
+        # The reduced order is still ahead of the later one at its price
+        event = ob.submit_market(2, 1.5, Side.Bid)
+        self.assertEqual([fill.order_2 for fill in event.fills], [0, 1])
+        self.assertEqual([fill.qty for fill in event.fills], [1.0, 0.5])
+
+    def test_invalid_reduction_raises(self):
+        ob = OrderBook.default()
+        ob.submit_limit(0, 2.0, 395.0, Side.Ask)
+        with self.assertRaises(KeyError):
+            ob.reduce_qty(1, 1.0)
+        with self.assertRaises(ValueError):
+            ob.reduce_qty(0, 3.0)
+        with self.assertRaises(ValueError):
+            ob.reduce_qty(0, 0.0)
+        self.assertEqual(ob.depth(1).asks[0].qty, 2.0)
+
+
+class IcebergTest(unittest.TestCase):
+    def test_depth_shows_the_displayed_slice(self):
+        ob = OrderBook.default()
+        event = ob.submit_iceberg(0, 5.0, 2.0, 396.0, Side.Ask)
+        self.assertEqual(event.event_type, OrderEventType.Placed)
+
+        def displayed():
+            return ob.depth(1).asks[0].qty
+
+        self.assertEqual(displayed(), 2.0)
+
+        # Each sweep only sees the displayed slice in the depth, while the
+        # hidden quantity is released behind it
+        sweeps = [(1.5, 0.5), (1.0, 1.5), (2.0, 0.5)]
+        for id, (qty, expected) in enumerate(sweeps, start=1):
+            event = ob.submit_market(id, qty, Side.Bid)
+            self.assertEqual(event.filled_qty, qty)
+            self.assertEqual(displayed(), expected)
+        ob.submit_market(4, 0.5, Side.Bid)
+        self.assertEqual(ob.depth(1).asks, [])
+
+    def test_invalid_display_qty_raises(self):
+        ob = OrderBook.default()
+        with self.assertRaises(ValueError):
+            ob.submit_iceberg(0, 5.0, 0.0, 396.0, Side.Ask)
+        self.assertEqual(ob.depth(1).asks, [])
+
+
+if __name__ == "__main__":
+    unittest.main()